@@ -0,0 +1,205 @@
+//! Line clear animations
+//!
+//! An animation is created with the full rows found by
+//! `Playfield::check_rows`, while they are still on the playfield
+//! (during the line clear delay), and lasts as long as the delay.
+//! It only keeps what it needs to be drawn at any time: the game
+//! goes on without waiting for it.
+
+use macroquad::prelude::rand;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::playfield::Playfield;
+
+
+/// Gravity applied to the particles, in cells per second squared
+const PARTICLE_GRAVITY: f32 = 40.0;
+
+/// Times the rows flash during the animation
+const FLASHES: f32 = 4.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ClearStyle {
+    /// The cells disappear from the centre of the rows to the
+    /// walls, as in the NES version
+    Wipe,
+    /// The rows flash before disappearing
+    Flash,
+    /// The blocks burst into particles
+    Particles,
+    /// The blocks shrink until they disappear
+    Collapse,
+}
+
+impl ClearStyle {
+
+    pub const ALL: [ClearStyle; 4] = [
+        ClearStyle::Wipe,
+        ClearStyle::Flash,
+        ClearStyle::Particles,
+        ClearStyle::Collapse,
+    ];
+
+    pub fn name(&self) -> &str {
+        match self {
+            ClearStyle::Wipe => "Wipe",
+            ClearStyle::Flash => "Flash",
+            ClearStyle::Particles => "Particles",
+            ClearStyle::Collapse => "Collapse",
+        }
+    }
+}
+
+/// A block flying away from a cleared row. Positions and speeds
+/// are given in cells (and cells per second).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Particle {
+    pub row: f32,
+    pub col: f32,
+    pub speed_rows: f32,
+    pub speed_cols: f32,
+    /// Value of the playfield cell it comes from
+    pub value: usize,
+}
+
+impl Particle {
+
+    /// Position (row, column) of the particle after the given time
+    pub fn position(&self, t: f32) -> (f32, f32) {
+        (self.row + (self.speed_rows * t) + (PARTICLE_GRAVITY * t * t / 2.0),
+         self.col + (self.speed_cols * t))
+    }
+}
+
+pub struct ClearAnimation {
+    style: ClearStyle,
+    rows: Vec<usize>,
+    duration: f64,
+    particles: Vec<Particle>,
+}
+
+impl ClearAnimation {
+
+    /// Create the animation of the given rows
+    ///
+    /// # Arguments
+    /// * `style` - How the rows are cleared
+    /// * `pf` - The playfield, with the full rows still on it
+    /// * `rows` - The full rows
+    /// * `duration` - Duration of the animation (the line clear
+    ///   delay), in seconds
+    pub fn new(style: ClearStyle, pf: &Playfield, rows: &[usize],
+               duration: f64) -> Self {
+
+        let mut particles = Vec::new();
+
+        if style == ClearStyle::Particles {
+            for row in rows {
+                for col in 1..pf.n_cols() - 1 {
+                    particles.push(Particle {
+                        row: *row as f32,
+                        col: col as f32,
+                        speed_rows: rand::gen_range(-20.0, -5.0),
+                        speed_cols: rand::gen_range(-8.0, 8.0),
+                        value: pf.get_cell(*row, col),
+                    });
+                }
+            }
+        }
+
+        Self {
+            style,
+            rows: rows.to_vec(),
+            duration,
+            particles,
+        }
+    }
+
+    pub fn style(&self) -> ClearStyle {
+        self.style
+    }
+
+    pub fn rows(&self) -> &[usize] {
+        &self.rows
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    /// Progress of the animation (0 to 1) after the given time
+    pub fn progress(&self, t: f64) -> f32 {
+        if self.duration <= 0.0 {
+            1.0
+        } else {
+            (t / self.duration).clamp(0.0, 1.0) as f32
+        }
+    }
+
+    /// Columns already cleared at each side of the centre of the
+    /// rows after the given time (wipe style)
+    pub fn wiped_cols(&self, t: f64, n_cols: usize) -> usize {
+        let half = (n_cols - 2) / 2;
+        (self.progress(t) * half as f32).ceil() as usize
+    }
+
+    /// True if the rows are lit after the given time (flash style)
+    pub fn flash_on(&self, t: f64) -> bool {
+        ((self.progress(t) * FLASHES * 2.0) as usize).is_multiple_of(2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::color::BLACK;
+
+    use super::{ClearAnimation, ClearStyle};
+    use crate::playfield::Playfield;
+    use crate::shape::{RotationType, Shape};
+
+    fn full_row_playfield() -> Playfield {
+        let mut pf = Playfield::new();
+        let row = Shape::new(vec![1; 10], 10, BLACK, RotationType::STILL);
+        pf.add(&row, 21, 1, 0);
+        pf
+    }
+
+    #[test]
+    fn test_clear_animation_progress() {
+        let pf = full_row_playfield();
+        let animation = ClearAnimation::new(ClearStyle::Wipe, &pf, &[21], 0.5);
+
+        assert_eq!(vec![21], animation.rows());
+        assert_eq!(0.0, animation.progress(0.0));
+        assert_eq!(0.5, animation.progress(0.25));
+        assert_eq!(1.0, animation.progress(1.0));
+
+        // The 10 columns are wiped 5 at each side
+        assert_eq!(0, animation.wiped_cols(0.0, pf.n_cols()));
+        assert_eq!(1, animation.wiped_cols(0.05, pf.n_cols()));
+        assert_eq!(5, animation.wiped_cols(0.5, pf.n_cols()));
+
+        // Without delay, the animation is over right away
+        let animation = ClearAnimation::new(ClearStyle::Flash, &pf, &[21], 0.0);
+        assert_eq!(1.0, animation.progress(0.0));
+    }
+
+    #[test]
+    fn test_clear_animation_particles() {
+        let pf = full_row_playfield();
+
+        let animation = ClearAnimation::new(ClearStyle::Flash, &pf, &[21], 0.5);
+        assert!(animation.particles().is_empty());
+
+        let animation = ClearAnimation::new(ClearStyle::Particles, &pf, &[21], 0.5);
+        assert_eq!(10, animation.particles().len());
+
+        let particle = &animation.particles()[0];
+        assert_eq!((21.0, 1.0), particle.position(0.0));
+        assert_eq!(1, particle.value);
+        // Particles fly upwards
+        assert!(particle.position(0.05).0 < 21.0);
+    }
+}
@@ -0,0 +1,358 @@
+//! Line clears: what happened when a piece was fixed, how it's
+//! announced, how many points it scores and how much garbage it
+//! sends to an opponent.
+//!
+//! Besides the cleared lines and spins, a clear keeps track of:
+//! * Combos (REN): consecutive pieces clearing lines
+//! * Back-to-back: difficult clears (Tetrises and spins) without
+//!   easier line clears in between
+//! * Perfect clears: clears that leave the playfield empty
+//!
+//! The clears of a game are counted by type (`ClearCounts`).
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::spin::Spin;
+
+
+/// Extra attack of combos, indexed by combo count (Guideline)
+const COMBO_ATTACK: [usize; 13] = [0, 0, 1, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+
+/// Extra attack of back-to-back clears
+const BACK_TO_BACK_ATTACK: usize = 1;
+
+/// Extra attack of perfect clears
+const PERFECT_CLEAR_ATTACK: usize = 10;
+
+/// Points per level of line clears without spins, by lines cleared
+/// (Guideline)
+const LINE_POINTS: [usize; 5] = [0, 100, 300, 500, 800];
+
+/// Points per level of mini spins, by lines cleared
+const MINI_SPIN_POINTS: [usize; 3] = [100, 200, 400];
+
+/// Points per level of spins, by lines cleared
+const SPIN_POINTS: [usize; 4] = [400, 800, 1200, 1600];
+
+/// Points per level of perfect clears, by lines cleared
+const PERFECT_CLEAR_POINTS: [usize; 5] = [0, 800, 1200, 1800, 2000];
+
+/// Points per level of back-to-back Tetris perfect clears
+const BACK_TO_BACK_PERFECT_CLEAR_POINTS: usize = 3200;
+
+/// Points per level and combo count
+const COMBO_POINTS: usize = 50;
+
+/// What happened when a piece was fixed to the playfield
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clear {
+    /// The cleared rows (none if the piece didn't clear any line)
+    pub rows: Vec<usize>,
+    pub spin: Spin,
+    /// The tetromino that was fixed (I, O, T, S, Z, L or J), if any
+    pub piece: Option<char>,
+    /// Previous consecutive pieces clearing lines (0 for the first
+    /// one, or if no line was cleared)
+    pub combo: usize,
+    /// A difficult clear following another one
+    pub back_to_back: bool,
+    /// The playfield was left empty
+    pub perfect_clear: bool,
+    /// Level reached with these lines, if they raised it
+    pub level_up: Option<usize>,
+    /// Game time when the piece was fixed
+    pub time: f64,
+}
+
+impl Clear {
+
+    pub fn lines(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Tetrises (or bigger clears) and spins clearing lines keep
+    /// back-to-back chains going
+    pub fn is_difficult(&self) -> bool {
+        self.lines() >= 4 || (self.lines() > 0 && self.spin != Spin::None)
+    }
+
+    /// Text announcing the clear (e.g. "T-Spin Double"), if there's
+    /// something to announce
+    pub fn callout(&self) -> Option<String> {
+        let lines = match self.lines() {
+            0 => None,
+            1 => Some("Single".to_string()),
+            2 => Some("Double".to_string()),
+            3 => Some("Triple".to_string()),
+            4 => Some("Tetris".to_string()),
+            n => Some(format!("{} Lines", n)),
+        };
+
+        let piece = self.piece.unwrap_or('T');
+        let spin = match self.spin {
+            Spin::None => None,
+            Spin::Mini => Some(format!("Mini {}-Spin", piece)),
+            Spin::Full => Some(format!("{}-Spin", piece)),
+        };
+
+        match (spin, lines) {
+            (Some(spin), Some(lines)) => Some(format!("{} {}", spin, lines)),
+            (Some(spin), None) => Some(spin),
+            (None, lines) => lines,
+        }
+    }
+
+    /// All the texts announcing the clear, one per line: back-to-back,
+    /// the clear itself, combo and perfect clear
+    pub fn callouts(&self) -> Vec<String> {
+        let mut callouts = Vec::new();
+
+        if self.back_to_back {
+            callouts.push("Back-to-Back".to_string());
+        }
+        if let Some(callout) = self.callout() {
+            callouts.push(callout);
+        }
+        if self.combo > 0 {
+            callouts.push(format!("{} Combo", self.combo));
+        }
+        if self.perfect_clear {
+            callouts.push("Perfect Clear".to_string());
+        }
+        if let Some(level) = self.level_up {
+            callouts.push(format!("Level {}", level));
+        }
+
+        callouts
+    }
+
+    /// Points scored at the given level (Guideline scoring):
+    /// difficult clears are worth 50% more when back-to-back, and
+    /// combos and perfect clears add bonus points
+    pub fn score(&self, level: usize) -> usize {
+        let lines = self.lines();
+
+        // Bigger clears (polyominoes) are scored as their size
+        // suggests: 200 points per line after a Tetris
+        let table = |points: &[usize]| points.get(lines).copied()
+            .unwrap_or_else(|| points[points.len() - 1]
+                + 200 * (lines + 1 - points.len()));
+
+        let mut points = match self.spin {
+            Spin::None => table(&LINE_POINTS),
+            Spin::Mini => table(&MINI_SPIN_POINTS),
+            Spin::Full => table(&SPIN_POINTS),
+        };
+
+        if self.back_to_back {
+            points += points / 2;
+        }
+        points += COMBO_POINTS * self.combo;
+        if self.perfect_clear {
+            points += if self.back_to_back && lines >= 4 {
+                BACK_TO_BACK_PERFECT_CLEAR_POINTS
+            } else {
+                table(&PERFECT_CLEAR_POINTS)
+            };
+        }
+
+        points * level
+    }
+
+    /// Garbage lines sent to an opponent (Guideline attack table)
+    pub fn attack(&self) -> usize {
+        let lines = self.lines();
+        if lines == 0 {
+            return 0;
+        }
+
+        let mut attack = match self.spin {
+            Spin::None if lines >= 4 => lines,
+            Spin::None => lines - 1,
+            Spin::Mini => lines - 1,
+            Spin::Full => lines * 2,
+        };
+
+        if self.back_to_back {
+            attack += BACK_TO_BACK_ATTACK;
+        }
+        attack += COMBO_ATTACK[self.combo.min(COMBO_ATTACK.len() - 1)];
+        if self.perfect_clear {
+            attack += PERFECT_CLEAR_ATTACK;
+        }
+
+        attack
+    }
+}
+
+/// Line clears of a game, by type. Spins are counted apart from the
+/// lines they clear.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ClearCounts {
+    pub singles: usize,
+    pub doubles: usize,
+    pub triples: usize,
+    /// Tetrises (and bigger clears)
+    pub tetrises: usize,
+    /// Spins, clearing lines or not
+    pub spins: usize,
+    pub mini_spins: usize,
+    pub perfect_clears: usize,
+}
+
+impl ClearCounts {
+
+    /// Count a piece fixed to the playfield
+    pub fn record(&mut self, clear: &Clear) {
+        match (clear.spin, clear.lines()) {
+            (Spin::Full, _) => self.spins += 1,
+            (Spin::Mini, _) => self.mini_spins += 1,
+            (Spin::None, 0) => {}
+            (Spin::None, 1) => self.singles += 1,
+            (Spin::None, 2) => self.doubles += 1,
+            (Spin::None, 3) => self.triples += 1,
+            (Spin::None, _) => self.tetrises += 1,
+        }
+        if clear.perfect_clear {
+            self.perfect_clears += 1;
+        }
+    }
+
+    /// Name and count of every type of clear
+    pub fn breakdown(&self) -> [(&str, usize); 7] {
+        [
+            ("Singles", self.singles),
+            ("Doubles", self.doubles),
+            ("Triples", self.triples),
+            ("Tetrises", self.tetrises),
+            ("Spins", self.spins),
+            ("Mini spins", self.mini_spins),
+            ("Perfect clears", self.perfect_clears),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clear, ClearCounts};
+    use crate::spin::Spin;
+
+    fn clear(lines: usize, spin: Spin) -> Clear {
+        Clear {
+            rows: (0..lines).collect(),
+            spin,
+            piece: Some('T'),
+            combo: 0,
+            back_to_back: false,
+            perfect_clear: false,
+            level_up: None,
+            time: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_callouts() {
+        assert_eq!(None, clear(0, Spin::None).callout());
+        assert_eq!(Some("Tetris".to_string()), clear(4, Spin::None).callout());
+        assert_eq!(Some("Mini T-Spin Single".to_string()),
+            clear(1, Spin::Mini).callout());
+        assert_eq!(Some("T-Spin Triple".to_string()),
+            clear(3, Spin::Full).callout());
+
+        let s_spin = Clear { piece: Some('S'), ..clear(2, Spin::Full) };
+        assert_eq!(Some("S-Spin Double".to_string()), s_spin.callout());
+
+        let all = Clear {
+            combo: 3,
+            back_to_back: true,
+            perfect_clear: true,
+            level_up: Some(2),
+            ..clear(4, Spin::None)
+        };
+        assert_eq!(vec!["Back-to-Back", "Tetris", "3 Combo", "Perfect Clear",
+                        "Level 2"],
+            all.callouts());
+        assert!(clear(0, Spin::None).callouts().is_empty());
+    }
+
+    #[test]
+    fn test_difficult() {
+        assert!(!clear(3, Spin::None).is_difficult());
+        assert!(clear(4, Spin::None).is_difficult());
+        assert!(clear(1, Spin::Mini).is_difficult());
+        assert!(!clear(0, Spin::Full).is_difficult());
+    }
+
+    #[test]
+    fn test_score() {
+        assert_eq!(0, clear(0, Spin::None).score(1));
+        assert_eq!(800, clear(4, Spin::None).score(1));
+        assert_eq!(1600, clear(4, Spin::None).score(2));
+        assert_eq!(400, clear(0, Spin::Full).score(1));
+        assert_eq!(200, clear(1, Spin::Mini).score(1));
+        assert_eq!(1000, clear(5, Spin::None).score(1));
+
+        let b2b = Clear { back_to_back: true, ..clear(2, Spin::Full) };
+        assert_eq!(1800, b2b.score(1));
+
+        let combo = Clear { combo: 3, ..clear(1, Spin::None) };
+        assert_eq!(250, combo.score(1));
+
+        let pc = Clear { perfect_clear: true, ..clear(1, Spin::None) };
+        assert_eq!(900, pc.score(1));
+        let pc = Clear { back_to_back: true, ..clear(4, Spin::None) };
+        let pc = Clear { perfect_clear: true, ..pc };
+        assert_eq!(1200 + 3200, pc.score(1));
+    }
+
+    #[test]
+    fn test_attack() {
+        assert_eq!(0, clear(0, Spin::Full).attack());
+        assert_eq!(0, clear(1, Spin::None).attack());
+        assert_eq!(1, clear(2, Spin::None).attack());
+        assert_eq!(4, clear(4, Spin::None).attack());
+        assert_eq!(1, clear(2, Spin::Mini).attack());
+        assert_eq!(4, clear(2, Spin::Full).attack());
+
+        let b2b = Clear { back_to_back: true, ..clear(3, Spin::Full) };
+        assert_eq!(7, b2b.attack());
+
+        let combo = Clear { combo: 4, ..clear(1, Spin::None) };
+        assert_eq!(1, combo.attack());
+        let combo = Clear { combo: 20, ..clear(1, Spin::None) };
+        assert_eq!(5, combo.attack());
+
+        let pc = Clear { perfect_clear: true, ..clear(4, Spin::None) };
+        assert_eq!(14, pc.attack());
+    }
+
+    #[test]
+    fn test_clear_counts() {
+        let mut counts = ClearCounts::default();
+        for clear in [
+            clear(0, Spin::None),
+            clear(1, Spin::None),
+            clear(4, Spin::None),
+            clear(5, Spin::None),
+            clear(0, Spin::Full),
+            clear(2, Spin::Full),
+            clear(1, Spin::Mini),
+            Clear { perfect_clear: true, ..clear(2, Spin::None) },
+        ] {
+            counts.record(&clear);
+        }
+
+        assert_eq!(ClearCounts {
+            singles: 1,
+            doubles: 1,
+            triples: 0,
+            tetrises: 2,
+            spins: 2,
+            mini_spins: 1,
+            perfect_clears: 1,
+        }, counts);
+        assert_eq!(("Tetrises", 2), counts.breakdown()[3]);
+    }
+}
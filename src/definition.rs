@@ -0,0 +1,496 @@
+//! Rotation system definitions loaded from TOML files
+//!
+//! A definition describes the shapes of a rotation system: their
+//! rotation states, colors, spawn row offsets and kick tables, so new
+//! rotation systems can be added without recompiling the game. See
+//! `assets/rotation-systems/ars.toml` for a complete example.
+//!
+//! ```toml
+//! name = "Example"
+//! lock_delay = 0.5          # optional, in seconds
+//!
+//! [[pieces]]
+//! name = "T"
+//! color = "purple"          # macroquad color name or "#rrggbb"
+//! row_offset = 1            # optional, defaults to 0
+//! rotations = [             # 1, 2 or 4 square matrices
+//!     ["...", "TTT", ".T."],
+//!     [".T.", "TT.", ".T."],
+//! ]
+//!
+//! [pieces.kicks]            # optional, (columns right, rows up)
+//! "*" = [[0, 0], [1, 0], [-1, 0]]     # any transition
+//! "0>1" = [[0, 0], [-1, 0]]           # from state 0 to state 1
+//! ```
+//!
+//! In the rotation matrices `.` is an empty cell and any other
+//! character is a block.
+
+#[cfg(feature = "serde")]
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use macroquad::{color::*, prelude::Color};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "serde")]
+use crate::playfield::Playfield;
+use crate::shape::Shape;
+
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DefinitionFile {
+    name: String,
+    lock_delay: Option<f64>,
+    #[serde(default)]
+    pieces: Vec<PieceFile>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PieceFile {
+    name: String,
+    color: String,
+    #[serde(default)]
+    row_offset: usize,
+    rotations: Vec<Vec<String>>,
+    #[serde(default)]
+    kicks: BTreeMap<String, Vec<[isize; 2]>>,
+}
+
+/// A validated rotation system definition, ready to be used
+/// with `RotationSystem::CUSTOM`
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RotationDefinition {
+    name: String,
+    lock_delay: Option<f64>,
+    shapes: Vec<Shape>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DefinitionError {
+    /// The definition file couldn't be read
+    Io(String),
+    /// The definition is not valid TOML or doesn't follow the format
+    Parse(String),
+    /// The definition doesn't contain any piece
+    NoPieces,
+    /// A piece is not valid. `rotation` is the offending rotation
+    /// state, if the error can be narrowed down to one of them.
+    Piece {
+        piece: String,
+        rotation: Option<usize>,
+        message: String,
+    },
+}
+
+impl fmt::Display for DefinitionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DefinitionError::Io(message) =>
+                write!(f, "can't read rotation system: {}", message),
+            DefinitionError::Parse(message) =>
+                write!(f, "invalid rotation system: {}", message),
+            DefinitionError::NoPieces =>
+                write!(f, "invalid rotation system: no pieces defined"),
+            DefinitionError::Piece { piece, rotation: Some(rot), message } =>
+                write!(f, "piece '{}', rotation {}: {}", piece, rot, message),
+            DefinitionError::Piece { piece, rotation: None, message } =>
+                write!(f, "piece '{}': {}", piece, message),
+        }
+    }
+}
+
+impl std::error::Error for DefinitionError {}
+
+
+impl RotationDefinition {
+
+    /// Create a definition from already built shapes
+    pub fn new(name: &str, lock_delay: Option<f64>, shapes: Vec<Shape>) -> Self {
+        Self {
+            name: name.to_string(),
+            lock_delay,
+            shapes,
+        }
+    }
+
+    /// Parse and validate a rotation system definition
+    ///
+    /// # Arguments
+    /// * `src` - The definition in TOML format
+    #[cfg(feature = "serde")]
+    pub fn from_toml(src: &str) -> Result<Self, DefinitionError> {
+
+        let file: DefinitionFile = toml::from_str(src)
+            .map_err(|e| DefinitionError::Parse(e.to_string()))?;
+
+        if file.pieces.is_empty() {
+            return Err(DefinitionError::NoPieces);
+        }
+
+        let shapes = file.pieces.iter()
+            .map(build_shape)
+            .collect::<Result<Vec<Shape>, DefinitionError>>()?;
+
+        Ok(Self {
+            name: file.name,
+            lock_delay: file.lock_delay,
+            shapes,
+        })
+    }
+
+    /// Load a rotation system definition from a file. Uses
+    /// macroquad file loading, so it works on the web too.
+    #[cfg(feature = "serde")]
+    pub async fn load(path: &str) -> Result<Self, DefinitionError> {
+        let src = macroquad::file::load_string(path).await
+            .map_err(|e| DefinitionError::Io(e.to_string()))?;
+
+        Self::from_toml(&src)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn lock_delay(&self) -> Option<f64> {
+        self.lock_delay
+    }
+
+    pub fn shapes(&self) -> &Vec<Shape> {
+        &self.shapes
+    }
+}
+
+
+#[cfg(feature = "serde")]
+fn piece_error(piece: &PieceFile, rotation: Option<usize>,
+               message: String) -> DefinitionError {
+    DefinitionError::Piece {
+        piece: piece.name.clone(),
+        rotation,
+        message,
+    }
+}
+
+#[cfg(feature = "serde")]
+fn build_shape(piece: &PieceFile) -> Result<Shape, DefinitionError> {
+
+    if ![1, 2, 4].contains(&piece.rotations.len()) {
+        return Err(piece_error(piece, None, format!(
+            "expected 1, 2 or 4 rotations, got {}", piece.rotations.len())));
+    }
+
+    let width = piece.rotations[0].len();
+    if width == 0 {
+        return Err(piece_error(piece, Some(0),
+            "the rotation state is empty".to_string()));
+    }
+
+    // The shape box has to fit in the playfield where it spawns
+    if width > Playfield::WIDTH {
+        return Err(piece_error(piece, None, format!(
+            "is {} cells wide, the playfield has {} columns",
+            width, Playfield::WIDTH)));
+    }
+    if piece.row_offset + width > Playfield::HEIGHT {
+        return Err(piece_error(piece, None, format!(
+            "row offset {} puts the piece below the floor ({} rows)",
+            piece.row_offset, Playfield::HEIGHT)));
+    }
+
+    let mut states: Vec<Vec<usize>> = Vec::new();
+    let mut n_blocks = 0;
+
+    for (rot, rows) in piece.rotations.iter().enumerate() {
+
+        if rows.len() != width {
+            return Err(piece_error(piece, Some(rot), format!(
+                "expected a square matrix of {} rows, got {}",
+                width, rows.len())));
+        }
+
+        let mut state: Vec<usize> = Vec::with_capacity(width * width);
+        for (row, cells) in rows.iter().enumerate() {
+            let n_cells = cells.chars().count();
+            if n_cells != width {
+                return Err(piece_error(piece, Some(rot), format!(
+                    "row {} has {} cells, expected {}", row, n_cells, width)));
+            }
+            state.extend(cells.chars().map(|c| if c == '.' { 0 } else { 1 }));
+        }
+
+        let blocks = state.iter().filter(|&&v| v > 0).count();
+        if blocks == 0 {
+            return Err(piece_error(piece, Some(rot),
+                "the rotation state has no blocks".to_string()));
+        } else if rot == 0 {
+            n_blocks = blocks;
+        } else if blocks != n_blocks {
+            return Err(piece_error(piece, Some(rot), format!(
+                "has {} blocks but the spawn state has {}", blocks, n_blocks)));
+        }
+
+        states.push(state);
+    }
+
+    let color = parse_color(&piece.color).ok_or_else(|| piece_error(
+        piece, None, format!("unknown color '{}'", piece.color)))?;
+
+    let mut shape = Shape::with_states(&states, width, color)
+        .set_row_offset(piece.row_offset);
+
+    // Validate the kick table before applying it: "*" is used for
+    // every transition without its own entry
+    let mut transitions: BTreeMap<(isize, isize), Vec<(isize, isize)>> =
+        BTreeMap::new();
+    for (key, offsets) in &piece.kicks {
+        let offsets: Vec<(isize, isize)> =
+            offsets.iter().map(|o| (o[0], o[1])).collect();
+
+        if key == "*" {
+            if offsets.is_empty() {
+                return Err(piece_error(piece, None,
+                    "kick '*' has no offsets".to_string()));
+            }
+            continue;
+        }
+
+        let (from, to) = parse_transition(key).ok_or_else(|| piece_error(
+            piece, None, format!(
+                "invalid kick transition '{}', expected '<from>><to>' \
+                 with rotation states from 0 to 3", key)))?;
+
+        if offsets.is_empty() {
+            return Err(piece_error(piece, Some(from as usize), format!(
+                "kick '{}' has no offsets", key)));
+        }
+
+        transitions.insert((from, to), offsets);
+    }
+
+    let default_kicks: Option<Vec<(isize, isize)>> = piece.kicks.get("*")
+        .map(|offsets| offsets.iter().map(|o| (o[0], o[1])).collect());
+
+    for from in 0..4 {
+        for to in 0..4 {
+            if from == to {
+                continue;
+            }
+            if let Some(offsets) = transitions.remove(&(from, to))
+                .or_else(|| default_kicks.clone()) {
+                shape = shape.set_kicks(from, to, offsets);
+            }
+        }
+    }
+
+    Ok(shape)
+}
+
+/// Parse a kick transition such as "0>1"
+#[cfg(feature = "serde")]
+fn parse_transition(key: &str) -> Option<(isize, isize)> {
+    let (from, to) = key.split_once('>')?;
+    let from: isize = from.trim().parse().ok()?;
+    let to: isize = to.trim().parse().ok()?;
+
+    if (0..4).contains(&from) && (0..4).contains(&to) && from != to {
+        Some((from, to))
+    } else {
+        None
+    }
+}
+
+/// Parse a color given as "#rrggbb" or as a macroquad color name
+#[cfg(feature = "serde")]
+fn parse_color(name: &str) -> Option<Color> {
+    if let Some(hex) = name.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let rgb = u32::from_str_radix(hex, 16).ok()?;
+        return Some(Color::from_rgba(
+            (rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8, 255));
+    }
+
+    let color = match name.to_lowercase().as_str() {
+        "lightgray" => LIGHTGRAY,
+        "gray" => GRAY,
+        "darkgray" => DARKGRAY,
+        "yellow" => YELLOW,
+        "gold" => GOLD,
+        "orange" => ORANGE,
+        "pink" => PINK,
+        "red" => RED,
+        "maroon" => MAROON,
+        "green" => GREEN,
+        "lime" => LIME,
+        "darkgreen" => DARKGREEN,
+        "skyblue" => SKYBLUE,
+        "blue" => BLUE,
+        "darkblue" => DARKBLUE,
+        "purple" => PURPLE,
+        "violet" => VIOLET,
+        "darkpurple" => DARKPURPLE,
+        "beige" => BEIGE,
+        "brown" => BROWN,
+        "darkbrown" => DARKBROWN,
+        "white" => WHITE,
+        "magenta" => MAGENTA,
+        _ => return None,
+    };
+
+    Some(color)
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use macroquad::color::PURPLE;
+
+    use super::{DefinitionError, RotationDefinition};
+    use crate::shape::{RotationSystem, ShapeBuilder};
+
+    const ARS: &str = include_str!("../assets/rotation-systems/ars.toml");
+
+    #[test]
+    fn test_load_definition() {
+        let definition = RotationDefinition::from_toml(ARS).unwrap();
+
+        assert_eq!("ARS", definition.name());
+        assert_eq!(Some(0.5), definition.lock_delay());
+        assert_eq!(7, definition.shapes().len());
+
+        let rs = RotationSystem::CUSTOM(definition);
+        assert_eq!("ARS", rs.name());
+        assert_eq!(Some(0.5), rs.lock_delay());
+        assert_eq!(7, rs.build_shapes().len());
+    }
+
+    #[test]
+    fn test_definition_states_and_kicks() {
+        let definition = RotationDefinition::from_toml(r#"
+            name = "Test"
+
+            [[pieces]]
+            name = "T"
+            color = "purple"
+            row_offset = 1
+            rotations = [
+                ["...", "TTT", ".T."],
+                [".T.", "TT.", ".T."],
+            ]
+
+            [pieces.kicks]
+            "*" = [[0, 0], [1, 0]]
+            "1>0" = [[0, 0], [-1, 0], [0, 1]]
+        "#).unwrap();
+
+        let t = &definition.shapes()[0];
+        assert_eq!(3, t.width());
+        assert_eq!(9, t.len());
+        assert_eq!(1, t.row_offset());
+        assert_eq!(PURPLE, t.color());
+
+        let cells = |rot: isize| -> Vec<usize> {
+            (0..t.len()).map(|i| *t.rotate(t.row(i), t.col(i), rot)).collect()
+        };
+        // Two states toggle
+        assert_eq!(vec![0, 0, 0, 1, 1, 1, 0, 1, 0], cells(0));
+        assert_eq!(vec![0, 1, 0, 1, 1, 0, 0, 1, 0], cells(1));
+        assert_eq!(cells(0), cells(2));
+        assert_eq!(cells(1), cells(3));
+
+        assert_eq!(&[(0, 0), (1, 0)], t.kicks(0, 1));
+        assert_eq!(&[(0, 0), (-1, 0), (0, 1)], t.kicks(1, 0));
+        assert_eq!(&[(0, 0), (1, 0)], t.kicks(3, 0));
+    }
+
+    #[test]
+    fn test_definition_errors() {
+        assert!(matches!(
+            RotationDefinition::from_toml("name = "),
+            Err(DefinitionError::Parse(_))));
+
+        assert_eq!(
+            Some(DefinitionError::NoPieces),
+            RotationDefinition::from_toml("name = \"Empty\"").err());
+
+        let piece = |rotations: &str, extra: &str| format!(r#"
+            name = "Test"
+
+            [[pieces]]
+            name = "Z"
+            color = "red"
+            rotations = {}
+            {}
+        "#, rotations, extra);
+
+        let err = RotationDefinition::from_toml(
+            &piece(r#"[["ZZ.", ".ZZ", "..."], ["..Z", ".ZZZ", ".Z."]]"#, ""));
+        assert_eq!(Some(DefinitionError::Piece {
+            piece: "Z".to_string(),
+            rotation: Some(1),
+            message: "row 1 has 4 cells, expected 3".to_string(),
+        }), err.err());
+
+        let err = RotationDefinition::from_toml(
+            &piece(r#"[["ZZ.", ".ZZ", "..."], ["..Z", ".ZZ"]]"#, ""));
+        assert_eq!(Some(DefinitionError::Piece {
+            piece: "Z".to_string(),
+            rotation: Some(1),
+            message: "expected a square matrix of 3 rows, got 2".to_string(),
+        }), err.err());
+
+        let err = RotationDefinition::from_toml(
+            &piece(r#"[["ZZ.", ".ZZ", "..."], ["..Z", ".ZZ", "..."]]"#, ""));
+        assert_eq!(Some(DefinitionError::Piece {
+            piece: "Z".to_string(),
+            rotation: Some(1),
+            message: "has 3 blocks but the spawn state has 4".to_string(),
+        }), err.err());
+
+        let err = RotationDefinition::from_toml(
+            &piece(r#"[["ZZ.", ".ZZ", "..."]]"#, "")
+                .replace("\"red\"", "\"nope\""));
+        assert_eq!(Some(DefinitionError::Piece {
+            piece: "Z".to_string(),
+            rotation: None,
+            message: "unknown color 'nope'".to_string(),
+        }), err.err());
+
+        let err = RotationDefinition::from_toml(&piece(
+            r#"[["ZZ.", ".ZZ", "..."]]"#,
+            "[pieces.kicks]\n\"0>4\" = [[0, 0]]"));
+        assert_eq!(Some(DefinitionError::Piece {
+            piece: "Z".to_string(),
+            rotation: None,
+            message: "invalid kick transition '0>4', expected '<from>><to>' \
+                      with rotation states from 0 to 3".to_string(),
+        }), err.err());
+
+        let wide = format!("[[{}]]",
+            vec![format!("\"{}\"", "Z".repeat(11)); 11].join(", "));
+        let err = RotationDefinition::from_toml(&piece(&wide, ""));
+        assert_eq!(Some(DefinitionError::Piece {
+            piece: "Z".to_string(),
+            rotation: None,
+            message: "is 11 cells wide, the playfield has 10 columns".to_string(),
+        }), err.err());
+
+        let err = RotationDefinition::from_toml(
+            &piece(r#"[["ZZ.", ".ZZ", "..."]]"#, "row_offset = 20"));
+        assert_eq!(Some(DefinitionError::Piece {
+            piece: "Z".to_string(),
+            rotation: None,
+            message: "row offset 20 puts the piece below the floor (22 rows)"
+                .to_string(),
+        }), err.err());
+    }
+}
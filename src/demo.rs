@@ -1,9 +1,9 @@
-/// Rotation demo for rusty blocks
- 
+//! Rotation demo for rusty blocks
+
 
 use macroquad::prelude::*;
 
-use crate::shape::{ShapeBuilder, ShapeFactory};
+use crate::shape::{RotationSystem, ShapeBuilder, ShapeFactory};
 use crate::graphics::Graphics;
 
 pub fn rotation_demo(graphics: &Graphics, shape_manager: &ShapeFactory,
@@ -19,8 +19,8 @@ pub fn rotation_demo(graphics: &Graphics, shape_manager: &ShapeFactory,
 
         if pos_x + graphics.block_size() * 5.0 >= screen_width() {
                      
-            pos_x += graphics.block_size() * 5.0;
-            pos_y += 100.0;
+            pos_x = graphics.block_size();
+            pos_y += graphics.block_size() * 6.0;
 
         }
 
@@ -29,4 +29,17 @@ pub fn rotation_demo(graphics: &Graphics, shape_manager: &ShapeFactory,
         pos_x += graphics.block_size() * 5.0;
     }
 
-}
\ No newline at end of file
+}
+
+/// Return the rotation system that follows the given one in a list
+/// (e.g. the ones in the menu: built-in, loaded from a definition
+/// file and polyomino piece sets), so the demo can cycle through all
+/// of them to compare their rotations
+pub fn next_rotation_system(current: &RotationSystem,
+                            systems: &[RotationSystem]) -> RotationSystem {
+    let next = systems.iter()
+        .position(|system| system.name() == current.name())
+        .map_or(0, |index| (index + 1) % systems.len());
+
+    systems.get(next).cloned().unwrap_or_else(|| current.clone())
+}
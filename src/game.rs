@@ -0,0 +1,1497 @@
+//! Game state and rules: moves the current piece, applies gravity,
+//! fixes pieces to the playfield and spawns the next ones.
+//!
+//! The game keeps its own clock, advanced by `update`, so it doesn't
+//! depend on the frame rate or on the wall clock.
+
+use crate::clear::{Clear, ClearCounts};
+use crate::handling::{AutoRepeat, Handling, INSTANT};
+use crate::input::{Action, Input};
+use crate::piece::Piece;
+use crate::playfield::Playfield;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::rsg;
+use crate::ruleset::Ruleset;
+#[cfg(feature = "serde")]
+use crate::savegame::{SaveError, SavedGame, SavedPiece, VERSION};
+use crate::shape::{RotationSystem, Shape, ShapeFactory};
+#[cfg(feature = "serde")]
+use crate::shape::ShapeBuilder;
+use crate::spin::{self, Kick};
+
+
+/// Points scored for every row the piece is soft dropped
+const SOFT_DROP_POINTS: usize = 1;
+
+/// Points scored for every row the piece is hard dropped
+const HARD_DROP_POINTS: usize = 2;
+
+/// Lines between split times (see `Game::splits`)
+pub const SPLIT_LINES: usize = 10;
+
+/// Duration of a game tick, in seconds. Games advance in fixed
+/// ticks so they can be replayed exactly. A tick lasts a frame of
+/// the original games, so their timings are given in ticks.
+pub const TICK: f64 = 1.0 / 60.0;
+
+/// Buttons held down during the current frame. Some actions
+/// depend on held buttons instead of button presses (e.g. shifts
+/// and soft drops, or the initial rotation and hold when a new
+/// piece spawns).
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Held {
+    pub left: bool,
+    pub right: bool,
+    pub soft_drop: bool,
+    pub rotate_cw: bool,
+    pub rotate_ccw: bool,
+    pub rotate_180: bool,
+    pub hold: bool,
+}
+
+/// Phases of the game loop. After a piece is fixed, the cleared
+/// lines stay on the playfield during the line clear delay, and
+/// then the next piece waits for the entry delay (ARE) to spawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Phase {
+    Falling,
+    LineClear,
+    Entry,
+}
+
+/// The ways a game can end
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameOver {
+    /// The new piece overlaps the stack when it spawns
+    BlockOut,
+    /// A piece was fixed entirely above the visible area
+    LockOut,
+    /// Garbage pushed the stack out of the top of the playfield
+    TopOut,
+    /// The line goal of the game was reached
+    Goal,
+    /// The time limit of the game was reached
+    TimeUp,
+}
+
+impl GameOver {
+    pub fn name(&self) -> &str {
+        match self {
+            GameOver::BlockOut => "Block out",
+            GameOver::LockOut => "Lock out",
+            GameOver::TopOut => "Top out",
+            GameOver::Goal => "Goal reached",
+            GameOver::TimeUp => "Time up",
+        }
+    }
+}
+
+/// Game statistics
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Stats {
+    /// Pieces fixed to the playfield
+    pub pieces: usize,
+    /// Lines cleared
+    pub lines: usize,
+    /// Current level
+    pub level: usize,
+    /// Points scored (Guideline scoring)
+    pub score: usize,
+    /// Game time, in seconds
+    pub time: f64,
+    /// Actions pressed by the player, plus the ticks where touch
+    /// gestures shift or drop the piece
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub keys: usize,
+    /// Line clears, by type
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub clears: ClearCounts,
+}
+
+#[derive(Clone)]
+pub struct Game {
+    pf: Playfield,
+    shapes: ShapeFactory,
+    ruleset: Ruleset,
+    handling: Handling,
+    start_level: usize,
+    piece: Piece,
+    hold: Option<Shape>,
+    // Hold can only be used once per piece
+    hold_used: bool,
+    // Game clock, in seconds
+    time: f64,
+    // Ticks run (see `tick`)
+    ticks: u64,
+    // Ticks when every `SPLIT_LINES` lines were cleared
+    splits: Vec<u64>,
+    drop_start: f64,
+    // Only used by rotation systems that define a lock delay
+    lock_start: Option<f64>,
+    // The piece will be fixed to the playfield in the next update
+    lock_pending: bool,
+    game_over: Option<GameOver>,
+    stats: Stats,
+    // Garbage rows (hole columns) added before the next piece spawns
+    garbage: Vec<usize>,
+    phase: Phase,
+    phase_start: f64,
+    // Full rows waiting for the line clear delay to end
+    clearing: Vec<usize>,
+    // Rotations and holds pressed during the delays, applied
+    // when the next piece spawns
+    buffer: Held,
+    // Shift handling state of each direction
+    left: AutoRepeat,
+    right: AutoRepeat,
+    down: AutoRepeat,
+    // Last direction pressed (-1 left, 1 right), which wins
+    // when both are held
+    last_shift: isize,
+    // Kick used by the last action, if it was a rotation
+    last_kick: Option<Kick>,
+    last_clear: Option<Clear>,
+    // Consecutive line clearing pieces
+    ren: usize,
+    // The last line clear was a Tetris or a spin
+    back_to_back: bool,
+}
+
+impl Game {
+
+    pub fn new(rotation_system: RotationSystem, ruleset: Ruleset) -> Self {
+        Self::build(ShapeFactory::new(rotation_system), ruleset)
+    }
+
+    fn build(mut shapes: ShapeFactory, ruleset: Ruleset) -> Self {
+        let pf = Playfield::new();
+        let piece = Piece::spawn(shapes.current_shape(), &pf);
+
+        Self {
+            pf,
+            shapes,
+            ruleset,
+            handling: Handling::default(),
+            start_level: 1,
+            piece,
+            hold: None,
+            hold_used: false,
+            time: 0.0,
+            ticks: 0,
+            splits: Vec::new(),
+            drop_start: 0.0,
+            lock_start: None,
+            lock_pending: false,
+            game_over: None,
+            stats: Stats { level: 1, ..Default::default() },
+            garbage: Vec::new(),
+            phase: Phase::Falling,
+            phase_start: 0.0,
+            clearing: Vec::new(),
+            buffer: Held::default(),
+            left: AutoRepeat::default(),
+            right: AutoRepeat::default(),
+            down: AutoRepeat::default(),
+            last_shift: 0,
+            last_kick: None,
+            last_clear: None,
+            ren: 0,
+            back_to_back: false,
+        }
+    }
+
+    pub fn playfield(&self) -> &Playfield {
+        &self.pf
+    }
+
+    pub fn piece(&self) -> &Piece {
+        &self.piece
+    }
+
+    /// Seed of the shapes dealt in this game
+    pub fn seed(&self) -> u64 {
+        self.shapes.seed()
+    }
+
+    pub fn shapes(&self) -> &ShapeFactory {
+        &self.shapes
+    }
+
+    pub fn shapes_mut(&mut self) -> &mut ShapeFactory {
+        &mut self.shapes
+    }
+
+    pub fn ruleset(&self) -> &Ruleset {
+        &self.ruleset
+    }
+
+    /// Change the rules. Takes effect on the next piece; use
+    /// `restart` to start a new game with them.
+    pub fn set_ruleset(&mut self, ruleset: Ruleset) {
+        self.ruleset = ruleset;
+        // The starting level must exist in the new level system
+        self.set_start_level(self.start_level);
+    }
+
+    pub fn level(&self) -> usize {
+        self.stats.level
+    }
+
+    /// Set the starting level (the lowest one of the level system,
+    /// or higher). The current level follows it.
+    pub fn set_start_level(&mut self, level: usize) {
+        let levels = self.ruleset.levels;
+        self.start_level = level.max(*levels.start_levels().start());
+        self.stats.level = levels.level(self.start_level, self.stats.lines);
+    }
+
+    /// Lines left to reach the line goal, if there's one
+    pub fn lines_left(&self) -> Option<usize> {
+        self.ruleset.line_goal
+            .map(|goal| goal.saturating_sub(self.stats.lines))
+    }
+
+    /// Ticks left to reach the time limit, if there's one
+    pub fn time_left(&self) -> Option<u64> {
+        self.ruleset.time_limit.map(|limit|
+            ((limit / TICK).round() as u64).saturating_sub(self.ticks))
+    }
+
+    pub fn handling(&self) -> &Handling {
+        &self.handling
+    }
+
+    pub fn set_handling(&mut self, handling: Handling) {
+        self.handling = handling;
+    }
+
+    /// The shape in the hold box, if any
+    pub fn hold_shape(&self) -> Option<&Shape> {
+        self.hold.as_ref()
+    }
+
+    /// True when the current piece was already swapped with
+    /// the held one
+    pub fn hold_used(&self) -> bool {
+        self.hold_used
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Ticks run since the game started. Timing by ticks instead of
+    /// seconds gives exact results, which replays reproduce.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Ticks when every `SPLIT_LINES` lines were cleared (e.g. the
+    /// second split is the tick when the 20th line was cleared)
+    pub fn splits(&self) -> &[u64] {
+        &self.splits
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.game_over.is_some()
+    }
+
+    /// How the game ended, if it's over
+    pub fn game_over(&self) -> Option<GameOver> {
+        self.game_over
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            time: self.time,
+            ..self.stats.clone()
+        }
+    }
+
+    /// Start a new game with the same rotation system, randomizer,
+    /// ruleset, handling and starting level
+    pub fn restart(&mut self) {
+        self.restart_with_seed(rsg::random_seed());
+    }
+
+    /// Start a new game like `restart`, dealing the shapes generated
+    /// with the given seed
+    pub fn restart_with_seed(&mut self, seed: u64) {
+        let shapes = ShapeFactory::with_seed(
+            self.shapes.current_rotation_system().clone(),
+            self.shapes.current_randomizer(),
+            seed);
+        let mut game = Game::build(shapes, self.ruleset.clone());
+        game.handling = self.handling.clone();
+        game.set_start_level(self.start_level);
+        *self = game;
+    }
+
+    /// The full state of the game, to resume it later. Input that is
+    /// being held down isn't saved.
+    #[cfg(feature = "serde")]
+    pub fn suspend(&self) -> SavedGame {
+        let index = |shape: &Shape| self.shapes.shape_index(shape).unwrap_or(0);
+
+        SavedGame {
+            version: VERSION,
+            mode: String::new(),
+            rotation_system: self.shapes.current_rotation_system().name().to_string(),
+            randomizer: self.shapes.current_randomizer(),
+            seed: self.shapes.seed(),
+            start_level: self.start_level,
+            cells: self.pf.cells().to_vec(),
+            hold: self.hold.as_ref().map(index),
+            hold_used: self.hold_used,
+            time: self.time,
+            ticks: self.ticks,
+            splits: self.splits.clone(),
+            drop_start: self.drop_start,
+            lock_start: self.lock_start,
+            lock_pending: self.lock_pending,
+            phase: self.phase,
+            phase_start: self.phase_start,
+            clearing: self.clearing.clone(),
+            garbage: self.garbage.clone(),
+            combo: self.ren,
+            back_to_back: self.back_to_back,
+            piece: SavedPiece {
+                shape: index(self.piece.shape()),
+                row: self.piece.row(),
+                col: self.piece.col(),
+                rot: self.piece.rot(),
+            },
+            generator: self.shapes.generator_state(),
+            stats: self.stats(),
+            ruleset: self.ruleset.clone(),
+            handling: self.handling.clone(),
+        }
+    }
+
+    /// Restore a game saved by `suspend`
+    ///
+    /// # Arguments
+    /// * `saved` - The saved game
+    /// * `rotation_system` - The rotation system named in the saved game
+    #[cfg(feature = "serde")]
+    pub fn resume(saved: &SavedGame, rotation_system: RotationSystem)
+        -> Result<Self, SaveError> {
+
+        let invalid = |message: &str| SaveError::Invalid(message.to_string());
+
+        let shapes = ShapeFactory::with_seed(
+            rotation_system, saved.randomizer, saved.seed);
+        let n_shapes = shapes.shapes().len();
+        let shape = |index: usize| shapes.shapes().get(index).cloned()
+            .ok_or_else(|| invalid("unknown shape"));
+
+        let generator = &saved.generator;
+        if generator.next >= n_shapes || generator.bag.iter().any(|s| *s >= n_shapes) {
+            return Err(invalid("unknown shape in the generator"));
+        }
+
+        let pf = Playfield::from_cells(&saved.cells)
+            .ok_or_else(|| invalid("wrong playfield size or walls"))?;
+        if saved.clearing.iter().any(|row| *row >= pf.n_rows()) {
+            return Err(invalid("clearing rows out of the playfield"));
+        }
+
+        let piece = Piece::place(shape(saved.piece.shape)?,
+            saved.piece.row, saved.piece.col, saved.piece.rot.rem_euclid(4));
+        if saved.phase == Phase::Falling && piece.collides(&pf) {
+            return Err(invalid("the piece overlaps the stack"));
+        }
+        let hold = saved.hold.map(shape).transpose()?;
+
+        let mut game = Game::build(shapes, saved.ruleset.clone());
+        game.shapes.set_generator_state(generator);
+        game.handling = saved.handling.clone();
+        game.set_start_level(saved.start_level);
+        game.pf = pf;
+        game.piece = piece;
+        game.hold = hold;
+        game.hold_used = saved.hold_used;
+        game.time = saved.time;
+        game.ticks = saved.ticks;
+        game.splits = saved.splits.clone();
+        game.drop_start = saved.drop_start;
+        game.lock_start = saved.lock_start;
+        game.lock_pending = saved.lock_pending;
+        game.stats = Stats {
+            level: saved.stats.level.max(game.start_level),
+            ..saved.stats.clone()
+        };
+        game.garbage = saved.garbage.clone();
+        game.phase = saved.phase;
+        game.phase_start = saved.phase_start;
+        game.clearing = saved.clearing.clone();
+        game.ren = saved.combo;
+        game.back_to_back = saved.back_to_back;
+
+        Ok(game)
+    }
+
+    /// Add a garbage row, with a hole at the given column (1 to 10),
+    /// before the next piece spawns
+    pub fn add_garbage(&mut self, hole: usize) {
+        self.garbage.push(hole);
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// Time elapsed since the current phase started, in seconds
+    pub fn phase_time(&self) -> f64 {
+        self.time - self.phase_start
+    }
+
+    /// The full rows shown during the line clear delay
+    pub fn clearing_rows(&self) -> &[usize] {
+        &self.clearing
+    }
+
+    /// What happened when the last piece was fixed, including spins
+    /// that didn't clear any line
+    pub fn last_clear(&self) -> Option<&Clear> {
+        self.last_clear.as_ref()
+    }
+
+    pub fn rotate_cw(&mut self) {
+        if self.phase == Phase::Falling {
+            self.rotate_piece(Piece::rotate_cw);
+        } else {
+            self.buffer.rotate_cw = true;
+        }
+    }
+
+    pub fn rotate_ccw(&mut self) {
+        if self.phase == Phase::Falling {
+            self.rotate_piece(Piece::rotate_ccw);
+        } else {
+            self.buffer.rotate_ccw = true;
+        }
+    }
+
+    pub fn rotate_180(&mut self) {
+        if self.phase == Phase::Falling {
+            self.rotate_piece(Piece::rotate_180);
+        } else {
+            self.buffer.rotate_180 = true;
+        }
+    }
+
+    /// Move the piece one column to the left (`cols` < 0) or
+    /// to the right (`cols` > 0)
+    ///
+    /// # Returns
+    /// True if the piece was moved
+    pub fn shift(&mut self, cols: isize) -> bool {
+        self.phase == Phase::Falling && self.shift_piece(cols)
+    }
+
+    /// Move the piece one row down, or fix it to the playfield
+    /// if it can't move down anymore
+    pub fn soft_drop(&mut self) {
+        if self.phase != Phase::Falling {
+            return;
+        }
+
+        if self.move_down() {
+            self.stats.score += SOFT_DROP_POINTS;
+        } else {
+            self.lock_pending = true;
+        }
+    }
+
+    /// Drop the piece straight to the floor and fix it to the
+    /// playfield on the next update. Until then it can't move
+    /// anymore, so held directions don't slide it on the floor.
+    pub fn hard_drop(&mut self) {
+        if self.phase != Phase::Falling {
+            return;
+        }
+
+        while self.move_down() {
+            self.stats.score += HARD_DROP_POINTS;
+        }
+        self.lock_pending = true;
+    }
+
+    /// Advance the game one tick with the input of the player
+    pub fn tick(&mut self, input: &Input) {
+        if !self.is_over() {
+            self.ticks += 1;
+            self.stats.keys += input.pressed().iter()
+                .filter(|action| **action != Action::Pause)
+                .count()
+                + usize::from(input.shift() != 0)
+                + usize::from(input.drops() > 0);
+        }
+
+        for action in input.pressed() {
+            self.perform(*action);
+        }
+
+        let cols = input.shift().signum();
+        for _ in 0..input.shift().unsigned_abs() {
+            self.shift(cols);
+        }
+        for _ in 0..input.drops() {
+            self.soft_drop();
+        }
+
+        // Held actions shift and drop the piece, and are used by the
+        // initial rotation and hold when a new piece spawns
+        self.update(TICK, &input.held());
+
+        if !self.is_over() && self.time_left() == Some(0) {
+            self.game_over = Some(GameOver::TimeUp);
+        }
+    }
+
+    /// Perform an action pressed by the player. Shifts and soft
+    /// drops are applied by `update` while they're held, and pausing
+    /// is up to the application.
+    pub fn perform(&mut self, action: Action) {
+        match action {
+            Action::HardDrop => self.hard_drop(),
+            Action::RotateCw => self.rotate_cw(),
+            Action::RotateCcw => self.rotate_ccw(),
+            Action::Rotate180 => self.rotate_180(),
+            Action::Hold => self.hold(),
+            Action::MoveLeft | Action::MoveRight | Action::SoftDrop
+                | Action::Pause => {}
+        }
+    }
+
+    /// Rotate the piece, remembering the kick that was used
+    fn rotate_piece(&mut self, rotate: fn(&mut Piece, &Playfield) -> Option<usize>) {
+        // A piece about to be fixed doesn't move anymore
+        if self.lock_pending {
+            return;
+        }
+
+        let from = self.piece.rot();
+        if let Some(kick) = rotate(&mut self.piece, &self.pf) {
+            let kicks = self.piece.shape().kicks(from, self.piece.rot());
+            self.last_kick = Some(Kick {
+                offset: kicks[kick],
+                last: kick == kicks.len() - 1,
+            });
+        }
+    }
+
+    fn shift_piece(&mut self, cols: isize) -> bool {
+        let moved = !self.lock_pending && self.piece.shift(&self.pf, cols);
+        if moved {
+            self.last_kick = None;
+        }
+        moved
+    }
+
+    /// Move the piece one row down, resetting the drop delay
+    fn move_down(&mut self) -> bool {
+        let moved = !self.lock_pending && self.piece.move_down(&self.pf);
+        if moved {
+            self.drop_start = self.time;
+            self.lock_start = None;
+            self.last_kick = None;
+        }
+        moved
+    }
+
+    /// Swap the current piece with the held one (or with the next
+    /// one when the hold box is empty). Can be used once per piece.
+    pub fn hold(&mut self) {
+        if !self.ruleset.hold || self.hold_used || self.is_over() {
+            return;
+        }
+
+        if self.phase != Phase::Falling {
+            self.buffer.hold = true;
+            return;
+        }
+
+        self.swap_hold();
+        self.check_block_out();
+    }
+
+    /// Swap the current piece with the held one (or with the next
+    /// one, if there's none held yet)
+    fn swap_hold(&mut self) {
+        let shape = match self.hold.take() {
+            Some(held) => held,
+            None => self.shapes.current_shape(),
+        };
+
+        self.hold = Some(self.piece.shape().clone());
+        self.hold_used = true;
+        self.place(shape);
+    }
+
+    /// Advance the game clock: move the piece with the held
+    /// direction buttons, apply gravity, fix the piece to the
+    /// playfield when needed and spawn the next one once the
+    /// delays are over.
+    ///
+    /// # Arguments
+    /// * `dt` - Time elapsed since the last update, in seconds
+    /// * `held` - Buttons held down
+    pub fn update(&mut self, dt: f64, held: &Held) {
+
+        if self.is_over() {
+            return;
+        }
+
+        self.time += dt;
+
+        // Held directions keep charging during the delays
+        self.apply_shift(dt, held);
+
+        match self.phase {
+            Phase::Falling => {
+                if !self.lock_pending {
+                    self.apply_gravity();
+                }
+
+                if self.lock_pending {
+                    self.lock_piece(held);
+                }
+            }
+            Phase::LineClear => {
+                if self.phase_time() >= self.ruleset.line_clear_delay {
+                    let rows = std::mem::take(&mut self.clearing);
+                    self.pf.clear_rows(&rows);
+                    self.enter(held);
+                }
+            }
+            Phase::Entry => {
+                if self.phase_time() >= self.ruleset.are {
+                    self.spawn_next(held);
+                }
+            }
+        }
+    }
+
+    fn apply_shift(&mut self, dt: f64, held: &Held) {
+
+        let handling = &self.handling;
+
+        // The last pressed direction wins when both are held
+        if held.left && !self.left.held() {
+            self.last_shift = -1;
+        }
+        if held.right && !self.right.held() {
+            self.last_shift = 1;
+        }
+
+        // Both directions are always updated to keep their DAS charge
+        let left = self.left.update(held.left, dt, handling.das, handling.arr);
+        let right = self.right.update(held.right, dt, handling.das, handling.arr);
+
+        let (cols, shifts) = match (held.left, held.right) {
+            (true, true) if self.last_shift < 0 => (-1, left),
+            (true, true) => (1, right),
+            (true, false) => (-1, left),
+            (false, true) => (1, right),
+            (false, false) => (0, 0),
+        };
+
+        // Soft drops work like shifts, but the first automatic drop
+        // happens after a single period
+        let period = self.ruleset.levels.drop_delay(self.stats.level) / handling.sdf;
+        let drops = self.down.update(held.soft_drop, dt, period, period);
+
+        if self.phase != Phase::Falling {
+            return;
+        }
+
+        for _ in 0..shifts {
+            if !self.shift_piece(cols) {
+                break;
+            }
+        }
+
+        if drops == INSTANT {
+            while self.move_down() {
+                self.stats.score += SOFT_DROP_POINTS;
+            }
+        } else {
+            for _ in 0..drops {
+                self.soft_drop();
+                if self.lock_pending {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn apply_gravity(&mut self) {
+
+        let grounded = self.piece.grounded(&self.pf);
+        let lock_delay = self.shapes.current_rotation_system().lock_delay();
+
+        if let (true, Some(lock_delay)) = (grounded, lock_delay) {
+
+            // The piece rests on the stack: it is fixed once the lock
+            // delay expires. The timer is only reset when the piece
+            // moves down again (step reset).
+            let start = *self.lock_start.get_or_insert(self.time);
+            if self.time - start >= lock_delay {
+                self.lock_pending = true;
+            }
+
+        } else if self.time - self.drop_start
+            >= self.ruleset.levels.drop_delay(self.stats.level)
+            && !self.move_down() {
+            self.lock_pending = true;
+        }
+    }
+
+    fn lock_piece(&mut self, held: &Held) {
+        // Spins are checked before the piece is part of the playfield
+        let spin = spin::detect(&self.piece, &self.pf, self.last_kick,
+            self.ruleset.spins);
+        // Add the shape to the playfield
+        let mod_rows = self.piece.lock(&mut self.pf);
+        // Get the rows that need to be cleared, if any
+        let cleared_lines = self.pf.check_rows(&mod_rows);
+
+        // The full rows are still on the playfield: check that all
+        // the other ones are empty
+        let perfect_clear = !cleared_lines.is_empty()
+            && (0..self.pf.n_rows() - 1)
+                .all(|row| cleared_lines.contains(&row) || self.pf.is_empty(row));
+
+        let mut clear = Clear {
+            rows: cleared_lines.clone(),
+            spin,
+            piece: spin::tetromino(&self.piece),
+            combo: 0,
+            back_to_back: false,
+            perfect_clear,
+            level_up: None,
+            time: self.time,
+        };
+
+        // Difficult clears keep the back-to-back chain, other line
+        // clears break it. Pieces that don't clear lines don't change
+        // it, but break combos.
+        if clear.lines() > 0 {
+            clear.back_to_back = clear.is_difficult() && self.back_to_back;
+            clear.combo = self.ren;
+            self.back_to_back = clear.is_difficult();
+            self.ren += 1;
+        } else {
+            self.ren = 0;
+        }
+        self.stats.score += clear.score(
+            self.ruleset.levels.score_level(self.stats.level));
+        self.stats.clears.record(&clear);
+
+        self.lock_pending = false;
+        self.hold_used = false;
+        self.stats.pieces += 1;
+        self.stats.lines += cleared_lines.len();
+        while self.splits.len() < self.stats.lines / SPLIT_LINES {
+            self.splits.push(self.ticks);
+        }
+        let level = self.ruleset.levels.level(self.start_level, self.stats.lines);
+        if level > self.stats.level {
+            self.stats.level = level;
+            clear.level_up = Some(level);
+        }
+        self.last_clear = Some(clear);
+
+        // Lock out: the piece is entirely above the visible area
+        if cleared_lines.is_empty()
+            && mod_rows.iter().all(|row| *row < Playfield::HIDDEN_ROWS) {
+            self.game_over = Some(GameOver::LockOut);
+            return;
+        }
+
+        if self.lines_left() == Some(0) {
+            self.pf.clear_rows(&cleared_lines);
+            self.game_over = Some(GameOver::Goal);
+            return;
+        }
+
+        if !cleared_lines.is_empty() {
+            if self.ruleset.line_clear_delay > 0.0 {
+                // The rows are cleared when the delay ends
+                self.clearing = cleared_lines;
+                self.start_phase(Phase::LineClear);
+                return;
+            }
+            self.pf.clear_rows(&cleared_lines);
+        }
+
+        self.enter(held);
+    }
+
+    /// Add the pending garbage, then wait for the entry delay, if
+    /// any, or spawn the next piece
+    fn enter(&mut self, held: &Held) {
+        for hole in std::mem::take(&mut self.garbage) {
+            if self.pf.add_garbage(hole) {
+                self.game_over = Some(GameOver::TopOut);
+                return;
+            }
+        }
+
+        if self.ruleset.are > 0.0 {
+            self.start_phase(Phase::Entry);
+        } else {
+            self.spawn_next(held);
+        }
+    }
+
+    fn start_phase(&mut self, phase: Phase) {
+        self.phase = phase;
+        self.phase_start = self.time;
+    }
+
+    /// Spawn the next piece, applying the initial hold and the
+    /// initial rotation when the corresponding buttons are held or
+    /// were pressed during the delays
+    fn spawn_next(&mut self, held: &Held) {
+
+        self.start_phase(Phase::Falling);
+        let buffer = std::mem::take(&mut self.buffer);
+
+        let shape = self.shapes.current_shape();
+        self.place(shape);
+
+        // Initial hold and rotation come before the block out check,
+        // so they can save the game
+        if self.ruleset.hold && self.ruleset.ihs && (held.hold || buffer.hold) {
+            self.swap_hold();
+        }
+
+        if self.ruleset.irs {
+            // The piece spawns in its normal orientation if the
+            // rotated one doesn't fit
+            if held.rotate_180 || buffer.rotate_180 {
+                self.piece.rotate_in_place(&self.pf, 2);
+            } else if held.rotate_cw || buffer.rotate_cw {
+                self.piece.rotate_in_place(&self.pf, 1);
+            } else if held.rotate_ccw || buffer.rotate_ccw {
+                self.piece.rotate_in_place(&self.pf, 3);
+            }
+        }
+
+        self.check_block_out();
+    }
+
+    /// Place a shape at its spawn position, without checking if it
+    /// fits
+    fn place(&mut self, shape: Shape) {
+        self.piece = Piece::spawn(shape, &self.pf);
+        self.drop_start = self.time;
+        self.lock_start = None;
+        self.last_kick = None;
+
+        self.handling.on_spawn(&mut self.left);
+        self.handling.on_spawn(&mut self.right);
+    }
+
+    /// Block out: the new piece overlaps the stack
+    fn check_block_out(&mut self) {
+        if self.piece.collides(&self.pf) {
+            self.game_over = Some(GameOver::BlockOut);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::color::BLACK;
+
+    use super::{Game, GameOver, Held, Phase, SPLIT_LINES};
+    use crate::definition::RotationDefinition;
+    use crate::input::{Action, Input};
+    use crate::handling::Handling;
+    use crate::playfield::Playfield;
+    use crate::ruleset::Ruleset;
+    use crate::shape::{RotationSystem, RotationType, Shape};
+    use crate::spin::Spin;
+
+    /// Drop the current piece to the bottom and fix it, waiting
+    /// for the next one to spawn
+    fn drop_piece(game: &mut Game, held: &Held) {
+        while !game.piece().grounded(game.playfield()) {
+            game.soft_drop();
+        }
+        game.soft_drop();
+        game.update(0.0, held);
+        while !game.is_over() && game.phase() != Phase::Falling {
+            game.update(1.0 / 60.0, held);
+        }
+    }
+
+    /// Fill the bottom row, except for the cells the current piece
+    /// will take when dropped
+    fn fill_bottom_row(game: &mut Game) {
+        let mut pf = Playfield::new();
+        let mut piece = game.piece().clone();
+        while piece.move_down(&pf) {}
+        piece.lock(&mut pf);
+
+        let block = Shape::new(vec![1], 1, BLACK, RotationType::STILL);
+        let row = pf.n_rows() - 2;
+        for col in 1..pf.n_cols() - 1 {
+            if pf.get_cell(row, col) == 0 {
+                game.pf.add(&block, row, col as isize, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_gravity() {
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        let row = game.piece().row();
+
+        game.update(0.5, &Held::default());
+        assert_eq!(row, game.piece().row());
+        game.update(0.5, &Held::default());
+        assert_eq!(row + 1, game.piece().row());
+
+        // Faster at higher levels
+        game.restart();
+        game.set_start_level(5);
+        assert_eq!(5, game.level());
+        game.update(0.375, &Held::default());
+        assert_eq!(row + 1, game.piece().row());
+
+        // The starting level is kept when restarting
+        game.restart();
+        assert_eq!(5, game.level());
+    }
+
+    #[test]
+    fn test_levels_and_line_goal() {
+        let mut ruleset = Ruleset::classic();
+        ruleset.line_goal = Some(3);
+        let mut game = Game::new(RotationSystem::NES, ruleset);
+        game.set_start_level(0);
+        assert_eq!(0, game.level());
+        assert_eq!(Some(3), game.lines_left());
+
+        // NES levels score singles at level 0 as level 1
+        for lines in 1..=3 {
+            assert!(!game.is_over());
+            game.pf = Playfield::new();
+            fill_bottom_row(&mut game);
+            drop_piece(&mut game, &Held::default());
+            assert_eq!(lines, game.stats().lines);
+        }
+        assert_eq!(Some(GameOver::Goal), game.game_over());
+        assert_eq!(Some(0), game.lines_left());
+        assert_eq!(0, game.level());
+        assert!(game.stats().score >= 3 * 100);
+
+        // Guideline levels start at 1
+        game.set_ruleset(Ruleset::modern());
+        assert_eq!(1, game.level());
+        assert_eq!(None, game.lines_left());
+    }
+
+    #[test]
+    fn test_ticks_splits_and_keys() {
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        let mut hard_drop = Input::default();
+        hard_drop.press(Action::HardDrop);
+
+        for _ in 0..SPLIT_LINES {
+            game.pf = Playfield::new();
+            fill_bottom_row(&mut game);
+            game.tick(&Input::default());
+            game.tick(&hard_drop);
+        }
+        assert_eq!(2 * SPLIT_LINES as u64, game.ticks());
+        assert_eq!(SPLIT_LINES, game.stats().lines);
+        assert_eq!(&[game.ticks()], game.splits());
+        assert_eq!(SPLIT_LINES, game.stats().keys);
+        assert_eq!(SPLIT_LINES, game.stats().clears.singles);
+    }
+
+    #[test]
+    fn test_time_limit() {
+        let mut ruleset = Ruleset::modern();
+        ruleset.time_limit = Some(1.0);
+        let mut game = Game::new(RotationSystem::SRS, ruleset);
+        assert_eq!(Some(60), game.time_left());
+
+        for _ in 0..59 {
+            game.tick(&Input::default());
+        }
+        assert!(!game.is_over());
+        assert_eq!(Some(1), game.time_left());
+
+        game.tick(&Input::default());
+        assert_eq!(Some(GameOver::TimeUp), game.game_over());
+        assert_eq!(Some(0), game.time_left());
+
+        // The clock stops when the game is over
+        game.tick(&Input::default());
+        assert_eq!(60, game.ticks());
+    }
+
+    #[test]
+    fn test_shift_handling() {
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        game.set_handling(Handling {
+            das: 0.25,
+            arr: 0.125,
+            ..Default::default()
+        });
+        let col = game.piece().col();
+        let left = Held { left: true, ..Default::default() };
+        let both = Held { left: true, right: true, ..Default::default() };
+
+        // First press
+        game.update(0.0, &left);
+        assert_eq!(col - 1, game.piece().col());
+        // DAS
+        game.update(0.125, &left);
+        assert_eq!(col - 1, game.piece().col());
+        game.update(0.125, &left);
+        assert_eq!(col - 2, game.piece().col());
+        // ARR
+        game.update(0.125, &left);
+        assert_eq!(col - 3, game.piece().col());
+
+        // Right was pressed last: it wins, and has its own DAS
+        game.update(0.0, &both);
+        assert_eq!(col - 2, game.piece().col());
+        game.update(0.125, &both);
+        assert_eq!(col - 2, game.piece().col());
+
+        // Releasing right goes back to the charged left direction
+        game.update(0.125, &left);
+        assert_eq!(col - 3, game.piece().col());
+
+        // Instant ARR moves to the wall
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        game.set_handling(Handling { das: 0.1, arr: 0.0, ..Default::default() });
+        game.update(0.0, &left);
+        game.update(0.1, &left);
+        assert!(!game.piece().clone().shift(game.playfield(), -1));
+    }
+
+    #[test]
+    fn test_soft_drop_factor() {
+        let down = Held { soft_drop: true, ..Default::default() };
+
+        // 1 / 16 = 0.0625 seconds per row at level 1
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        let row = game.piece().row();
+        game.update(0.0, &down);
+        assert_eq!(row + 1, game.piece().row());
+        game.update(0.125, &down);
+        assert_eq!(row + 3, game.piece().row());
+
+        // Infinite soft drop factor moves to the floor without locking
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        game.set_handling(Handling { sdf: f64::INFINITY, ..Default::default() });
+        game.update(0.0, &down);
+        game.update(0.0, &down);
+        assert!(game.piece().grounded(game.playfield()));
+        let pf = game.playfield();
+        assert!((0..pf.n_rows() - 1).all(|row| pf.is_empty(row)));
+    }
+
+    #[test]
+    fn test_delays() {
+        let mut ruleset = Ruleset::modern();
+        ruleset.line_clear_delay = 0.5;
+        ruleset.are = 0.25;
+
+        let mut game = Game::new(RotationSystem::SRS, ruleset);
+        game.set_handling(Handling { das: 0.25, ..Default::default() });
+        fill_bottom_row(&mut game);
+        let bottom = game.playfield().n_rows() - 2;
+        let left = Held { left: true, ..Default::default() };
+
+        while !game.piece().grounded(game.playfield()) {
+            game.soft_drop();
+        }
+        game.soft_drop();
+        game.update(0.0, &Held::default());
+
+        // The full row stays on the playfield during the delay
+        assert_eq!(Phase::LineClear, game.phase());
+        assert_eq!(vec![bottom], game.clearing_rows());
+        assert!(!game.playfield().is_empty(bottom));
+
+        // Inputs are buffered
+        game.rotate_cw();
+        game.update(0.25, &left);
+        assert_eq!(Phase::LineClear, game.phase());
+
+        game.update(0.25, &left);
+        assert_eq!(Phase::Entry, game.phase());
+        assert!(game.clearing_rows().is_empty());
+
+        game.update(0.25, &left);
+        assert_eq!(Phase::Falling, game.phase());
+        assert_eq!(1, game.piece().rot());
+
+        // DAS was charged during the delays
+        let col = game.piece().col();
+        game.update(0.0, &left);
+        assert_eq!(col - 1, game.piece().col());
+    }
+
+    #[test]
+    fn test_game_over() {
+        let block = Shape::new(vec![1], 1, BLACK, RotationType::STILL);
+        let t = Shape::new(
+            vec![0, 1, 0,
+                 1, 1, 1,
+                 0, 0, 0],
+            3,
+            BLACK,
+            RotationType::SRS,
+        );
+
+        // Pieces pile up until the game is over
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        while !game.is_over() {
+            drop_piece(&mut game, &Held::default());
+        }
+        let stats = game.stats();
+        assert!(stats.pieces > 0);
+        assert_eq!(0, stats.lines);
+
+        // Restart
+        game.restart();
+        assert!(!game.is_over());
+        assert_eq!(0, game.stats().pieces);
+        assert!(game.playfield().is_empty(game.playfield().n_rows() - 2));
+
+        // Block out: the new piece overlaps the stack
+        for col in 1..game.playfield().n_cols() - 1 {
+            game.pf.add(&block, 1, col as isize, 0);
+        }
+        game.place(t.clone());
+        game.check_block_out();
+        assert_eq!(Some(GameOver::BlockOut), game.game_over());
+
+        // Lock out: a piece fixed in the hidden rows, above a stack
+        // that reaches the top of the visible area
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        let bottom = game.playfield().n_rows() - 2;
+        for row in Playfield::HIDDEN_ROWS..=bottom {
+            for col in 2..game.playfield().n_cols() - 1 {
+                game.pf.add(&block, row, col as isize, 0);
+            }
+        }
+        game.place(t);
+        game.soft_drop();
+        game.update(0.0, &Held::default());
+        assert_eq!(Some(GameOver::LockOut), game.game_over());
+
+        // Top out: garbage pushes the stack out of the playfield
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        game.add_garbage(1);
+        drop_piece(&mut game, &Held::default());
+        assert!(!game.is_over());
+        game.pf.add(&block, 0, 1, 0);
+        game.add_garbage(1);
+        drop_piece(&mut game, &Held::default());
+        assert_eq!(Some(GameOver::TopOut), game.game_over());
+    }
+
+    #[test]
+    fn test_t_spin() {
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        let block = Shape::new(vec![1], 1, BLACK, RotationType::STILL);
+        let t = Shape::new(
+            vec![0, 1, 0,
+                 1, 1, 1,
+                 0, 0, 0],
+            3,
+            BLACK,
+            RotationType::SRS,
+        );
+
+        // T pointing down on the floor, with blocks at its sides
+        game.place(t);
+        game.rotate_180();
+        while !game.piece().grounded(game.playfield()) {
+            game.soft_drop();
+        }
+        let col = game.piece().col();
+        game.pf.add(&block, 21, col, 0);
+        game.pf.add(&block, 21, col + 2, 0);
+        game.pf.add(&block, 19, col, 0);
+        game.pf.add(&block, 19, col + 2, 0);
+
+        // Rotated into a T pointing up, with 4 corners occupied
+        game.rotate_180();
+        assert_eq!(0, game.piece().rot());
+        game.soft_drop();
+        game.update(0.0, &Held::default());
+
+        let clear = game.last_clear().unwrap();
+        assert_eq!(Spin::Full, clear.spin);
+        assert_eq!(0, clear.lines());
+        assert_eq!(Some("T-Spin".to_string()), clear.callout());
+
+        // Moving after the rotation cancels the spin
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        game.rotate_cw();
+        game.shift(1);
+        drop_piece(&mut game, &Held::default());
+        assert_eq!(Spin::None, game.last_clear().unwrap().spin);
+    }
+
+    #[test]
+    fn test_t_spin_last_kick() {
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        let block = Shape::new(vec![1], 1, BLACK, RotationType::STILL);
+        // The SRS T, with its kick table
+        let t = game.shapes().shapes()[5].clone();
+
+        // A T-spin slot in columns 2 and 3, under an overhang at
+        // (17, 2): rows 19 to 21 are full but for the T and one of
+        // its front corners
+        let slot = [(19, 2), (20, 2), (20, 3), (21, 2), (21, 3)];
+        for row in 19..22 {
+            for col in 1..11 {
+                if !slot.contains(&(row, col)) {
+                    game.pf.add(&block, row, col, 0);
+                }
+            }
+        }
+        game.pf.add(&block, 17, 2, 0);
+
+        // T pointing up on row 19, slid under the overhang
+        game.place(t);
+        game.shift(3 - game.piece().col());
+        while !game.piece().grounded(game.playfield()) {
+            game.soft_drop();
+        }
+        game.shift(-1);
+        assert_eq!((17, 2), (game.piece().row(), game.piece().col()));
+
+        // Only the last kick test (1 left, 2 down) fits, which makes
+        // a full T-spin even with a single front corner occupied
+        game.rotate_cw();
+        assert_eq!((19, 1), (game.piece().row(), game.piece().col()));
+        game.soft_drop();
+        game.update(0.0, &Held::default());
+
+        let clear = game.last_clear().unwrap();
+        assert_eq!(Spin::Full, clear.spin);
+        assert_eq!(2, clear.lines());
+    }
+
+    #[test]
+    fn test_combo_and_back_to_back() {
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        let block = Shape::new(vec![1], 1, BLACK, RotationType::STILL);
+        let i = Shape::new(
+            vec![0, 0, 0, 0,
+                 1, 1, 1, 1,
+                 0, 0, 0, 0,
+                 0, 0, 0, 0],
+            4,
+            BLACK,
+            RotationType::SRS,
+        );
+        let bottom = game.playfield().n_rows() - 2;
+
+        // Tetris with a vertical I in a well at the left wall
+        let tetris = |game: &mut Game| {
+            for row in bottom - 3..=bottom {
+                for col in 2..game.playfield().n_cols() - 1 {
+                    game.pf.add(&block, row, col as isize, 0);
+                }
+            }
+            game.place(i.clone());
+            game.rotate_cw();
+            while game.shift(-1) {}
+            drop_piece(game, &Held::default());
+            game.last_clear().unwrap().clone()
+        };
+
+        let clear = tetris(&mut game);
+        assert_eq!(4, clear.lines());
+        assert_eq!(0, clear.combo);
+        assert!(!clear.back_to_back);
+        assert!(clear.perfect_clear);
+
+        let clear = tetris(&mut game);
+        assert_eq!(1, clear.combo);
+        assert!(clear.back_to_back);
+        assert!(clear.perfect_clear);
+
+        // A single breaks the back-to-back chain, but keeps the combo
+        let t = Shape::new(vec![0, 1, 0, 1, 1, 1, 0, 0, 0], 3, BLACK,
+            RotationType::SRS);
+        game.place(t);
+        fill_bottom_row(&mut game);
+        drop_piece(&mut game, &Held::default());
+        let clear = game.last_clear().unwrap();
+        assert_eq!(1, clear.lines());
+        assert_eq!(2, clear.combo);
+        assert!(!clear.back_to_back);
+        assert!(!clear.perfect_clear);
+
+        assert!(!game.back_to_back);
+
+        // No lines: no combo
+        drop_piece(&mut game, &Held::default());
+        assert_eq!(0, game.last_clear().unwrap().combo);
+        assert_eq!(0, game.last_clear().unwrap().lines());
+    }
+
+    #[test]
+    fn test_hard_drop() {
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        let bottom = game.playfield().n_rows() - 2;
+
+        let row = game.piece().row();
+        game.perform(Action::HardDrop);
+        let cells = game.piece().cells();
+        let dropped = game.piece().row() - row;
+        game.update(0.0, &Held::default());
+
+        assert_eq!(1, game.stats().pieces);
+        assert_eq!(2 * dropped, game.stats().score);
+        assert!(cells.iter().any(|(row, _)| *row == bottom));
+        assert!(cells.iter().all(|(row, col)| game.playfield().get_cell(*row, *col) != 0));
+    }
+
+    #[test]
+    fn test_hard_drop_held_direction() {
+        let locked = |game: &Game, cells: &[(usize, usize)]| cells.iter()
+            .all(|(row, col)| game.playfield().get_cell(*row, *col) != 0);
+
+        // A held direction doesn't slide the piece before it's fixed
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        game.perform(Action::HardDrop);
+        let cells = game.piece().cells();
+        game.update(0.0, &Held { left: true, ..Default::default() });
+        assert!(locked(&game, &cells));
+
+        // Neither do touch shifts and drops in the same tick
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        let mut dropped = game.piece().clone();
+        while dropped.move_down(game.playfield()) {}
+        let mut input = Input::default();
+        input.press(Action::HardDrop);
+        input.hold(Action::MoveLeft);
+        input.add_shift(-2);
+        input.add_drops(1);
+        game.tick(&input);
+        assert!(locked(&game, &dropped.cells()));
+    }
+
+    #[test]
+    fn test_initial_rotation() {
+        let held = Held { rotate_cw: true, ..Default::default() };
+
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        drop_piece(&mut game, &held);
+        assert_eq!(1, game.piece().rot());
+
+        let held = Held { rotate_180: true, ..Default::default() };
+        drop_piece(&mut game, &held);
+        assert_eq!(2, game.piece().rot());
+
+        // IRS disabled
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::classic());
+        drop_piece(&mut game, &held);
+        assert_eq!(0, game.piece().rot());
+    }
+
+    #[test]
+    fn test_hold() {
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        let first = game.piece().shape().color();
+        let next = game.shapes().next_shape().color();
+
+        game.hold();
+        assert_eq!(Some(first), game.hold_shape().map(|s| s.color()));
+        assert_eq!(next, game.piece().shape().color());
+        assert!(game.hold_used());
+
+        // Only once per piece
+        game.hold();
+        assert_eq!(Some(first), game.hold_shape().map(|s| s.color()));
+        assert_eq!(next, game.piece().shape().color());
+
+        drop_piece(&mut game, &Held::default());
+        assert!(!game.hold_used());
+        game.hold();
+        assert_eq!(first, game.piece().shape().color());
+
+        // Hold disabled
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::classic());
+        game.hold();
+        assert!(game.hold_shape().is_none());
+    }
+
+    #[test]
+    fn test_initial_hold() {
+        let held = Held { hold: true, ..Default::default() };
+
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        let next = game.shapes().next_shape().color();
+        drop_piece(&mut game, &held);
+
+        // The spawned piece went straight to the hold box
+        assert_eq!(Some(next), game.hold_shape().map(|s| s.color()));
+        assert!(game.hold_used());
+
+        let mut ruleset = Ruleset::modern();
+        ruleset.ihs = false;
+        let mut game = Game::new(RotationSystem::SRS, ruleset);
+        drop_piece(&mut game, &held);
+        assert!(game.hold_shape().is_none());
+    }
+
+    #[test]
+    fn test_initial_actions_avoid_block_out() {
+        let block = Shape::new(vec![1], 1, BLACK, RotationType::STILL);
+        let i = Shape::new(
+            vec![0, 0, 0, 0,
+                 1, 1, 1, 1,
+                 0, 0, 0, 0,
+                 0, 0, 0, 0],
+            4,
+            BLACK,
+            RotationType::SRS,
+        );
+        let o = Shape::new(vec![1, 1, 1, 1], 2, BLACK, RotationType::STILL);
+
+        // Only I shapes, with a block where the left end of a
+        // horizontal one spawns (but not a vertical one)
+        let only_i = RotationDefinition::new("I", None, vec![i]);
+        let mut game = Game::new(RotationSystem::CUSTOM(only_i), Ruleset::modern());
+        let (row, col) = (game.piece().row() + 1, game.piece().col());
+        game.pf.add(&block, row, col, 0);
+
+        game.spawn_next(&Held { rotate_cw: true, ..Default::default() });
+        assert!(!game.is_over());
+        assert_eq!(1, game.piece().rot());
+
+        game.spawn_next(&Held::default());
+        assert_eq!(Some(GameOver::BlockOut), game.game_over());
+
+        // Holding swaps it for a held shape that fits
+        game.game_over = None;
+        game.hold = Some(o);
+        game.spawn_next(&Held { hold: true, ..Default::default() });
+        assert!(!game.is_over());
+        assert!(game.hold_used());
+    }
+}
@@ -1,49 +1,139 @@
-/// Graphics module for drawing on the screen
+//! Graphics module for drawing on the screen
 
 use macroquad::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
-use crate::{playfield::Playfield, shape::Shape};
+use crate::{animation::{ClearAnimation, ClearStyle}, game::GameOver,
+    input::{Action, Input}, menu::Menu, playfield::Playfield, shape::Shape};
+#[cfg(feature = "serde")]
+use crate::scores::{format_date, Ranking, Score};
 
-const BLOCK_SIZE: f32 = 20.0;
+/// Default largest block size, in pixels
+pub const BLOCK_SIZE: f32 = 20.0;
 
 pub struct Graphics {
     pub block_size: f32,
     pub pf_x: f32,
     pub pf_y: f32,
+    theme: Theme,
+    // Largest block size, in pixels (smaller screens use smaller ones)
+    max_block_size: f32,
+}
+
+/// Colors of the screen and the playfield
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Theme {
+    /// White blocks on black cells, with purple walls
+    #[default]
+    Classic,
+    /// Light gray blocks on dark cells, with blue walls
+    Dark,
+    /// Dark blocks on white cells, with gray walls
+    Light,
+}
+
+impl Theme {
+
+    pub const ALL: [Theme; 3] = [Theme::Classic, Theme::Dark, Theme::Light];
+
+    pub fn name(&self) -> &str {
+        match self {
+            Theme::Classic => "Classic",
+            Theme::Dark => "Dark",
+            Theme::Light => "Light",
+        }
+    }
+
+    pub fn background(&self) -> Color {
+        match self {
+            Theme::Classic => DARKGRAY,
+            Theme::Dark => Color::new(0.05, 0.05, 0.08, 1.0),
+            Theme::Light => Color::new(0.7, 0.72, 0.76, 1.0),
+        }
+    }
+
+    /// Color of a playfield cell with the given value (0 is empty,
+    /// 99 is a wall)
+    pub fn cell_color(&self, value: usize) -> Color {
+        match (self, value) {
+            (Theme::Classic, 0) => BLACK,
+            (Theme::Classic, 99) => DARKPURPLE,
+            (Theme::Classic, _) => WHITE,
+            (Theme::Dark, 0) => Color::new(0.12, 0.12, 0.15, 1.0),
+            (Theme::Dark, 99) => DARKBLUE,
+            (Theme::Dark, _) => LIGHTGRAY,
+            (Theme::Light, 0) => WHITE,
+            (Theme::Light, 99) => GRAY,
+            (Theme::Light, _) => DARKGRAY,
+        }
+    }
 }
 
 
-pub fn color_for(i: usize) -> Color {
-    if i == 0 {
-        BLACK
-    } else if i == 99 {
-        DARKPURPLE 
-    } else {
-        WHITE
+/// Short label of an action, for buttons and overlays
+fn action_label(action: Action) -> &'static str {
+    match action {
+        Action::MoveLeft => "<",
+        Action::MoveRight => ">",
+        Action::SoftDrop => "v",
+        Action::HardDrop => "DROP",
+        Action::RotateCw => "CW",
+        Action::RotateCcw => "CCW",
+        Action::Rotate180 => "180",
+        Action::Hold => "HOLD",
+        Action::Pause => "||",
     }
 }
 
 
+/// Format a time given in seconds as minutes, seconds and
+/// hundredths (e.g. 1:05.42)
+pub fn format_time(seconds: f64) -> String {
+    let hundredths = (seconds * 100.0).floor() as u64;
+    format!("{}:{:02}.{:02}",
+        hundredths / 6000, (hundredths / 100) % 60, hundredths % 100)
+}
+
+
 impl Graphics {
 
     pub fn new(pf: &Playfield) -> Self {
         
-        let (block_size, pf_x, pf_y) = Graphics::get_scaled_vars(pf);
+        let (block_size, pf_x, pf_y) = Graphics::get_scaled_vars(pf, BLOCK_SIZE);
 
         Self { 
             block_size, 
             pf_x, 
-            pf_y 
+            pf_y,
+            theme: Theme::default(),
+            max_block_size: BLOCK_SIZE,
         }
     }
 
+    pub fn theme(&self) -> Theme {
+        self.theme
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    /// Set the largest block size, in pixels (applied on the next
+    /// `update_scale`)
+    pub fn set_max_block_size(&mut self, size: f32) {
+        self.max_block_size = size;
+    }
+
     pub fn block_size(&self) -> f32 {
         self.block_size
     }
 
     pub fn update_scale(&mut self, pf: &Playfield) {
         
-        let (block_size, pf_x, pf_y) = Graphics::get_scaled_vars(pf);
+        let (block_size, pf_x, pf_y) =
+            Graphics::get_scaled_vars(pf, self.max_block_size);
         
         self.block_size = block_size;
         self.pf_x = pf_x;
@@ -53,7 +143,7 @@ impl Graphics {
     pub fn draw_playfield(&self, p: &Playfield) {
         // Draw hidden rows
         // TODO we might use some kind of animation for these killing zone
-        for row in 0..2 {
+        for row in 0..Playfield::HIDDEN_ROWS {
             for col in 0..p.n_cols() {
                 draw_rectangle_lines(
                     self.pf_x + (col as f32 * self.block_size) + 1.0,
@@ -61,20 +151,20 @@ impl Graphics {
                     self.block_size - 2.0,
                     self.block_size - 2.0,
                     4.0,
-                    color_for(p.get_cell(row, col)),
+                    self.theme.cell_color(p.get_cell(row, col)),
                 );
             }
         }
     
         // Draw visible rows
-        for row in 2..p.n_rows() {
+        for row in Playfield::HIDDEN_ROWS..p.n_rows() {
             for col in 0..p.n_cols() {
                 draw_rectangle(
                     self.pf_x + (col as f32 * self.block_size) + 1.0,
                     self.pf_y + (row as f32 * self.block_size) + 1.0,
                     self.block_size - 2.0,
                     self.block_size - 2.0,
-                    color_for(p.get_cell(row, col)),
+                    self.theme.cell_color(p.get_cell(row, col)),
                 );
             }
         }
@@ -119,9 +209,353 @@ impl Graphics {
 
     }
 
-    fn get_scaled_vars(pf: &Playfield) -> (f32, f32, f32){
+    /// Draw a line clear animation on top of the playfield
+    ///
+    /// # Arguments
+    /// * `pf` - The playfield, with the full rows still on it
+    /// * `animation` - The animation of the full rows
+    /// * `t` - Time elapsed since the animation started, in seconds
+    pub fn draw_line_clear(&self, pf: &Playfield, animation: &ClearAnimation,
+                           t: f64) {
+        let progress = animation.progress(t);
+        let cols = 1..pf.n_cols() - 1;
+
+        for row in animation.rows() {
+            let row = *row;
+
+            match animation.style() {
+                ClearStyle::Wipe => {
+                    let half = (pf.n_cols() - 2) / 2;
+                    let wiped = animation.wiped_cols(t, pf.n_cols());
+                    for col in (half + 1 - wiped)..=(half + wiped) {
+                        self.draw_cell(row as f32, col as f32, 1.0, BLACK);
+                    }
+                }
+                ClearStyle::Flash => {
+                    let color = if animation.flash_on(t) { WHITE } else { BLACK };
+                    for col in cols.clone() {
+                        self.draw_cell(row as f32, col as f32, 1.0, color);
+                    }
+                }
+                ClearStyle::Particles => {
+                    for col in cols.clone() {
+                        self.draw_cell(row as f32, col as f32, 1.0, BLACK);
+                    }
+                }
+                ClearStyle::Collapse => {
+                    for col in cols.clone() {
+                        self.draw_cell(row as f32, col as f32, 1.0, BLACK);
+                        self.draw_cell(row as f32, col as f32, 1.0 - progress,
+                            self.theme.cell_color(pf.get_cell(row, col)));
+                    }
+                }
+            }
+        }
+
+        for particle in animation.particles() {
+            let (row, col) = particle.position(t as f32);
+            self.draw_cell(row, col, 1.0 - (progress / 2.0),
+                self.theme.cell_color(particle.value));
+        }
+    }
+
+    /// Draw a block of the playfield scaled around its centre
+    fn draw_cell(&self, row: f32, col: f32, scale: f32, color: Color) {
+        let size = (self.block_size - 2.0) * scale;
+        let margin = (self.block_size - size) / 2.0;
+
+        draw_rectangle(
+            self.pf_x + (col * self.block_size) + margin,
+            self.pf_y + (row * self.block_size) + margin,
+            size,
+            size,
+            color,
+        );
+    }
+
+    /// Draw the held shape at the left side of the playfield
+    pub fn draw_hold(&self, shape: &Shape) {
+        let x = self.pf_x - (6.0 * self.block_size);
+        let y = self.pf_y + (2.0 * self.block_size);
+
+        draw_text("HOLD", x, y, self.block_size * 1.5, WHITE);
+        self.draw_shape_abs(shape, x, y + self.block_size, 0);
+    }
+
+    /// Draw the game over screen on top of the playfield: how the
+    /// game ended, a summary of the game and the available options
+    pub fn draw_game_over(&self, pf: &Playfield, reason: GameOver,
+                          summary: &[String]) {
+        draw_rectangle(
+            self.pf_x,
+            self.pf_y,
+            pf.n_cols() as f32 * self.block_size,
+            pf.n_rows() as f32 * self.block_size,
+            Color::new(0.0, 0.0, 0.0, 0.75),
+        );
+
+        let x = self.pf_x + self.block_size;
+        let mut y = self.pf_y + (5.0 * self.block_size);
+        let font_size = self.block_size * 1.5;
+
+        // Reaching the goal or time limit of the game mode isn't losing
+        let (title, color) = match reason {
+            GameOver::Goal | GameOver::TimeUp => ("FINISHED", GREEN),
+            _ => ("GAME OVER", RED),
+        };
+        draw_text(title, x, y, font_size * 1.5, color);
+        y += 1.5 * self.block_size;
+        draw_text(reason.name(), x, y, font_size, WHITE);
+
+        // Long summaries are squeezed into the space of six lines
+        y += 3.0 * self.block_size;
+        let line_height = (1.5 * self.block_size)
+            .min(9.0 * self.block_size / summary.len() as f32);
+        for line in summary {
+            draw_text(line, x, y, font_size.min(line_height), WHITE);
+            y += line_height;
+        }
+
+        y += 3.0 * self.block_size;
+        draw_text("ENTER: restart", x, y, font_size, LIGHTGRAY);
+        y += 1.5 * self.block_size;
+        draw_text("R: watch replay", x, y, font_size, LIGHTGRAY);
+        y += 1.5 * self.block_size;
+        draw_text("ESC: menu", x, y, font_size, LIGHTGRAY);
+    }
+
+    /// Draw a menu centered on the screen, on top of a dark
+    /// overlay, highlighting the item under the cursor
+    pub fn draw_menu(&self, menu: &Menu) {
+        draw_rectangle(0.0, 0.0, screen_width(), screen_height(),
+            Color::new(0.0, 0.0, 0.0, 0.75));
+
+        let (x, y, width, line_height) = self.menu_layout(menu);
+        let font_size = self.block_size * 1.5;
+
+        draw_text(menu.title(), x, y - (2.0 * line_height),
+            font_size * 1.5, BLUE);
+
+        for (i, item) in menu.items().iter().enumerate() {
+            let item_y = y + (i as f32 * line_height);
+            let color = if i == menu.cursor() { YELLOW } else { WHITE };
+
+            draw_text(item.label(), x, item_y, font_size, color);
+            if let Some(value) = item.value() {
+                let value = format!("< {} >", value);
+                let size = measure_text(&value, None, font_size as u16, 1.0);
+                draw_text(&value, x + width - size.width, item_y,
+                    font_size, color);
+            }
+        }
+    }
+
+    /// Draw the on-screen buttons of the touch controls, highlighting
+    /// the ones held down
+    pub fn draw_touch_buttons(&self, buttons: &[(Action, Rect)], input: &Input) {
+        let font_size = self.block_size;
+
+        for (action, rect) in buttons {
+            let color = if input.is_down(*action) {
+                Color::new(1.0, 1.0, 1.0, 0.5)
+            } else {
+                Color::new(1.0, 1.0, 1.0, 0.2)
+            };
+            draw_rectangle(rect.x, rect.y, rect.w, rect.h, color);
+
+            let label = action_label(*action);
+            let size = measure_text(label, None, font_size as u16, 1.0);
+            draw_text(label, rect.center().x - (size.width / 2.0),
+                rect.center().y + (size.height / 2.0), font_size, WHITE);
+        }
+    }
+
+    /// Draw the actions of the game at the right side of the
+    /// playfield, highlighting the ones pressed or held down
+    pub fn draw_input_overlay(&self, pf: &Playfield, input: &Input) {
+        let x = self.pf_x + ((pf.n_cols() + 1) as f32 * self.block_size);
+        let mut y = self.pf_y + (12.0 * self.block_size);
+        let size = 2.0 * self.block_size;
+
+        for action in Action::ALL.iter().filter(|a| **a != Action::Pause) {
+            let active = input.is_down(*action) || input.is_pressed(*action);
+            let color = if active { YELLOW } else { Color::new(1.0, 1.0, 1.0, 0.2) };
+
+            draw_rectangle_lines(x, y, size * 2.0, size * 0.8, 2.0, color);
+            let label = action_label(*action);
+            let text = measure_text(label, None, self.block_size as u16, 1.0);
+            draw_text(label, x + size - (text.width / 2.0), y + (size * 0.55),
+                self.block_size, color);
+
+            y += size;
+        }
+    }
+
+    /// Draw some lines of information at the right side of the
+    /// playfield
+    pub fn draw_info(&self, pf: &Playfield, lines: &[String]) {
+        let x = self.pf_x + ((pf.n_cols() + 1) as f32 * self.block_size);
+        let mut y = self.pf_y + (2.0 * self.block_size);
+
+        for line in lines {
+            draw_text(line, x, y, self.block_size * 1.2, WHITE);
+            y += 1.5 * self.block_size;
+        }
+    }
+
+    /// Draw the high score table of a game mode on top of a dark
+    /// overlay, highlighting the given entry
+    #[cfg(feature = "serde")]
+    pub fn draw_leaderboard(&self, mode: &str, entries: &[Score],
+                            highlight: Option<usize>) {
+        draw_rectangle(0.0, 0.0, screen_width(), screen_height(),
+            Color::new(0.0, 0.0, 0.0, 0.75));
+
+        let font_size = self.block_size * 1.2;
+        let width = 28.0 * self.block_size;
+        let line_height = 1.5 * self.block_size;
+        let x = (screen_width() - width) / 2.0;
+        let mut y = (screen_height() / 2.0) - (9.0 * line_height);
+
+        draw_text("LEADERBOARD", x, y, font_size * 1.8, BLUE);
+        y += 2.0 * line_height;
+        let title = format!("< {} >", mode);
+        draw_text(&title, x, y, font_size * 1.2, WHITE);
+        y += 2.0 * line_height;
+
+        // Columns: rank and name, score, lines, time, date. Tables
+        // ranked by time leave the score out.
+        let by_time = Ranking::of(mode) == Ranking::Time;
+        let offsets: &[f32] = if by_time {
+            &[0.0, 10.0, 14.0, 20.0]
+        } else {
+            &[0.0, 10.0, 16.0, 20.0, 25.0]
+        };
+        let columns: Vec<f32> = offsets.iter()
+            .map(|col| x + (col * self.block_size))
+            .collect();
+        let headers: &[&str] = if by_time {
+            &["NAME", "LINES", "TIME", "DATE"]
+        } else {
+            &["NAME", "SCORE", "LINES", "TIME", "DATE"]
+        };
+        for (header, col) in headers.iter().zip(columns.iter().copied()) {
+            draw_text(header, col, y, font_size, LIGHTGRAY);
+        }
+        y += line_height;
+
+        if entries.is_empty() {
+            draw_text("No scores yet", x, y, font_size, LIGHTGRAY);
+        }
+
+        for (i, entry) in entries.iter().enumerate() {
+            let color = if highlight == Some(i) { YELLOW } else { WHITE };
+            let mut cells = vec![
+                format!("{:>2}. {}", i + 1, entry.name),
+                entry.score.to_string(),
+                entry.lines.to_string(),
+                format_time(entry.time),
+                format_date(entry.date),
+            ];
+            if by_time {
+                cells.remove(1);
+            }
+            for (cell, col) in cells.iter().zip(columns.iter().copied()) {
+                draw_text(cell, col, y, font_size, color);
+            }
+            y += line_height;
+        }
+    }
+
+    /// Draw a name being typed (for a new high score or profile)
+    pub fn draw_name_entry(&self, title: &str, subtitle: &str, name: &str) {
+        draw_rectangle(0.0, 0.0, screen_width(), screen_height(),
+            Color::new(0.0, 0.0, 0.0, 0.75));
+
+        let font_size = self.block_size * 1.5;
+        let x = (screen_width() - (20.0 * self.block_size)) / 2.0;
+        let mut y = (screen_height() / 2.0) - (3.0 * self.block_size);
+
+        draw_text(title, x, y, font_size * 1.5, BLUE);
+        y += 2.5 * self.block_size;
+        draw_text(subtitle, x, y, font_size, WHITE);
+        y += 2.5 * self.block_size;
+        let line = format!("Name: {}_", name);
+        draw_text(&line, x, y, font_size, YELLOW);
+    }
+
+    /// Draw some lines of text below a menu
+    pub fn draw_menu_notes(&self, menu: &Menu, lines: &[String]) {
+        let (x, y, _, line_height) = self.menu_layout(menu);
+        let mut y = y + ((menu.items().len() as f32 + 0.5) * line_height);
+
+        for line in lines {
+            draw_text(line, x, y, self.block_size * 1.2, LIGHTGRAY);
+            y += 1.5 * self.block_size;
+        }
+    }
+
+    /// Draw a message at the bottom of the screen, on top of a menu
+    pub fn draw_prompt(&self, text: &str) {
+        let font_size = self.block_size * 1.2;
+        let size = measure_text(text, None, font_size as u16, 1.0);
+
+        draw_text(text, (screen_width() - size.width) / 2.0,
+            screen_height() - (2.0 * self.block_size), font_size, YELLOW);
+    }
+
+    /// The index of the menu item drawn at the given screen
+    /// position, if any
+    pub fn menu_item_at(&self, menu: &Menu, pos: Vec2) -> Option<usize> {
+        let (x, y, width, line_height) = self.menu_layout(menu);
+
+        if pos.x < x || pos.x > x + width {
+            return None;
+        }
+
+        // Text is drawn on its baseline: each item takes the line
+        // height above it, plus a small margin below
+        let top = y - (0.75 * line_height);
+        let index = ((pos.y - top) / line_height).floor();
+
+        if index >= 0.0 && (index as usize) < menu.items().len() {
+            Some(index as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Position of the first menu item (x, baseline y), width of the
+    /// items and line height
+    fn menu_layout(&self, menu: &Menu) -> (f32, f32, f32, f32) {
+        let width = 20.0 * self.block_size;
+        // Long menus are packed tighter, leaving room for the title
+        // and the notes below
+        let line_height = (2.0 * self.block_size)
+            .min(screen_height() / (menu.items().len() + 6) as f32);
+        let height = menu.items().len() as f32 * line_height;
+
+        let x = (screen_width() - width) / 2.0;
+        let y = ((screen_height() - height) / 2.0) + line_height;
+
+        (x, y, width, line_height)
+    }
+
+    /// Draw the texts announcing a line clear (spins, combos...) at
+    /// the left side of the playfield, below the hold box, one per line
+    pub fn draw_callouts(&self, callouts: &[String]) {
+        let x = self.pf_x - (8.0 * self.block_size);
+        let y = self.pf_y + (9.0 * self.block_size);
+
+        for (i, text) in callouts.iter().enumerate() {
+            draw_text(text, x, y + (i as f32 * 1.5 * self.block_size),
+                self.block_size * 1.2, YELLOW);
+        }
+    }
+
+    fn get_scaled_vars(pf: &Playfield, max_block_size: f32) -> (f32, f32, f32){
         // We'll use this to scale the game
-        let block_size = BLOCK_SIZE.min(screen_width() / 30.0)
+        let block_size = max_block_size.min(screen_width() / 30.0)
             .min(screen_height() / 30.0);
     
         let pf_x = (screen_width() / 2.0) 
@@ -132,4 +566,16 @@ impl Graphics {
         (block_size, pf_x, pf_y)
     
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_time;
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!("0:00.00", format_time(0.0));
+        assert_eq!("1:05.42", format_time(65.425));
+        assert_eq!("61:00.00", format_time(3660.0));
+    }
+}
@@ -0,0 +1,206 @@
+//! Shift handling: how held direction buttons move the shape
+//!
+//! The first press moves the shape right away. If the button is held
+//! for the Delayed Auto Shift (DAS) time, the shape starts moving
+//! automatically once every Auto Repeat Rate (ARR) period.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+
+/// Shift handling settings. Times are given in seconds. Missing
+/// settings take their default values.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Handling {
+    /// Delayed Auto Shift: time a direction must be held before
+    /// the shape starts moving automatically
+    pub das: f64,
+    /// Auto Repeat Rate: time between two automatic moves. 0 moves
+    /// the shape straight to the wall.
+    pub arr: f64,
+    /// Soft drop factor: soft drops are this many times faster than
+    /// gravity. `f64::INFINITY` moves the shape straight to the
+    /// floor (without fixing it).
+    pub sdf: f64,
+    /// Keep the DAS charge of a held direction when a new shape
+    /// spawns, so it can start moving automatically right away
+    pub preserve_das: bool,
+    /// DAS cut delay: when a new shape spawns, the DAS charge is
+    /// cut so it waits at least this time before moving
+    /// automatically (0 disables it)
+    pub das_cut: f64,
+}
+
+impl Default for Handling {
+    fn default() -> Self {
+        Self {
+            das: 0.1,
+            arr: 0.1,
+            sdf: 16.0,
+            preserve_das: true,
+            das_cut: 0.0,
+        }
+    }
+}
+
+impl Handling {
+
+    /// Update the DAS charge of a held direction when a new shape
+    /// spawns, according to `preserve_das` and `das_cut`
+    pub fn on_spawn(&self, repeat: &mut AutoRepeat) {
+        let charge = if self.preserve_das {
+            repeat.charge.min(self.das - self.das_cut).max(0.0)
+        } else {
+            0.0
+        };
+        repeat.recharge(charge);
+    }
+}
+
+
+/// Number of moves meaning "as many as possible"
+pub const INSTANT: usize = usize::MAX;
+
+/// Auto repeat state of a single button
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct AutoRepeat {
+    held: bool,
+    // Time the button has been held
+    charge: f64,
+    // Automatic moves done since the delay was reached
+    moves: usize,
+}
+
+impl AutoRepeat {
+
+    /// Update the state of the button.
+    ///
+    /// # Arguments
+    /// * `down` - True if the button is held down
+    /// * `dt` - Time elapsed since the last update
+    /// * `delay` - Time the button must be held before auto repeat
+    /// * `rate` - Time between automatic moves (0 for instant moves)
+    ///
+    /// # Returns
+    /// The number of moves to perform (`INSTANT` for as many
+    /// as possible)
+    pub fn update(&mut self, down: bool, dt: f64,
+                  delay: f64, rate: f64) -> usize {
+
+        if !down {
+            *self = Self::default();
+            return 0;
+        }
+
+        if !self.held {
+            // First press
+            self.held = true;
+            return 1;
+        }
+
+        self.charge += dt;
+        if self.charge < delay {
+            return 0;
+        }
+
+        if rate <= 0.0 {
+            return INSTANT;
+        }
+
+        // The first automatic move happens when the delay is reached
+        let due = ((self.charge - delay) / rate).floor() as usize + 1;
+        let moves = due - self.moves;
+        self.moves = due;
+
+        moves
+    }
+
+    pub fn held(&self) -> bool {
+        self.held
+    }
+
+    pub fn charge(&self) -> f64 {
+        self.charge
+    }
+
+    /// Set the charge of a held button, restarting auto repeat
+    fn recharge(&mut self, charge: f64) {
+        self.charge = charge;
+        self.moves = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AutoRepeat, Handling, INSTANT};
+
+    #[test]
+    fn test_auto_repeat() {
+        let mut repeat = AutoRepeat::default();
+
+        assert_eq!(0, repeat.update(false, 0.1, 0.2, 0.05));
+        // First press
+        assert_eq!(1, repeat.update(true, 0.1, 0.2, 0.05));
+        // Charging
+        assert_eq!(0, repeat.update(true, 0.1, 0.2, 0.05));
+        assert_eq!(0, repeat.update(true, 0.05, 0.2, 0.05));
+        // Delay reached
+        assert_eq!(1, repeat.update(true, 0.05, 0.2, 0.05));
+        // Repeat: 2 periods in a single update
+        assert_eq!(2, repeat.update(true, 0.1, 0.2, 0.05));
+        // Release
+        assert_eq!(0, repeat.update(false, 0.1, 0.2, 0.05));
+        assert!(!repeat.held());
+        assert_eq!(1, repeat.update(true, 0.1, 0.2, 0.05));
+    }
+
+    #[test]
+    fn test_auto_repeat_instant() {
+        let mut repeat = AutoRepeat::default();
+
+        assert_eq!(1, repeat.update(true, 0.0, 0.1, 0.0));
+        assert_eq!(0, repeat.update(true, 0.05, 0.1, 0.0));
+        assert_eq!(INSTANT, repeat.update(true, 0.05, 0.1, 0.0));
+        assert_eq!(INSTANT, repeat.update(true, 0.05, 0.1, 0.0));
+    }
+
+    #[test]
+    fn test_das_on_spawn() {
+        let charged = || {
+            let mut repeat = AutoRepeat::default();
+            repeat.update(true, 0.0, 0.1, 0.05);
+            repeat.update(true, 0.5, 0.1, 0.05);
+            repeat
+        };
+
+        let handling = Handling { das: 0.1, ..Default::default() };
+        let mut repeat = charged();
+        handling.on_spawn(&mut repeat);
+        assert_eq!(0.1, repeat.charge());
+        // Moves right away
+        assert_eq!(1, repeat.update(true, 0.0, 0.1, 0.05));
+
+        let handling = Handling {
+            das: 0.1,
+            preserve_das: false,
+            ..Default::default()
+        };
+        let mut repeat = charged();
+        handling.on_spawn(&mut repeat);
+        assert_eq!(0.0, repeat.charge());
+        assert!(repeat.held());
+        assert_eq!(0, repeat.update(true, 0.05, 0.1, 0.05));
+
+        let handling = Handling {
+            das: 0.1,
+            das_cut: 0.05,
+            ..Default::default()
+        };
+        let mut repeat = charged();
+        handling.on_spawn(&mut repeat);
+        assert_eq!(0.05, repeat.charge());
+        assert_eq!(0, repeat.update(true, 0.0, 0.1, 0.05));
+    }
+}
@@ -0,0 +1,424 @@
+//! Input layer: maps physical inputs to game actions
+//!
+//! Keys are bound to actions through a binding table that players
+//! can edit. An action can have several keys, but a key only
+//! triggers one action. Every frame, the state of the bound keys is
+//! polled into an `Input`, which is what the game consumes.
+
+use std::collections::BTreeMap;
+
+use macroquad::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::game::Held;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    HardDrop,
+    RotateCw,
+    RotateCcw,
+    Rotate180,
+    Hold,
+    Pause,
+}
+
+impl Action {
+
+    pub const ALL: [Action; 9] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::SoftDrop,
+        Action::HardDrop,
+        Action::RotateCw,
+        Action::RotateCcw,
+        Action::Rotate180,
+        Action::Hold,
+        Action::Pause,
+    ];
+
+    pub fn name(&self) -> &str {
+        match self {
+            Action::MoveLeft => "Move left",
+            Action::MoveRight => "Move right",
+            Action::SoftDrop => "Soft drop",
+            Action::HardDrop => "Hard drop",
+            Action::RotateCw => "Rotate CW",
+            Action::RotateCcw => "Rotate CCW",
+            Action::Rotate180 => "Rotate 180",
+            Action::Hold => "Hold",
+            Action::Pause => "Pause",
+        }
+    }
+}
+
+/// Every key that can be bound
+const KEYS: [KeyCode; 121] = [
+    KeyCode::Space, KeyCode::Apostrophe, KeyCode::Comma, KeyCode::Minus,
+    KeyCode::Period, KeyCode::Slash, KeyCode::Key0, KeyCode::Key1,
+    KeyCode::Key2, KeyCode::Key3, KeyCode::Key4, KeyCode::Key5,
+    KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
+    KeyCode::Semicolon, KeyCode::Equal, KeyCode::A, KeyCode::B, KeyCode::C,
+    KeyCode::D, KeyCode::E, KeyCode::F, KeyCode::G, KeyCode::H, KeyCode::I,
+    KeyCode::J, KeyCode::K, KeyCode::L, KeyCode::M, KeyCode::N, KeyCode::O,
+    KeyCode::P, KeyCode::Q, KeyCode::R, KeyCode::S, KeyCode::T, KeyCode::U,
+    KeyCode::V, KeyCode::W, KeyCode::X, KeyCode::Y, KeyCode::Z,
+    KeyCode::LeftBracket, KeyCode::Backslash, KeyCode::RightBracket,
+    KeyCode::GraveAccent, KeyCode::World1, KeyCode::World2, KeyCode::Escape,
+    KeyCode::Enter, KeyCode::Tab, KeyCode::Backspace, KeyCode::Insert,
+    KeyCode::Delete, KeyCode::Right, KeyCode::Left, KeyCode::Down,
+    KeyCode::Up, KeyCode::PageUp, KeyCode::PageDown, KeyCode::Home,
+    KeyCode::End, KeyCode::CapsLock, KeyCode::ScrollLock, KeyCode::NumLock,
+    KeyCode::PrintScreen, KeyCode::Pause, KeyCode::F1, KeyCode::F2,
+    KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6, KeyCode::F7,
+    KeyCode::F8, KeyCode::F9, KeyCode::F10, KeyCode::F11, KeyCode::F12,
+    KeyCode::F13, KeyCode::F14, KeyCode::F15, KeyCode::F16, KeyCode::F17,
+    KeyCode::F18, KeyCode::F19, KeyCode::F20, KeyCode::F21, KeyCode::F22,
+    KeyCode::F23, KeyCode::F24, KeyCode::F25, KeyCode::Kp0, KeyCode::Kp1,
+    KeyCode::Kp2, KeyCode::Kp3, KeyCode::Kp4, KeyCode::Kp5, KeyCode::Kp6,
+    KeyCode::Kp7, KeyCode::Kp8, KeyCode::Kp9, KeyCode::KpDecimal,
+    KeyCode::KpDivide, KeyCode::KpMultiply, KeyCode::KpSubtract,
+    KeyCode::KpAdd, KeyCode::KpEnter, KeyCode::KpEqual, KeyCode::LeftShift,
+    KeyCode::LeftControl, KeyCode::LeftAlt, KeyCode::LeftSuper,
+    KeyCode::RightShift, KeyCode::RightControl, KeyCode::RightAlt,
+    KeyCode::RightSuper, KeyCode::Menu, KeyCode::Back,
+];
+
+/// Name of a key (e.g. "LeftShift")
+pub fn key_name(key: KeyCode) -> String {
+    format!("{:?}", key)
+}
+
+/// The key with the given name, if there's one
+pub fn key_from_name(name: &str) -> Option<KeyCode> {
+    KEYS.into_iter().find(|key| key_name(*key) == name)
+}
+
+/// Keys bound to each action
+///
+/// Saved as the key names bound to every action (e.g.
+/// `Hold = ["C", "LeftShift"]`). Unknown actions and keys are
+/// ignored, and actions missing from the table get their default
+/// keys, unless they're already bound to other actions.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "BTreeMap<String, Vec<String>>",
+    into = "BTreeMap<String, Vec<String>>"))]
+pub struct Bindings {
+    // Indexed by action
+    keys: Vec<Vec<KeyCode>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let mut bindings = Self::empty();
+
+        bindings.bind(Action::MoveLeft, KeyCode::Left);
+        bindings.bind(Action::MoveRight, KeyCode::Right);
+        bindings.bind(Action::SoftDrop, KeyCode::Down);
+        bindings.bind(Action::HardDrop, KeyCode::Space);
+        bindings.bind(Action::RotateCw, KeyCode::D);
+        bindings.bind(Action::RotateCw, KeyCode::Up);
+        bindings.bind(Action::RotateCcw, KeyCode::S);
+        bindings.bind(Action::RotateCcw, KeyCode::Z);
+        bindings.bind(Action::Rotate180, KeyCode::A);
+        bindings.bind(Action::Hold, KeyCode::C);
+        bindings.bind(Action::Hold, KeyCode::LeftShift);
+        bindings.bind(Action::Pause, KeyCode::Escape);
+        bindings.bind(Action::Pause, KeyCode::P);
+
+        bindings
+    }
+}
+
+impl Bindings {
+
+    /// A table without any binding
+    pub fn empty() -> Self {
+        Self {
+            keys: vec![Vec::new(); Action::ALL.len()],
+        }
+    }
+
+    pub fn keys(&self, action: Action) -> &[KeyCode] {
+        &self.keys[action as usize]
+    }
+
+    /// The action the given key is bound to, if any
+    pub fn action(&self, key: KeyCode) -> Option<Action> {
+        Action::ALL.into_iter()
+            .find(|action| self.keys(*action).contains(&key))
+    }
+
+    /// Bind a key to an action, removing it from any other action
+    pub fn bind(&mut self, action: Action, key: KeyCode) {
+        for keys in self.keys.iter_mut() {
+            keys.retain(|k| *k != key);
+        }
+        self.keys[action as usize].push(key);
+    }
+
+    pub fn unbind(&mut self, action: Action, key: KeyCode) {
+        self.keys[action as usize].retain(|k| *k != key);
+    }
+
+    /// Bind the key to the action, or unbind it if it already was
+    pub fn toggle(&mut self, action: Action, key: KeyCode) {
+        if self.keys(action).contains(&key) {
+            self.unbind(action, key);
+        } else {
+            self.bind(action, key);
+        }
+    }
+
+    /// Names of the keys bound to an action (e.g. "Up, D")
+    pub fn describe(&self, action: Action) -> String {
+        let keys = self.keys(action);
+        if keys.is_empty() {
+            return "-".to_string();
+        }
+
+        keys.iter()
+            .map(|key| key_name(*key))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+impl From<BTreeMap<String, Vec<String>>> for Bindings {
+    fn from(table: BTreeMap<String, Vec<String>>) -> Self {
+        let mut bindings = Self::empty();
+        let defaults = Self::default();
+
+        for action in Action::ALL {
+            match table.get(&format!("{:?}", action)) {
+                Some(names) => names.iter()
+                    .filter_map(|name| key_from_name(name))
+                    .for_each(|key| bindings.bind(action, key)),
+                None => defaults.keys(action).iter()
+                    .filter(|key| !table.values().flatten()
+                        .any(|name| *name == key_name(**key)))
+                    .for_each(|key| bindings.bind(action, *key)),
+            }
+        }
+
+        bindings
+    }
+}
+
+impl From<Bindings> for BTreeMap<String, Vec<String>> {
+    fn from(bindings: Bindings) -> Self {
+        Action::ALL.iter()
+            .map(|action| (format!("{:?}", action),
+                bindings.keys(*action).iter().map(|key| key_name(*key)).collect()))
+            .collect()
+    }
+}
+
+/// Actions triggered during a frame
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Input {
+    // Actions whose input started this frame
+    pressed: Vec<Action>,
+    // Actions whose input is held down
+    down: Vec<Action>,
+    // Columns and rows moved directly (e.g. by touch gestures)
+    shift: isize,
+    drops: usize,
+}
+
+impl Input {
+
+    /// Read the state of the bound keys
+    pub fn poll(bindings: &Bindings) -> Self {
+        let mut input = Self::default();
+
+        for action in Action::ALL {
+            let keys = bindings.keys(action);
+            if keys.iter().any(|key| is_key_pressed(*key)) {
+                input.pressed.push(action);
+            }
+            if keys.iter().any(|key| is_key_down(*key)) {
+                input.down.push(action);
+            }
+        }
+
+        input
+    }
+
+    /// Trigger an action from another source (e.g. a touch screen)
+    pub fn press(&mut self, action: Action) {
+        if !self.pressed.contains(&action) {
+            self.pressed.push(action);
+        }
+    }
+
+    /// Keep an action held down from another source
+    pub fn hold(&mut self, action: Action) {
+        if !self.down.contains(&action) {
+            self.down.push(action);
+        }
+    }
+
+    /// Stop holding an action down
+    pub fn release(&mut self, action: Action) {
+        self.down.retain(|a| *a != action);
+    }
+
+    /// Move the piece the given columns (negative to the left)
+    /// without waiting for auto repeat
+    pub fn add_shift(&mut self, cols: isize) {
+        self.shift += cols;
+    }
+
+    /// Soft drop the piece the given rows
+    pub fn add_drops(&mut self, rows: usize) {
+        self.drops += rows;
+    }
+
+    /// Actions pressed this frame
+    pub fn pressed(&self) -> &[Action] {
+        &self.pressed
+    }
+
+    /// Actions held down
+    pub fn down(&self) -> &[Action] {
+        &self.down
+    }
+
+    pub fn shift(&self) -> isize {
+        self.shift
+    }
+
+    pub fn drops(&self) -> usize {
+        self.drops
+    }
+
+    /// Add the input of a newer frame that wasn't consumed yet:
+    /// presses and moves add up, and held actions are replaced
+    pub fn merge(&mut self, newer: &Input) {
+        for action in &newer.pressed {
+            self.press(*action);
+        }
+        self.down = newer.down.clone();
+        self.shift += newer.shift;
+        self.drops += newer.drops;
+    }
+
+    /// Forget what was consumed by a game tick, keeping the actions
+    /// held down
+    pub fn consume(&mut self) {
+        self.pressed.clear();
+        self.shift = 0;
+        self.drops = 0;
+    }
+
+    pub fn is_pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn is_down(&self, action: Action) -> bool {
+        self.down.contains(&action)
+    }
+
+    /// Held buttons, as used by the game
+    pub fn held(&self) -> Held {
+        Held {
+            left: self.is_down(Action::MoveLeft),
+            right: self.is_down(Action::MoveRight),
+            soft_drop: self.is_down(Action::SoftDrop),
+            rotate_cw: self.is_down(Action::RotateCw),
+            rotate_ccw: self.is_down(Action::RotateCcw),
+            rotate_180: self.is_down(Action::Rotate180),
+            hold: self.is_down(Action::Hold),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::input::KeyCode;
+
+    use std::collections::BTreeMap;
+
+    use super::{key_from_name, Action, Bindings, Input};
+
+    #[test]
+    fn test_bindings() {
+        let mut bindings = Bindings::default();
+        assert_eq!(Some(Action::RotateCw), bindings.action(KeyCode::Up));
+        assert_eq!(None, bindings.action(KeyCode::Q));
+        assert_eq!("D, Up", bindings.describe(Action::RotateCw));
+
+        // A key only triggers one action
+        bindings.bind(Action::Hold, KeyCode::Up);
+        assert_eq!(Some(Action::Hold), bindings.action(KeyCode::Up));
+        assert_eq!(vec![KeyCode::D], bindings.keys(Action::RotateCw));
+
+        bindings.toggle(Action::Hold, KeyCode::Up);
+        assert_eq!(None, bindings.action(KeyCode::Up));
+        bindings.toggle(Action::Hold, KeyCode::Q);
+        assert_eq!(Some(Action::Hold), bindings.action(KeyCode::Q));
+
+        bindings.unbind(Action::Pause, KeyCode::P);
+        bindings.unbind(Action::Pause, KeyCode::Escape);
+        assert_eq!("-", bindings.describe(Action::Pause));
+    }
+
+    #[test]
+    fn test_bindings_serde() {
+        let mut bindings = Bindings::default();
+        bindings.bind(Action::Hold, KeyCode::Kp0);
+        let table = BTreeMap::from(bindings.clone());
+        assert_eq!(vec!["C", "LeftShift", "Kp0"], table["Hold"]);
+        assert_eq!(bindings, Bindings::from(table.clone()));
+
+        // Unknown keys are ignored, missing actions get their default
+        // keys if they're free
+        let mut table = table;
+        table.insert("Hold".to_string(), vec!["Nope".to_string(), "P".to_string()]);
+        table.remove("Pause");
+        let bindings = Bindings::from(table);
+        assert_eq!(vec![KeyCode::P], bindings.keys(Action::Hold));
+        assert_eq!(vec![KeyCode::Escape], bindings.keys(Action::Pause));
+
+        assert_eq!(Some(KeyCode::PageDown), key_from_name("PageDown"));
+        assert_eq!(None, key_from_name("Unknown"));
+    }
+
+    #[test]
+    fn test_input() {
+        let mut input = Input::default();
+        input.press(Action::Hold);
+        input.hold(Action::MoveLeft);
+        input.hold(Action::MoveLeft);
+
+        assert_eq!(vec![Action::Hold], input.pressed());
+        assert!(input.is_pressed(Action::Hold));
+        assert!(!input.is_down(Action::Hold));
+
+        let held = input.held();
+        assert!(held.left);
+        assert!(!held.right && !held.hold);
+
+        let mut newer = Input::default();
+        newer.press(Action::RotateCw);
+        newer.add_shift(-2);
+        input.add_shift(1);
+        input.merge(&newer);
+        assert_eq!(vec![Action::Hold, Action::RotateCw], input.pressed());
+        assert!(input.down().is_empty());
+        assert_eq!(-1, input.shift());
+
+        input.hold(Action::SoftDrop);
+        input.consume();
+        assert!(input.pressed().is_empty());
+        assert_eq!(0, input.shift());
+        assert!(input.is_down(Action::SoftDrop));
+    }
+}
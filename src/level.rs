@@ -0,0 +1,142 @@
+//! Level systems: how the level advances with the lines cleared and
+//! how fast pieces fall at every level
+//!
+//! * Guideline: games start at levels 1 to 15 and the level goes up
+//!   every 10 lines. Pieces fall following the Guideline curve.
+//! * NES: games start at levels 0 to 19 and the first level up
+//!   depends on the starting level, as in the original game: it
+//!   happens after `min(start * 10 + 10, max(100, start * 10 - 50))`
+//!   lines, and then every 10 lines. Pieces fall at the speed of the
+//!   original game (frames per row at 60 Hz).
+
+use std::ops::RangeInclusive;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::game::TICK;
+
+
+/// Lines to clear to advance to the next level
+const LINES_PER_LEVEL: usize = 10;
+
+/// Frames per row at NES levels 0 to 29 (level 29 and above, the
+/// "kill screen", drop a row every frame)
+const NES_FRAMES_PER_ROW: [usize; 30] = [
+    48, 43, 38, 33, 28, 23, 18, 13, 8, 6,
+    5, 5, 5, 4, 4, 4, 3, 3, 3, 2,
+    2, 2, 2, 2, 2, 2, 2, 2, 2, 1,
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum LevelSystem {
+    #[default]
+    Guideline,
+    Nes,
+}
+
+impl LevelSystem {
+
+    pub const ALL: [LevelSystem; 2] = [LevelSystem::Guideline, LevelSystem::Nes];
+
+    pub fn name(&self) -> &str {
+        match self {
+            LevelSystem::Guideline => "Guideline",
+            LevelSystem::Nes => "NES",
+        }
+    }
+
+    /// Levels a game can start at
+    pub fn start_levels(&self) -> RangeInclusive<usize> {
+        match self {
+            LevelSystem::Guideline => 1..=15,
+            LevelSystem::Nes => 0..=19,
+        }
+    }
+
+    /// Level reached after clearing some lines
+    ///
+    /// # Arguments
+    /// * `start` - Starting level
+    /// * `lines` - Lines cleared since the game started
+    pub fn level(&self, start: usize, lines: usize) -> usize {
+        match self {
+            LevelSystem::Guideline => start + lines / LINES_PER_LEVEL,
+            LevelSystem::Nes => {
+                let first = (start * 10 + 10)
+                    .min(100.max((start * 10).saturating_sub(50)));
+                if lines < first {
+                    start
+                } else {
+                    start + 1 + (lines - first) / LINES_PER_LEVEL
+                }
+            }
+        }
+    }
+
+    /// Time (in seconds) between two gravity drops at a level
+    pub fn drop_delay(&self, level: usize) -> f64 {
+        match self {
+            LevelSystem::Guideline => {
+                // 1 second at level 1
+                let level = level.max(1) as f64;
+                (0.8 - ((level - 1.0) * 0.007)).powf(level - 1.0)
+            }
+            LevelSystem::Nes => {
+                let frames = NES_FRAMES_PER_ROW[level.min(NES_FRAMES_PER_ROW.len() - 1)];
+                frames as f64 * TICK
+            }
+        }
+    }
+
+    /// Level line clears are scored at (NES levels start at 0, so
+    /// points are multiplied by the level plus one, as in the NES)
+    pub fn score_level(&self, level: usize) -> usize {
+        match self {
+            LevelSystem::Guideline => level.max(1),
+            LevelSystem::Nes => level + 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LevelSystem;
+
+    #[test]
+    fn test_guideline_levels() {
+        let levels = LevelSystem::Guideline;
+        assert_eq!(1, levels.level(1, 9));
+        assert_eq!(2, levels.level(1, 10));
+        assert_eq!(15, levels.level(1, 149));
+        assert_eq!(8, levels.level(5, 30));
+        assert_eq!(1.0, levels.drop_delay(1));
+        assert!((levels.drop_delay(2) - 0.793).abs() < 1e-9);
+        assert!(levels.drop_delay(15) < levels.drop_delay(14));
+    }
+
+    #[test]
+    fn test_nes_levels() {
+        let levels = LevelSystem::Nes;
+        // Level 0: every 10 lines
+        assert_eq!(0, levels.level(0, 9));
+        assert_eq!(1, levels.level(0, 10));
+        assert_eq!(3, levels.level(0, 35));
+        // Levels 9 to 15: first level up after 100 lines
+        assert_eq!(9, levels.level(9, 99));
+        assert_eq!(10, levels.level(9, 100));
+        assert_eq!(15, levels.level(15, 99));
+        assert_eq!(16, levels.level(15, 100));
+        // Levels 16 to 19: 60 lines earlier than 10 lines per level
+        assert_eq!(18, levels.level(18, 129));
+        assert_eq!(19, levels.level(18, 130));
+        assert_eq!(20, levels.level(19, 140));
+        assert_eq!(21, levels.level(19, 150));
+
+        assert_eq!(48.0 / 60.0, levels.drop_delay(0));
+        assert_eq!(2.0 / 60.0, levels.drop_delay(19));
+        assert_eq!(1.0 / 60.0, levels.drop_delay(40));
+        assert_eq!(1, levels.score_level(0));
+    }
+}
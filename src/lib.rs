@@ -1,9 +1,34 @@
 
+pub mod clear;
 pub mod playfield;
 pub mod shape;
 pub mod rsg;
 pub mod graphics;
 pub mod demo;
+pub mod definition;
+pub mod piece;
+pub mod polyomino;
+pub mod ruleset;
+pub mod handling;
+pub mod menu;
+pub mod animation;
+pub mod spin;
+pub mod game;
+pub mod input;
+pub mod touch;
+#[cfg(feature = "serde")]
+pub mod replay;
+#[cfg(feature = "serde")]
+pub mod viewer;
+pub mod storage;
+#[cfg(feature = "serde")]
+pub mod scores;
+#[cfg(feature = "serde")]
+pub mod settings;
+#[cfg(feature = "serde")]
+pub mod savegame;
+pub mod level;
+pub mod sprint;
 
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
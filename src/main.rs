@@ -3,245 +3,1411 @@
 
 use macroquad::prelude::*;
 
+use rusty_blocks::animation::{ClearAnimation, ClearStyle};
+use rusty_blocks::definition::RotationDefinition;
 use rusty_blocks::demo;
-use rusty_blocks::graphics::Graphics;
-use rusty_blocks::playfield::Playfield;
-use rusty_blocks::shape::RotationSystem;
-use rusty_blocks::shape::Shape;
-use rusty_blocks::shape::ShapeFactory;
+use rusty_blocks::game::{Game, GameOver, Phase, SPLIT_LINES, TICK};
+use rusty_blocks::graphics::{format_time, Graphics, Theme};
+use rusty_blocks::handling::Handling;
+use rusty_blocks::input::{Action, Bindings, Input};
+use rusty_blocks::level::LevelSystem;
+use rusty_blocks::menu::{Menu, MenuEvent, MenuItem};
+use rusty_blocks::polyomino::PieceSet;
+use rusty_blocks::replay::{Recorder, Replay};
+use rusty_blocks::rsg::Randomizer;
+use rusty_blocks::savegame::SavedGame;
+use rusty_blocks::scores::{HighScores, Score, MAX_NAME_LENGTH};
+use rusty_blocks::settings::{Profiles, Settings};
+use rusty_blocks::ruleset::Ruleset;
+use rusty_blocks::shape::{RotationSystem, ShapeBuilder};
+use rusty_blocks::spin::SpinRule;
+use rusty_blocks::sprint::{self, PersonalBests};
+use rusty_blocks::touch::{self, GestureRecognizer, GestureSettings, TouchLayout};
+use rusty_blocks::viewer::ReplayViewer;
 
 
-const SHIFT_DELAY: f64 = 0.1;
-const QUICK_DROP_DELAY: f64 = 0.05;
+/// Game modes
+const MODES: [&str; 3] = ["Marathon", "Sprint", "Ultra"];
+const MARATHON: usize = 0;
+const SPRINT: usize = 1;
+const ULTRA: usize = 2;
 
+/// Rules games are played with, named after their `Ruleset`
+const RULESETS: [&str; 3] = ["Modern", "Classic", "Arcade"];
 
-fn rotate_clockwise(pf: &Playfield, shape: &Shape, 
-                    cs_row: usize, cs_col: isize, rot: isize) -> isize {
-    
-    if pf.collides(shape, cs_row, cs_col as isize, (rot + 1) % 4) {
-        rot
-    } else {
-        (rot + 1) % 4
+/// Line goals of Marathon games (`None` is endless)
+const MARATHON_GOALS: [Option<usize>; 3] = [Some(150), Some(200), None];
+
+/// Line goals of Sprint games
+const SPRINT_GOALS: [Option<usize>; 3] = [Some(40), Some(20), Some(100)];
+
+/// Time limits of Ultra games, in seconds
+const ULTRA_LIMITS: [f64; 2] = [120.0, 180.0];
+
+/// Split times shown while playing Sprint
+const MAX_SPLITS: usize = 6;
+
+/// Time line clears and spins are announced, in seconds
+const CALLOUT_TIME: f64 = 1.5;
+
+
+/// Most game ticks run in a single frame, so slow frames don't make
+/// the game fall further behind
+const MAX_TICKS_PER_FRAME: usize = 10;
+
+/// Directory where the replays of finished games are saved
+const REPLAY_DIR: &str = "replays";
+
+// Handling settings (seconds, except for the soft drop factor)
+const DAS_OPTIONS: [f64; 7] = [0.05, 0.083, 0.1, 0.133, 0.167, 0.2, 0.25];
+const ARR_OPTIONS: [f64; 5] = [0.0, 0.017, 0.033, 0.05, 0.1];
+const SDF_OPTIONS: [f64; 6] = [2.0, 4.0, 8.0, 16.0, 32.0, f64::INFINITY];
+
+const VOLUME_OPTIONS: [f32; 11] = [0.0, 0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9, 1.0];
+
+/// Touch drag distances, in blocks
+const DRAG_OPTIONS: [f32; 5] = [0.5, 1.0, 1.5, 2.0, 3.0];
+
+/// Largest block sizes, in pixels
+const BLOCK_SIZE_OPTIONS: [f32; 5] = [16.0, 20.0, 24.0, 28.0, 32.0];
+
+// Main menu items
+const MODE: usize = 0;
+const RULES: usize = 1;
+const SPINS: usize = 2;
+const ROTATION: usize = 3;
+const RANDOMIZER: usize = 4;
+const LEVEL: usize = 5;
+const GOAL: usize = 6;
+const PROFILES: usize = 7;
+const LEADERBOARD: usize = 8;
+const SETTINGS: usize = 9;
+const START: usize = 10;
+const RESUME_GAME: usize = 11;
+
+// Settings menu items
+const DAS: usize = 0;
+const ARR: usize = 1;
+const SDF: usize = 2;
+const CLEAR: usize = 3;
+const TOUCH: usize = 4;
+const DRAG: usize = 5;
+const THEME: usize = 6;
+const VOLUME: usize = 7;
+const BLOCK_SIZE: usize = 8;
+const CONTROLS: usize = 9;
+const BACK: usize = 10;
+
+// Controls menu items (one per action first)
+const RESET_CONTROLS: usize = Action::ALL.len();
+const CONTROLS_BACK: usize = RESET_CONTROLS + 1;
+
+// Profiles menu items (one per profile first)
+const NEW_PROFILE: usize = 0;
+const DELETE_PROFILE: usize = 1;
+const PROFILES_BACK: usize = 2;
+
+// Pause menu items
+const RESUME: usize = 0;
+const RESTART: usize = 1;
+const SUSPEND: usize = 2;
+const QUIT: usize = 3;
+
+
+/// Screens of the application
+#[derive(Clone, Copy, PartialEq)]
+enum Screen {
+    MainMenu,
+    Settings,
+    Controls,
+    Playing,
+    Paused,
+    Replay,
+    Leaderboard,
+    NameEntry,
+    Profiles,
+    ProfileName,
+}
+
+
+/// Command line arguments: an optional rotation system definition
+/// file (e.g. assets/rotation-systems/ars.toml) and an optional
+/// replay to play (`--replay <file>`)
+struct Args {
+    definition: Option<String>,
+    replay: Option<String>,
+}
+
+fn args() -> Args {
+    let mut args = Args { definition: None, replay: None };
+    let mut iter = std::env::args().skip(1);
+
+    while let Some(arg) = iter.next() {
+        if arg == "--replay" {
+            args.replay = iter.next();
+        } else {
+            args.definition = Some(arg);
+        }
     }
+
+    args
 }
 
-fn rotate_counter_cw(pf: &Playfield, shape:  &Shape, 
-                     cs_row: usize, cs_col: isize, rot: isize) -> isize{
-    
-    let new_rot: isize;
-    if rot == 0 {
-        new_rot = 3;
-    } else {
-        new_rot = (rot - 1) % 4;
+/// Use the rotation system definition file given as argument, if any
+async fn custom_rotation_system(path: Option<String>) -> Option<RotationSystem> {
+    let path = path?;
+
+    match RotationDefinition::load(&path).await {
+        Ok(definition) => Some(RotationSystem::CUSTOM(definition)),
+        Err(e) => {
+            println!("{}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// All the rotation systems that can be chosen from the menu:
+/// the built-in ones, the loaded definition (if any) and the
+/// polyomino piece sets
+fn rotation_systems(custom: Option<RotationSystem>) -> Vec<RotationSystem> {
+    let mut systems = vec![
+        RotationSystem::SRS,
+        RotationSystem::NES,
+        RotationSystem::GB,
+        RotationSystem::SEGA,
+    ];
+    systems.extend(custom);
+    systems.extend(PieceSet::ALL.iter()
+        .map(|set| RotationSystem::CUSTOM(set.definition())));
+
+    systems
+}
+
+/// Index of the option closest to a value
+fn nearest(options: &[f64], value: f64) -> usize {
+    options.iter().position(|option| *option == value)
+        .or_else(|| (0..options.len()).min_by(|a, b|
+            (options[*a] - value).abs().total_cmp(&(options[*b] - value).abs())))
+        .unwrap_or(0)
+}
+
+fn main_menu(systems: &[RotationSystem], rotation: usize) -> Menu {
+    Menu::new("RUSTY BLOCKS", vec![
+        MenuItem::choice("Mode",
+            MODES.iter().map(|name| name.to_string()).collect(), 0),
+        MenuItem::choice("Rules",
+            RULESETS.iter().map(|name| name.to_string()).collect(), 0),
+        MenuItem::choice("Spins",
+            SpinRule::ALL.iter().map(|r| r.name().to_string()).collect(), 0),
+        MenuItem::choice("Rotation",
+            systems.iter().map(|s| s.name().to_string()).collect(), rotation),
+        MenuItem::choice("Randomizer",
+            Randomizer::ALL.iter().map(|r| r.name().to_string()).collect(), 0),
+        MenuItem::choice("Level", level_options(Ruleset::modern().levels), 0),
+        MenuItem::choice("Goal", goal_options(MARATHON), 0),
+        MenuItem::action("Profiles"),
+        MenuItem::action("Leaderboard"),
+        MenuItem::action("Settings"),
+        MenuItem::action("Start"),
+        MenuItem::action("Resume saved game"),
+    ])
+}
+
+/// Settings menu showing the settings of a profile
+fn settings_menu(settings: &Settings) -> Menu {
+    let millis = |options: &[f64]| options.iter()
+        .map(|s| format!("{} ms", (s * 1000.0).round()))
+        .collect::<Vec<String>>();
+    let factors = SDF_OPTIONS.iter()
+        .map(|f| if f.is_finite() { format!("{}x", f) } else { "Instant".into() })
+        .collect::<Vec<String>>();
+
+    let handling = &settings.handling;
+    let index = |options: &[f32], value: f32| nearest(
+        &options.iter().map(|o| *o as f64).collect::<Vec<f64>>(), value as f64);
+
+    Menu::new("SETTINGS", vec![
+        MenuItem::choice("DAS", millis(&DAS_OPTIONS),
+            nearest(&DAS_OPTIONS, handling.das)),
+        MenuItem::choice("ARR", millis(&ARR_OPTIONS),
+            nearest(&ARR_OPTIONS, handling.arr)),
+        MenuItem::choice("Soft drop", factors, nearest(&SDF_OPTIONS, handling.sdf)),
+        MenuItem::choice("Line clear",
+            ClearStyle::ALL.iter().map(|s| s.name().to_string()).collect(),
+            ClearStyle::ALL.iter().position(|s| *s == settings.clear_style)
+                .unwrap_or(0)),
+        MenuItem::choice("Touch",
+            TouchLayout::ALL.iter().map(|l| l.name().to_string()).collect(),
+            TouchLayout::ALL.iter().position(|l| *l == settings.touch_layout)
+                .unwrap_or(0)),
+        MenuItem::choice("Drag",
+            DRAG_OPTIONS.iter().map(|d| format!("{} blocks", d)).collect(),
+            index(&DRAG_OPTIONS, settings.drag_distance)),
+        MenuItem::choice("Theme",
+            Theme::ALL.iter().map(|t| t.name().to_string()).collect(),
+            Theme::ALL.iter().position(|t| *t == settings.theme).unwrap_or(0)),
+        MenuItem::choice("Volume",
+            VOLUME_OPTIONS.iter().map(|v| format!("{}%", (v * 100.0).round())).collect(),
+            index(&VOLUME_OPTIONS, settings.volume)),
+        MenuItem::choice("Block size",
+            BLOCK_SIZE_OPTIONS.iter().map(|b| format!("{} px", b)).collect(),
+            index(&BLOCK_SIZE_OPTIONS, settings.block_size)),
+        MenuItem::action("Controls"),
+        MenuItem::action("Back"),
+    ])
+}
+
+/// Keys bound to each action. Activating an action waits for a key
+/// to bind to it (or to unbind, if it already was).
+fn controls_menu(bindings: &Bindings, cursor: usize) -> Menu {
+    let mut items: Vec<MenuItem> = Action::ALL.iter()
+        .map(|action| MenuItem::action(
+            &format!("{}: {}", action.name(), bindings.describe(*action))))
+        .collect();
+    items.push(MenuItem::action("Reset"));
+    items.push(MenuItem::action("Back"));
+
+    let mut menu = Menu::new("CONTROLS", items);
+    menu.set_cursor(cursor);
+    menu
+}
+
+/// The profiles (the one in use is marked) and the actions on them
+fn profiles_menu(profiles: &Profiles, cursor: usize) -> Menu {
+    let mut items: Vec<MenuItem> = profiles.profiles().iter().enumerate()
+        .map(|(i, profile)| MenuItem::action(&if i == profiles.index() {
+            format!("* {}", profile.name)
+        } else {
+            profile.name.clone()
+        }))
+        .collect();
+    items.push(MenuItem::action("New profile"));
+    items.push(MenuItem::action("Delete profile"));
+    items.push(MenuItem::action("Back"));
+
+    let mut menu = Menu::new("PROFILES", items);
+    menu.set_cursor(cursor);
+    menu
+}
+
+/// Lifetime statistics of the profile in use
+fn profile_stats(profiles: &Profiles) -> Vec<String> {
+    let stats = &profiles.current().stats;
+    vec![
+        format!("Games: {}  Pieces: {}  Lines: {}",
+            stats.games, stats.pieces, stats.lines),
+        format!("Best score: {}  Total score: {}", stats.best_score, stats.score),
+        format!("Time played: {}", format_time(stats.time)),
+    ]
+}
+
+fn pause_menu() -> Menu {
+    Menu::new("PAUSED", vec![
+        MenuItem::action("Resume"),
+        MenuItem::action("Restart"),
+        MenuItem::action("Save and quit"),
+        MenuItem::action("Quit"),
+    ])
+}
+
+/// Rules chosen in the main menu
+fn ruleset(menu: &Menu) -> Ruleset {
+    let mut ruleset = preset(menu.item(RULES).selected());
+    ruleset.spins = SpinRule::ALL[menu.item(SPINS).selected()];
+    let goal = menu.item(GOAL).selected();
+    match menu.item(MODE).selected() {
+        ULTRA => ruleset.time_limit = ULTRA_LIMITS.get(goal).copied(),
+        mode => ruleset.line_goal = goals(mode).get(goal).copied().flatten(),
+    }
+
+    ruleset
+}
+
+/// Rules named in `RULESETS`, without a goal or time limit
+fn preset(index: usize) -> Ruleset {
+    match index {
+        1 => Ruleset::classic(),
+        2 => Ruleset::arcade(),
+        _ => Ruleset::modern(),
+    }
+}
+
+/// Name in `RULESETS` of the rules of a game, whatever its spins,
+/// goal or time limit
+fn rules_name(ruleset: &Ruleset) -> &'static str {
+    let index = (0..RULESETS.len()).find(|index| *ruleset == Ruleset {
+        spins: ruleset.spins,
+        line_goal: ruleset.line_goal,
+        time_limit: ruleset.time_limit,
+        ..preset(*index)
+    });
+    RULESETS[index.unwrap_or(0)]
+}
+
+/// Starting levels of a level system, as menu options
+fn level_options(levels: LevelSystem) -> Vec<String> {
+    levels.start_levels().map(|level| level.to_string()).collect()
+}
+
+/// Starting level chosen in the main menu
+fn start_level(menu: &Menu) -> usize {
+    menu.item(LEVEL).value()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(1)
+}
+
+/// Line goals of a game mode
+fn goals(mode: usize) -> &'static [Option<usize>] {
+    match mode {
+        SPRINT => &SPRINT_GOALS,
+        _ => &MARATHON_GOALS,
+    }
+}
+
+/// Goals of a game mode (line goals or time limits), as menu options
+fn goal_options(mode: usize) -> Vec<String> {
+    match mode {
+        ULTRA => ULTRA_LIMITS.iter().map(|limit| limit_name(*limit)).collect(),
+        _ => goals(mode).iter().map(|goal| goal_name(*goal)).collect(),
+    }
+}
+
+fn goal_name(goal: Option<usize>) -> String {
+    match goal {
+        Some(lines) => format!("{} lines", lines),
+        None => "Endless".to_string(),
+    }
+}
+
+fn limit_name(limit: f64) -> String {
+    format!("{} minutes", limit / 60.0)
+}
+
+/// Name of the high score table of a game mode, its rules and their
+/// goal (or time limit)
+fn board(mode: &str, ruleset: &Ruleset) -> String {
+    let rules = rules_name(ruleset);
+    match ruleset.time_limit {
+        Some(limit) => format!("{} {} {}", mode, rules, limit_name(limit)),
+        None => format!("{} {} {}", mode, rules, goal_name(ruleset.line_goal)),
+    }
+}
+
+/// All the high score tables, in the order they're browsed
+fn boards() -> Vec<String> {
+    let marathon = (0..RULESETS.len()).flat_map(|rules|
+        MARATHON_GOALS.iter().map(move |goal| Ruleset {
+            line_goal: *goal,
+            ..preset(rules)
+        }));
+    let sprint = (0..RULESETS.len()).flat_map(|rules|
+        SPRINT_GOALS.iter().map(move |goal| Ruleset {
+            line_goal: *goal,
+            ..preset(rules)
+        }));
+    let ultra = (0..RULESETS.len()).flat_map(|rules|
+        ULTRA_LIMITS.iter().map(move |limit| Ruleset {
+            time_limit: Some(*limit),
+            ..preset(rules)
+        }));
+
+    marathon.map(|ruleset| board(MODES[MARATHON], &ruleset))
+        .chain(sprint.map(|ruleset| board(MODES[SPRINT], &ruleset)))
+        .chain(ultra.map(|ruleset| board(MODES[ULTRA], &ruleset)))
+        .collect()
+}
+
+/// Entry of a finished game in its high score table
+fn score_entry(game: &Game, name: &str) -> Score {
+    let stats = game.stats();
+    Score::new(name, stats.score, stats.lines, stats.time)
+}
+
+/// Position of the table of a game in `boards`
+fn board_index(mode: &str, ruleset: &Ruleset) -> usize {
+    let name = board(mode, ruleset);
+    boards().iter().position(|board| *board == name).unwrap_or(0)
+}
+
+/// Information shown next to the playfield while playing. Sprint
+/// games show their splits compared to the given ones.
+fn hud(game: &Game, mode: usize, ghost: Option<&[u64]>) -> Vec<String> {
+    let stats = game.stats();
+    let lines = match game.ruleset().line_goal {
+        Some(goal) => format!("Lines: {}/{}", stats.lines, goal),
+        None => format!("Lines: {}", stats.lines),
+    };
+
+    if mode == SPRINT {
+        let mut hud = vec![
+            MODES[mode].to_uppercase(),
+            format!("Time: {}", sprint::format_ticks(game.ticks())),
+            lines,
+            format!("PPS: {:.2}", sprint::pps(stats.pieces, game.ticks())),
+        ];
+        // Only the latest splits fit next to the playfield
+        let splits = split_lines(game.splits(), ghost);
+        hud.extend(splits.iter().skip(splits.len().saturating_sub(MAX_SPLITS)).cloned());
+        return hud;
+    }
+
+    if mode == ULTRA {
+        let left = game.time_left().unwrap_or_default();
+        return vec![
+            MODES[mode].to_uppercase(),
+            format!("Time left: {}", format_time(left as f64 * TICK)),
+            format!("Score: {}", stats.score),
+            format!("Level: {}", stats.level),
+            lines,
+        ];
+    }
+
+    vec![
+        MODES[mode].to_uppercase(),
+        format!("Score: {}", stats.score),
+        format!("Level: {}", stats.level),
+        lines,
+        format!("Time: {}", format_time(stats.time)),
+    ]
+}
+
+/// Personal best a Sprint game is compared to while playing
+fn sprint_ghost<'a>(game: &Game, bests: &'a PersonalBests) -> Option<&'a [u64]> {
+    let rules = rules_name(game.ruleset());
+    game.ruleset().line_goal.and_then(|goal| bests.best(rules, goal))
+}
+
+/// Split times, with their difference to the given ones
+fn split_lines(splits: &[u64], ghost: Option<&[u64]>) -> Vec<String> {
+    splits.iter().enumerate().map(|(i, &split)| {
+        let lines = (i + 1) * SPLIT_LINES;
+        match ghost.and_then(|ghost| ghost.get(i)) {
+            Some(&best) => format!("{}: {} ({})", lines,
+                sprint::format_ticks(split), sprint::format_delta(split, best)),
+            None => format!("{}: {}", lines, sprint::format_ticks(split)),
+        }
+    }).collect()
+}
+
+/// Summary of a finished game. Sprint games are compared to the
+/// personal best they tried to beat.
+fn summary(game: &Game, mode: usize, ghost: Option<&[u64]>,
+           new_best: bool) -> Vec<String> {
+    let stats = game.stats();
+
+    if mode == SPRINT {
+        let mut summary = vec![format!("Time: {}", sprint::format_ticks(game.ticks()))];
+        if let Some(&best) = ghost.and_then(|ghost| ghost.last()) {
+            summary.push(format!("Best: {} ({})", sprint::format_ticks(best),
+                sprint::format_delta(game.ticks(), best)));
+        }
+        if new_best {
+            summary.push("New personal best!".to_string());
+        }
+        summary.extend([
+            format!("Pieces: {}", stats.pieces),
+            format!("PPS: {:.2}", sprint::pps(stats.pieces, game.ticks())),
+            format!("KPP: {:.2}", sprint::kpp(stats.keys, stats.pieces)),
+        ]);
+        return summary;
+    }
+
+    if mode == ULTRA {
+        // Every game lasts the time limit, so the clears it made are
+        // shown instead
+        let mut summary = vec![
+            format!("Score: {}", stats.score),
+            format!("Level: {}", stats.level),
+            format!("Lines: {}", stats.lines),
+            format!("Pieces: {}", stats.pieces),
+        ];
+        summary.extend(stats.clears.breakdown().iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(name, count)| format!("{}: {}", name, count)));
+        return summary;
+    }
+
+    vec![
+        format!("Score: {}", stats.score),
+        format!("Level: {}", stats.level),
+        format!("Lines: {}", stats.lines),
+        format!("Pieces: {}", stats.pieces),
+        format!("Time: {}", format_time(stats.time)),
+    ]
+}
+
+/// Handling chosen in the settings menu (the rest of the handling
+/// settings are kept)
+fn handling(settings: &Menu, base: &Handling) -> Handling {
+    Handling {
+        das: DAS_OPTIONS[settings.item(DAS).selected()],
+        arr: ARR_OPTIONS[settings.item(ARR).selected()],
+        sdf: SDF_OPTIONS[settings.item(SDF).selected()],
+        ..base.clone()
+    }
+}
+
+/// Touch gestures chosen in the settings menu
+fn gesture_settings(settings: &Menu) -> GestureSettings {
+    GestureSettings {
+        drag_distance: DRAG_OPTIONS[settings.item(DRAG).selected()],
+        ..Default::default()
+    }
+}
+
+/// Update the settings of a profile with the options chosen in the
+/// menus and the bindings
+fn update_settings(settings: &mut Settings, menu: &Menu, settings_menu: &Menu,
+                   bindings: &Bindings, systems: &[RotationSystem]) {
+    settings.bindings = bindings.clone();
+    settings.handling = handling(settings_menu, &settings.handling);
+    settings.rotation_system =
+        systems[menu.item(ROTATION).selected()].name().to_string();
+    settings.randomizer = Randomizer::ALL[menu.item(RANDOMIZER).selected()];
+    settings.theme = Theme::ALL[settings_menu.item(THEME).selected()];
+    settings.volume = VOLUME_OPTIONS[settings_menu.item(VOLUME).selected()];
+    settings.block_size = BLOCK_SIZE_OPTIONS[settings_menu.item(BLOCK_SIZE).selected()];
+    settings.clear_style = ClearStyle::ALL[settings_menu.item(CLEAR).selected()];
+    settings.touch_layout = TouchLayout::ALL[settings_menu.item(TOUCH).selected()];
+    settings.drag_distance = DRAG_OPTIONS[settings_menu.item(DRAG).selected()];
+}
+
+/// Show the settings of a profile in the menus: the preferred
+/// rotation system and randomizer are selected in the main menu
+///
+/// # Returns
+/// The settings menu and the key bindings of the profile
+fn apply_settings(settings: &Settings, menu: &mut Menu,
+                  systems: &[RotationSystem]) -> (Menu, Bindings) {
+    if let Some(rotation) = systems.iter()
+        .position(|system| system.name() == settings.rotation_system) {
+        menu.select(ROTATION, rotation);
+    }
+    menu.select(RANDOMIZER, Randomizer::ALL.iter()
+        .position(|r| *r == settings.randomizer).unwrap_or(0));
+
+    (settings_menu(settings), settings.bindings.clone())
+}
+
+/// Save the profiles, reporting any error
+fn save_profiles(profiles: &Profiles) {
+    if let Err(e) = profiles.save() {
+        println!("{}", e);
+    }
+}
+
+/// Save the game as the suspended one, with the mode chosen in the
+/// main menu
+fn suspend_game(game: &Game, menu: &Menu) -> Option<SavedGame> {
+    let mut saved = game.suspend();
+    saved.mode = MODES[menu.item(MODE).selected()].to_string();
+
+    match saved.store() {
+        Ok(()) => Some(saved),
+        Err(e) => {
+            println!("{}", e);
+            None
+        }
+    }
+}
+
+/// Forget the suspended game
+fn discard_suspended() {
+    if let Err(e) = SavedGame::discard() {
+        println!("{}", e);
+    }
+}
+
+/// The suspended game, if there's a valid one
+fn load_suspended() -> Option<SavedGame> {
+    match SavedGame::load()? {
+        Ok(saved) => Some(saved),
+        Err(e) => {
+            println!("{}", e);
+            None
+        }
+    }
+}
+
+/// Restore a suspended game and forget it, so it can only be resumed
+/// once (invalid games are forgotten too)
+fn resume_game(saved: &SavedGame, systems: &[RotationSystem]) -> Option<Game> {
+    discard_suspended();
+
+    let Some(rotation_system) = systems.iter()
+        .find(|system| system.name() == saved.rotation_system) else {
+        println!("unknown rotation system {}", saved.rotation_system);
+        return None;
+    };
+
+    match Game::resume(saved, rotation_system.clone()) {
+        Ok(game) => Some(game),
+        Err(e) => {
+            println!("{}", e);
+            None
+        }
+    }
+}
+
+/// Read the characters typed into a name
+fn edit_name(name: &mut String) {
+    while let Some(c) = get_char_pressed() {
+        if !c.is_control() && name.chars().count() < MAX_NAME_LENGTH {
+            name.push(c);
+        }
+    }
+    if is_key_pressed(KeyCode::Backspace) {
+        name.pop();
+    }
+}
+
+/// Load a replay and set up a viewer to play it
+async fn load_replay(path: &str, systems: &[RotationSystem])
+    -> Option<ReplayViewer> {
+
+    let replay = match Replay::load(path).await {
+        Ok(replay) => replay,
+        Err(e) => {
+            println!("{}: {}", path, e);
+            return None;
+        }
+    };
+
+    let Some(rotation_system) = systems.iter()
+        .find(|system| system.name() == replay.rotation_system) else {
+        println!("{}: unknown rotation system {}", path, replay.rotation_system);
+        return None;
+    };
+
+    Some(ReplayViewer::new(replay, rotation_system.clone()))
+}
+
+/// Save the replay of a finished game (only natively)
+fn save_replay(recorder: &Recorder) {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let replay = recorder.replay();
+        let path = format!("{}/{}.toml", REPLAY_DIR, replay.seed);
+        if let Err(e) = replay.save(&path) {
+            println!("{}: {}", path, e);
+        }
     }
 
-    if pf.collides(shape, cs_row, cs_col as isize, new_rot) {
-        rot
+    #[cfg(target_arch = "wasm32")]
+    let _ = recorder;
+}
+
+/// Draw the game: playfield, line clear animation, current and
+/// held shapes and callouts
+fn draw_game(graphics: &Graphics, game: &Game,
+             animation: &mut Option<ClearAnimation>, style: ClearStyle) {
+
+    graphics.draw_playfield(game.playfield());
+
+    // DRAW LINE CLEAR ANIMATION --------------------------------------
+
+    if game.phase() == Phase::LineClear {
+        let animation = animation.get_or_insert_with(||
+            ClearAnimation::new(
+                style,
+                game.playfield(),
+                game.clearing_rows(),
+                game.ruleset().line_clear_delay));
+        graphics.draw_line_clear(
+            game.playfield(), animation, game.phase_time());
     } else {
-        new_rot
+        *animation = None;
+    }
+
+    // DRAW CURRENT AND HELD SHAPES -----------------------------------
+
+    // The fixed piece is already part of the playfield during the
+    // line clear and entry delays
+    if game.phase() == Phase::Falling {
+        let piece = game.piece();
+        graphics.draw_shape(
+            piece.shape(), piece.row() as f32, piece.col() as f32,
+            piece.rot());
+    }
+
+    if let Some(shape) = game.hold_shape() {
+        graphics.draw_hold(shape);
+    }
+
+    // DRAW CALLOUTS --------------------------------------------------
+
+    if let Some(clear) = game.last_clear()
+        && game.time() - clear.time < CALLOUT_TIME {
+        graphics.draw_callouts(&clear.callouts());
     }
 }
 
+/// Set up the game with the options chosen in the menus and
+/// start it
+fn start_game(game: &mut Game, menu: &Menu, handling: &Handling,
+              systems: &[RotationSystem]) {
+    let rotation_system = systems[menu.item(ROTATION).selected()].clone();
+
+    game.shapes_mut().update_rotation_system(rotation_system);
+    game.shapes_mut().update_randomizer(
+        Randomizer::ALL[menu.item(RANDOMIZER).selected()]);
+    game.set_ruleset(ruleset(menu));
+    game.set_start_level(start_level(menu));
+    game.set_handling(handling.clone());
+    game.restart();
+}
+
 #[macroquad::main("Rusty Blocks")]
 async fn main() {
 
-    // TODO: add a menu
+    let args = args();
+    let custom = custom_rotation_system(args.definition).await;
+    let custom_name = custom.as_ref().map(|system| system.name().to_string());
+    let systems = rotation_systems(custom);
 
-    let mut pf = Playfield::new();
+    let mut profiles = Profiles::load();
+    let mut menu = main_menu(&systems, 0);
+    let (mut settings, mut bindings) =
+        apply_settings(&profiles.current().settings, &mut menu, &systems);
+    // The loaded definition is selected by default
+    if let Some(name) = &custom_name
+        && let Some(rotation) = systems.iter()
+            .position(|system| system.name() == name) {
+        menu.select(ROTATION, rotation);
+    }
+    let rotation = menu.item(ROTATION).selected();
+    let mut profile_menu = profiles_menu(&profiles, 0);
+    let mut pause = pause_menu();
+    let mut controls = controls_menu(&bindings, 0);
+    // Action waiting for a key to be bound
+    let mut rebinding: Option<Action> = None;
+    let mut gestures = GestureRecognizer::new(gesture_settings(&settings));
+    let mut screen = Screen::MainMenu;
 
-    let mut graphics = Graphics::new(&pf);
+    let mut game = Game::new(systems[rotation].clone(), Ruleset::modern());
 
-    let mut shape_manager = ShapeFactory::new(RotationSystem::NES);
+    let mut graphics = Graphics::new(game.playfield());
 
     let mut rotation_demo = false;
 
-    // Initialize first shape
-    let mut current_shape = shape_manager.current_shape();
-    let mut cs_col: isize = 
-                    ((pf.n_cols() / 2) 
-                     - (current_shape.width() / 2)) 
-                     as isize;
-    let mut cs_row: usize = current_shape.row_offset();
-    let mut rot: isize = 0;
-    let mut spawn_shape = false;
-
-    // Initialize Shape Drop System
-    let mut drop_start = get_time();
-    let drop_delay = 0.8;
-
-    // Initialize Shift Delay System
-    let mut shift_start = get_time();
-    let mut first_press = true;
-    
+    let mut animation: Option<ClearAnimation> = None;
+
+    // Time not simulated yet, and input not consumed by a tick yet
+    let mut accumulator = 0.0;
+    let mut pending = Input::default();
+    let mut recorder: Option<Recorder> = None;
+    // Replay of the last finished game
+    let mut last_replay: Option<Replay> = None;
+    let mut viewer: Option<ReplayViewer> = None;
+    // Screen shown when the replay viewer is closed
+    let mut viewer_exit = Screen::MainMenu;
+
+    let mut scores = HighScores::load();
+    let mut bests = PersonalBests::load();
+    // Personal best the last Sprint game was compared to, and whether
+    // it beat it
+    let mut ghost: Option<Vec<u64>> = None;
+    let mut new_best = false;
+    // The result of the current game hasn't been recorded yet
+    let mut result_pending = false;
+    // Name being typed (high score or new profile)
+    let mut name = String::new();
+    // Table shown in the leaderboard (see `boards`) and entry just
+    // added to it
+    let mut shown_board = 0;
+    let mut highlight: Option<usize> = None;
+    // Screen shown when the leaderboard is closed
+    let mut board_exit = Screen::MainMenu;
+    let mut suspended = load_suspended();
+    // The suspended game is the current one, saved as its pieces lock
+    // and when it's paused in case the game is closed
+    let mut autosaved = false;
+    // Closing the window suspends the current game first
+    prevent_quit();
+
+    if let Some(path) = args.replay {
+        viewer = load_replay(&path, &systems).await;
+        if viewer.is_some() {
+            screen = Screen::Replay;
+        }
+    }
+
     loop {
 
-        // let delta = get_frame_time();
+        // QUIT -------------------------------------------------------
+
+        if is_quit_requested() {
+            let playing = matches!(screen, Screen::Playing | Screen::Paused);
+            if playing && !game.is_over() {
+                suspend_game(&game, &menu);
+            }
+            break;
+        }
 
         // CLEAR SCREEN -----------------------------------------------
 
-        clear_background(DARKGRAY);
+        graphics.set_theme(Theme::ALL[settings.item(THEME).selected()]);
+        clear_background(graphics.theme().background());
 
         // SCALE BLOCK SIZE AND COMPUTE UI COMPONENTS POSITIONS -------
 
-        graphics.update_scale(&pf);
-
-        // SPAWN NEW SHAPE --------------------------------------------
-            
-        if spawn_shape {
-            
-            // TODO: shape parameters could be stored in a struct
-            // TODO: this code is repeated before starting the loop
-            current_shape = shape_manager.current_shape();
-
-            cs_col = ((pf.n_cols() / 2) 
-                     - (current_shape.width() / 2))
-                     as isize;
-            
-            cs_row = current_shape.row_offset();
-            rot = 0;
-            spawn_shape = false;
-
-            drop_start = get_time();
-
-            if pf.collides(&current_shape, cs_row, cs_col as isize, rot) {
-                // TODO game over
-                println!("Game Over");
-                break;
+        graphics.set_max_block_size(
+            BLOCK_SIZE_OPTIONS[settings.item(BLOCK_SIZE).selected()]);
+        graphics.update_scale(game.playfield());
+
+        // MENUS ------------------------------------------------------
+
+        match screen {
+            Screen::MainMenu => {
+                graphics.draw_menu(&menu);
+                let saved_game = match &suspended {
+                    Some(saved) => format!("Saved game: {}, level {}, {}",
+                        saved.mode, saved.stats.level,
+                        format_time(saved.stats.time)),
+                    None => "No saved game".to_string(),
+                };
+                graphics.draw_menu_notes(&menu, &[
+                    format!("Profile: {}", profiles.current().name),
+                    saved_game,
+                ]);
+
+                match menu.handle_input(&graphics) {
+                    Some(MenuEvent::Activated(PROFILES)) => {
+                        // Keep the choices of the profile in use
+                        update_settings(&mut profiles.current_mut().settings,
+                            &menu, &settings, &bindings, &systems);
+                        save_profiles(&profiles);
+                        profile_menu = profiles_menu(&profiles, profiles.index());
+                        screen = Screen::Profiles;
+                    }
+                    Some(MenuEvent::Activated(LEADERBOARD)) => {
+                        shown_board = board_index(
+                            MODES[menu.item(MODE).selected()], &ruleset(&menu));
+                        highlight = None;
+                        board_exit = Screen::MainMenu;
+                        screen = Screen::Leaderboard;
+                    }
+                    Some(MenuEvent::Activated(SETTINGS)) =>
+                        screen = Screen::Settings,
+                    Some(MenuEvent::Changed(RULES)) =>
+                        menu.set_options(LEVEL, level_options(ruleset(&menu).levels)),
+                    Some(MenuEvent::Changed(MODE)) =>
+                        menu.set_options(GOAL, goal_options(menu.item(MODE).selected())),
+                    Some(MenuEvent::Activated(START)) => {
+                        update_settings(&mut profiles.current_mut().settings,
+                            &menu, &settings, &bindings, &systems);
+                        save_profiles(&profiles);
+                        start_game(&mut game, &menu,
+                            &profiles.current().settings.handling, &systems);
+                        recorder = Some(Recorder::new(&game));
+                        pending = Input::default();
+                        result_pending = true;
+                        autosaved = false;
+                        screen = Screen::Playing;
+                    }
+                    Some(MenuEvent::Activated(RESUME_GAME)) => {
+                        if let Some(saved) = suspended.take()
+                            && let Some(resumed) = resume_game(&saved, &systems) {
+                            game = resumed;
+                            // The result goes to the table of its mode
+                            if let Some(mode) = MODES.iter()
+                                .position(|mode| *mode == saved.mode) {
+                                menu.select(MODE, mode);
+                                menu.set_options(GOAL, goal_options(mode));
+                            }
+                            // Without the start of the game, there's
+                            // nothing to replay
+                            recorder = None;
+                            last_replay = None;
+                            pending = Input::default();
+                            result_pending = true;
+                            autosaved = false;
+                            screen = Screen::Playing;
+                        }
+                    }
+                    _ => {}
+                }
+
+                next_frame().await;
+                continue;
             }
-        }
+            Screen::Settings => {
+                graphics.draw_menu(&settings);
 
-        // PROCESS INPUT ----------------------------------------------
+                match settings.handle_input(&graphics) {
+                    Some(MenuEvent::Activated(CONTROLS)) =>
+                        screen = Screen::Controls,
+                    Some(MenuEvent::Activated(BACK) | MenuEvent::Back) => {
+                        update_settings(&mut profiles.current_mut().settings,
+                            &menu, &settings, &bindings, &systems);
+                        save_profiles(&profiles);
+                        screen = Screen::MainMenu;
+                    }
+                    _ => {}
+                }
 
-        for touch in touches() {
-            match touch.phase {
-                TouchPhase::Started => {
-                    if touch.position.x > screen_width() / 2.0 {
-                        
-                        rot = rotate_clockwise(
-                            &pf, &current_shape, cs_row, cs_col, rot);
+                next_frame().await;
+                continue;
+            }
+            Screen::Profiles => {
+                graphics.draw_menu(&profile_menu);
+                graphics.draw_menu_notes(&profile_menu, &profile_stats(&profiles));
 
-                    } else {
+                let n_profiles = profiles.profiles().len();
+                let mut changed = false;
 
-                        rot = rotate_counter_cw(
-                            &pf, &current_shape, cs_row, cs_col, rot);
+                match profile_menu.handle_input(&graphics) {
+                    Some(MenuEvent::Activated(index)) if index < n_profiles => {
+                        profiles.select(index);
+                        changed = true;
                     }
-                },
-                _ => (),
+                    Some(MenuEvent::Activated(index))
+                        if index == n_profiles + NEW_PROFILE => {
+                        name.clear();
+                        while get_char_pressed().is_some() {}
+                        screen = Screen::ProfileName;
+                    }
+                    Some(MenuEvent::Activated(index))
+                        if index == n_profiles + DELETE_PROFILE => {
+                        changed = profiles.remove_current();
+                    }
+                    Some(MenuEvent::Activated(_) | MenuEvent::Back) =>
+                        screen = Screen::MainMenu,
+                    _ => {}
+                }
+
+                if changed {
+                    (settings, bindings) = apply_settings(
+                        &profiles.current().settings, &mut menu, &systems);
+                    controls = controls_menu(&bindings, 0);
+                    save_profiles(&profiles);
+                    profile_menu = profiles_menu(&profiles, profile_menu.cursor()
+                        .min(profiles.profiles().len() + PROFILES_BACK));
+                }
+
+                next_frame().await;
+                continue;
             }
-        }
+            Screen::ProfileName => {
+                edit_name(&mut name);
 
-        if is_key_pressed(KeyCode::D) { 
-            rot = rotate_clockwise(
-                &pf, &current_shape, cs_row, cs_col, rot);
-        }
+                graphics.draw_menu(&profile_menu);
+                graphics.draw_name_entry("NEW PROFILE",
+                    "Settings start with their defaults", &name);
+                graphics.draw_prompt("Enter: create  Esc: cancel");
 
-        if is_key_pressed(KeyCode::S) { 
-            rot = rotate_counter_cw(
-                &pf, &current_shape, cs_row, cs_col, rot);
-        }
+                if is_key_pressed(KeyCode::Enter) && !name.trim().is_empty() {
+                    let index = profiles.add(&name);
+                    (settings, bindings) = apply_settings(
+                        &profiles.current().settings, &mut menu, &systems);
+                    controls = controls_menu(&bindings, 0);
+                    save_profiles(&profiles);
+                    profile_menu = profiles_menu(&profiles, index);
+                    screen = Screen::Profiles;
+                } else if is_key_pressed(KeyCode::Escape) {
+                    screen = Screen::Profiles;
+                }
 
-        if is_key_pressed(KeyCode::R) {
-            rotation_demo = !rotation_demo;
-        }
+                next_frame().await;
+                continue;
+            }
+            Screen::Controls => {
+                graphics.draw_menu(&controls);
 
-        if is_key_released(KeyCode::Left) || 
-            is_key_released(KeyCode::Right) ||
-            is_key_released(KeyCode::Down) {
-            first_press = true;
-        }
-            
-        if is_key_down(KeyCode::Left) 
-            && !pf.collides(
-                &current_shape, cs_row, cs_col as isize - 1, rot) {
-                
-                if first_press || get_time() - shift_start >= SHIFT_DELAY {
-                    
-                    cs_col -= 1;
-                    first_press = false;
-                    shift_start = get_time();
+                if let Some(action) = rebinding {
+                    graphics.draw_prompt(
+                        &format!("Press a key for {} (Esc: cancel)", action.name()));
+
+                    if let Some(key) = get_last_key_pressed() {
+                        if key != KeyCode::Escape {
+                            bindings.toggle(action, key);
+                            controls = controls_menu(&bindings, controls.cursor());
+                        }
+                        rebinding = None;
+                    }
+                } else {
+                    match controls.handle_input(&graphics) {
+                        Some(MenuEvent::Activated(RESET_CONTROLS)) => {
+                            bindings = Bindings::default();
+                            controls = controls_menu(&bindings, RESET_CONTROLS);
+                        }
+                        Some(MenuEvent::Activated(CONTROLS_BACK) | MenuEvent::Back) =>
+                            screen = Screen::Settings,
+                        Some(MenuEvent::Activated(index)) =>
+                            rebinding = Some(Action::ALL[index]),
+                        _ => {}
+                    }
                 }
-        }
 
-        if is_key_down(KeyCode::Right)
-            && !pf.collides(
-                &current_shape, cs_row, cs_col  as isize + 1 as isize, rot) {
-                
-                if first_press || get_time() - shift_start >= SHIFT_DELAY {
-                    
-                    cs_col += 1;
-                    first_press = false;
-                    shift_start = get_time();
+                next_frame().await;
+                continue;
+            }
+            Screen::Paused => {
+                graphics.draw_playfield(game.playfield());
+                graphics.draw_menu(&pause);
+
+                match pause.handle_input(&graphics) {
+                    Some(MenuEvent::Activated(RESUME) | MenuEvent::Back) =>
+                        screen = Screen::Playing,
+                    Some(MenuEvent::Activated(RESTART)) => {
+                        if autosaved {
+                            discard_suspended();
+                            suspended = None;
+                            autosaved = false;
+                        }
+                        game.restart();
+                        recorder = Some(Recorder::new(&game));
+                        pending = Input::default();
+                        result_pending = true;
+                        screen = Screen::Playing;
+                    }
+                    Some(MenuEvent::Activated(SUSPEND)) => {
+                        if let Some(saved) = suspend_game(&game, &menu) {
+                            suspended = Some(saved);
+                            // Recorded once the resumed game ends
+                            recorder = None;
+                            result_pending = false;
+                            autosaved = false;
+                            screen = Screen::MainMenu;
+                        }
+                    }
+                    Some(MenuEvent::Activated(QUIT)) => {
+                        // The game is abandoned
+                        if autosaved {
+                            discard_suspended();
+                            suspended = None;
+                            autosaved = false;
+                        }
+                        screen = Screen::MainMenu;
+                    }
+                    _ => {}
                 }
+
+                next_frame().await;
+                continue;
+            }
+            Screen::Replay => {
+                let Some(viewer) = viewer.as_mut() else {
+                    screen = Screen::MainMenu;
+                    continue;
+                };
+
+                let tapped = touches().iter()
+                    .any(|touch| touch.phase == TouchPhase::Started);
+
+                if is_key_pressed(KeyCode::Space) || tapped {
+                    viewer.toggle_pause();
+                }
+                if is_key_pressed(KeyCode::Period) {
+                    viewer.step();
+                }
+                if is_key_pressed(KeyCode::Minus) || is_key_pressed(KeyCode::Down) {
+                    viewer.change_speed(-1);
+                }
+                if is_key_pressed(KeyCode::Equal) || is_key_pressed(KeyCode::Up) {
+                    viewer.change_speed(1);
+                }
+                if is_key_pressed(KeyCode::Left) {
+                    viewer.seek(viewer.piece().saturating_sub(1));
+                }
+                if is_key_pressed(KeyCode::Right) {
+                    viewer.seek(viewer.piece() + 1);
+                }
+                if is_key_pressed(KeyCode::PageUp) {
+                    viewer.seek(viewer.piece().saturating_sub(10));
+                }
+                if is_key_pressed(KeyCode::PageDown) {
+                    viewer.seek(viewer.piece() + 10);
+                }
+
+                viewer.update(get_frame_time() as f64);
+
+                let replay_game = viewer.game();
+                draw_game(&graphics, replay_game, &mut animation,
+                    ClearStyle::ALL[settings.item(CLEAR).selected()]);
+                graphics.draw_input_overlay(
+                    replay_game.playfield(), viewer.player().input());
+
+                let state = if viewer.player().is_over() {
+                    "END"
+                } else if viewer.is_paused() {
+                    "PAUSED"
+                } else {
+                    ""
+                };
+                graphics.draw_info(replay_game.playfield(), &[
+                    "REPLAY".to_string(),
+                    format!("Time: {}", format_time(replay_game.time())),
+                    format!("Piece: {}", viewer.piece()),
+                    format!("Speed: {}x", viewer.speed()),
+                    state.to_string(),
+                ]);
+                graphics.draw_prompt(
+                    "Space: pause  .: step  -/+: speed  Left/Right: piece  Esc: exit");
+
+                if is_key_pressed(KeyCode::Escape) {
+                    screen = viewer_exit;
+                }
+
+                next_frame().await;
+                continue;
+            }
+            Screen::Leaderboard => {
+                let boards = boards();
+                if is_key_pressed(KeyCode::Left) {
+                    shown_board = (shown_board + boards.len() - 1) % boards.len();
+                    highlight = None;
+                }
+                if is_key_pressed(KeyCode::Right) {
+                    shown_board = (shown_board + 1) % boards.len();
+                    highlight = None;
+                }
+
+                let board = &boards[shown_board];
+                graphics.draw_leaderboard(board, scores.table(board), highlight);
+                graphics.draw_prompt("Left/Right: table  Esc: back");
+
+                let tapped = touches().iter()
+                    .any(|touch| touch.phase == TouchPhase::Started);
+
+                if is_key_pressed(KeyCode::Escape) || is_key_pressed(KeyCode::Enter)
+                    || tapped {
+                    screen = board_exit;
+                }
+
+                next_frame().await;
+                continue;
+            }
+            Screen::NameEntry => {
+                edit_name(&mut name);
+
+                graphics.draw_playfield(game.playfield());
+                if menu.item(MODE).selected() == SPRINT {
+                    graphics.draw_name_entry("NEW BEST TIME",
+                        &format!("Time: {}", sprint::format_ticks(game.ticks())), &name);
+                } else {
+                    graphics.draw_name_entry("NEW HIGH SCORE",
+                        &format!("Score: {}", game.stats().score), &name);
+                }
+                graphics.draw_prompt("Enter: save  Esc: skip");
+
+                let tapped = touches().iter()
+                    .any(|touch| touch.phase == TouchPhase::Started);
+
+                if is_key_pressed(KeyCode::Enter) || tapped {
+                    if name.trim().is_empty() {
+                        name = profiles.current().name.clone();
+                    }
+
+                    let mode = MODES[menu.item(MODE).selected()];
+                    shown_board = board_index(mode, game.ruleset());
+                    highlight = scores.insert(
+                        &board(mode, game.ruleset()), score_entry(&game, &name));
+                    if let Err(e) = scores.save() {
+                        println!("{}", e);
+                    }
+
+                    board_exit = Screen::Playing;
+                    screen = Screen::Leaderboard;
+                } else if is_key_pressed(KeyCode::Escape) {
+                    screen = Screen::Playing;
+                }
+
+                next_frame().await;
+                continue;
+            }
+            Screen::Playing => {}
         }
 
-        if is_key_down(KeyCode::Down) {
-            if !pf.collides(
-                &current_shape, cs_row + 1, cs_col as isize, rot) {
-                
-                if first_press || get_time() - shift_start >= QUICK_DROP_DELAY {
-                    
-                    cs_row += 1;
-                    first_press = false;
-                    shift_start = get_time();
-                    // Cancel drop delay
-                    drop_start = get_time();
+        // GAME OVER SCREEN -------------------------------------------
+
+        if let Some(reason) = game.game_over() {
+
+            // Finished games can't be resumed
+            if autosaved {
+                discard_suspended();
+                suspended = None;
+                autosaved = false;
+            }
+
+            if let Some(recorder) = recorder.take() {
+                save_replay(&recorder);
+                last_replay = Some(recorder.replay().clone());
+            }
+
+            if result_pending {
+                result_pending = false;
+
+                profiles.current_mut().stats.record(&game.stats());
+                save_profiles(&profiles);
+
+                let mode = menu.item(MODE).selected();
+                let board = board(MODES[mode], game.ruleset());
+                if mode == SPRINT {
+                    // Only finished games can be personal bests
+                    ghost = None;
+                    new_best = false;
+                    if reason == GameOver::Goal
+                        && let Some(goal) = game.ruleset().line_goal {
+                        let rules = rules_name(game.ruleset());
+                        ghost = bests.best(rules, goal).map(|best| best.to_vec());
+                        new_best = bests.record(rules, goal, game.splits());
+                        if new_best && let Err(e) = bests.save() {
+                            println!("{}", e);
+                        }
+                    }
                 }
 
-            } else {
-                spawn_shape = true;
+                // Sprint games that top out aren't ranked
+                let ranked = mode != SPRINT || reason == GameOver::Goal;
+                if ranked && scores.qualifies(&board, &score_entry(&game, &name)) {
+                    name = profiles.current().name.clone();
+                    // Forget the keys typed while playing
+                    while get_char_pressed().is_some() {}
+                    screen = Screen::NameEntry;
+                    next_frame().await;
+                    continue;
+                }
             }
+
+            graphics.draw_playfield(game.playfield());
+            graphics.draw_game_over(game.playfield(), reason, &summary(&game,
+                menu.item(MODE).selected(), ghost.as_deref(), new_best));
+
+            let tapped = touches().iter()
+                .any(|touch| touch.phase == TouchPhase::Started);
+
+            if is_key_pressed(KeyCode::Enter) || tapped {
+                game.restart();
+                recorder = Some(Recorder::new(&game));
+                pending = Input::default();
+                result_pending = true;
+            } else if is_key_pressed(KeyCode::R)
+                && let Some(replay) = &last_replay {
+                viewer = Some(ReplayViewer::new(replay.clone(),
+                    game.shapes().current_rotation_system().clone()));
+                viewer_exit = Screen::Playing;
+                screen = Screen::Replay;
+            } else if is_key_pressed(KeyCode::Escape) {
+                screen = Screen::MainMenu;
+            }
+
+            next_frame().await;
+            continue;
         }
 
-        // TODO: implement hard drop
+        // PROCESS INPUT ----------------------------------------------
 
-        // PROCESS FALLING RATE ---------------------------------------
+        let mut input = Input::poll(&bindings);
 
-        // We do this after processing input to ensure that the
-        // the shape is fixed to the playfield in the correct position
+        // Pause with the bound keys, or tapping above the playfield
+        let paused_by_touch = touches().iter().any(|touch|
+            touch.phase == TouchPhase::Started
+                && touch.position.y < graphics.pf_y);
 
-        if !spawn_shape && get_time() - drop_start >= drop_delay {
-            if !pf.collides(
-                &current_shape, cs_row + 1, cs_col as isize, rot) {
-                
-                cs_row += 1;
-                drop_start = get_time();
-                
-            } else {
-                spawn_shape = true;
+        if input.is_pressed(Action::Pause) || paused_by_touch {
+            // Saved in case the game is closed while paused
+            if let Some(saved) = suspend_game(&game, &menu) {
+                suspended = Some(saved);
+                autosaved = true;
             }
+            pause = pause_menu();
+            screen = Screen::Paused;
+            next_frame().await;
+            continue;
         }
 
-        // FIX SHAPE --------------------------------------------------
+        let layout = TouchLayout::ALL[settings.item(TOUCH).selected()];
+        let buttons = touch::buttons(
+            screen_width(), screen_height(), graphics.block_size);
 
-        if spawn_shape {
-            
-            // Add the shape to the playfield
-            let mod_rows = pf.add(&current_shape, cs_row, cs_col, rot);
-            // Get the rows that need to be cleared, if any
-            let cleared_lines = pf.check_rows(&mod_rows);
-            // Clear the rows, if any
-            pf.clear_rows(&cleared_lines);
+        match layout {
+            TouchLayout::Gestures => {
+                gestures.set_settings(gesture_settings(&settings));
+                let frame = gestures.update(
+                    &touches(), get_time(), graphics.block_size);
 
-            // TODO add a score system
-            // TODO add a level system
-            // TODO add a line clear animation
+                input.add_shift(frame.shift);
+                input.add_drops(frame.drops);
+                for action in frame.actions {
+                    input.press(action);
+                }
+            }
+            TouchLayout::Buttons =>
+                touch::press_buttons(&buttons, &touches(), &mut input),
+        }
+
+        // Rotation demo keys, unless they were bound to an action
+        let demo_key = |key| is_key_pressed(key) && bindings.action(key).is_none();
+
+        if demo_key(KeyCode::R) {
+            rotation_demo = !rotation_demo;
+        }
+
+        if rotation_demo && demo_key(KeyCode::N) {
+            let next = demo::next_rotation_system(
+                game.shapes().current_rotation_system(), &systems);
+            game.shapes_mut().update_rotation_system(next);
+            // The game can't be replayed anymore
+            recorder = None;
+        }
+
+        // UPDATE GAME ------------------------------------------------
+
+        // The game runs in fixed ticks: input is consumed by the
+        // first tick run after it happened
+        pending.merge(&input);
+        accumulator += get_frame_time() as f64;
+
+        let pieces = game.stats().pieces;
+        let mut ticks = 0;
+        while accumulator >= TICK && ticks < MAX_TICKS_PER_FRAME {
+            accumulator -= TICK;
+            ticks += 1;
+
+            if let Some(recorder) = recorder.as_mut() {
+                recorder.record(&pending);
+            }
+            game.tick(&pending);
+            pending.consume();
+
+            if game.is_over() {
+                break;
+            }
+        }
+        if ticks == MAX_TICKS_PER_FRAME {
+            accumulator = 0.0;
+        }
+
+        // Saved each time a piece locks, since a closed browser tab
+        // doesn't give the game a chance to suspend itself
+        if game.stats().pieces != pieces && !game.is_over()
+            && let Some(saved) = suspend_game(&game, &menu) {
+            suspended = Some(saved);
+            autosaved = true;
         }
 
         // DRAW PLAYFIELD ---------------------------------------------
 
         if !rotation_demo {
-            
-            graphics.draw_playfield(&pf);
 
-            // DRAW CURRENT SHAPE -------------------------------------
+            draw_game(&graphics, &game, &mut animation,
+                ClearStyle::ALL[settings.item(CLEAR).selected()]);
+            graphics.draw_info(game.playfield(),
+                &hud(&game, menu.item(MODE).selected(), match game.game_over() {
+                    // The best may be this game already
+                    Some(_) => ghost.as_deref(),
+                    None => sprint_ghost(&game, &bests),
+                }));
+
+            if layout == TouchLayout::Buttons {
+                graphics.draw_touch_buttons(&buttons, &input);
+            }
 
-            graphics.draw_shape(
-                &current_shape, cs_row as f32, cs_col as f32, rot);
-        
         } else {
-        
+
             demo::rotation_demo(&graphics,
-                &shape_manager, rot);
+                game.shapes(), game.piece().rot());
 
         }
 
@@ -250,4 +1416,4 @@ async fn main() {
         next_frame().await
     }
 
-}
\ No newline at end of file
+}
@@ -0,0 +1,257 @@
+//! Menus navigable with the keyboard and touch screens
+//!
+//! A menu is a list of items. Action items are activated with
+//! Enter (or a tap), and choice items cycle through their options
+//! with the arrow keys (or a tap on the left or right half of the
+//! screen).
+
+use macroquad::prelude::*;
+
+use crate::graphics::Graphics;
+
+
+/// Something that happened in a menu
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MenuEvent {
+    /// The item at the given index was activated
+    Activated(usize),
+    /// The option of the choice item at the given index changed
+    Changed(usize),
+    /// The menu was closed (Escape)
+    Back,
+}
+
+pub struct MenuItem {
+    label: String,
+    // Empty for action items
+    options: Vec<String>,
+    selected: usize,
+}
+
+impl MenuItem {
+
+    pub fn action(label: &str) -> Self {
+        Self {
+            label: label.to_string(),
+            options: Vec::new(),
+            selected: 0,
+        }
+    }
+
+    pub fn choice(label: &str, options: Vec<String>, selected: usize) -> Self {
+        Self {
+            label: label.to_string(),
+            options,
+            selected,
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// The selected option, or `None` for action items
+    pub fn value(&self) -> Option<&str> {
+        self.options.get(self.selected).map(|s| s.as_str())
+    }
+
+    /// Index of the selected option
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn is_choice(&self) -> bool {
+        !self.options.is_empty()
+    }
+}
+
+pub struct Menu {
+    title: String,
+    items: Vec<MenuItem>,
+    cursor: usize,
+}
+
+impl Menu {
+
+    pub fn new(title: &str, items: Vec<MenuItem>) -> Self {
+        Self {
+            title: title.to_string(),
+            items,
+            cursor: 0,
+        }
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn items(&self) -> &[MenuItem] {
+        &self.items
+    }
+
+    pub fn item(&self, index: usize) -> &MenuItem {
+        &self.items[index]
+    }
+
+    /// Index of the highlighted item
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Highlight the item at the given index (e.g. when the menu is
+    /// rebuilt with new labels)
+    pub fn set_cursor(&mut self, index: usize) {
+        self.cursor = index.min(self.items.len().saturating_sub(1));
+    }
+
+    /// Select an option of the choice item at the given index (the
+    /// last one, if there aren't that many)
+    pub fn select(&mut self, index: usize, option: usize) {
+        let item = &mut self.items[index];
+        item.selected = option.min(item.options.len().saturating_sub(1));
+    }
+
+    /// Replace the options of the choice item at the given index.
+    /// The selected option is kept if it's still there; otherwise
+    /// the one at the same index (or the last one) is selected.
+    pub fn set_options(&mut self, index: usize, options: Vec<String>) {
+        let item = &mut self.items[index];
+        let selected = item.value()
+            .and_then(|value| options.iter().position(|option| option == value))
+            .unwrap_or(item.selected);
+        item.options = options;
+        self.select(index, selected);
+    }
+
+    /// Move the cursor up (`delta` < 0) or down (`delta` > 0),
+    /// wrapping around
+    pub fn move_cursor(&mut self, delta: isize) {
+        let n = self.items.len() as isize;
+        if n > 0 {
+            self.cursor = (self.cursor as isize + delta).rem_euclid(n) as usize;
+        }
+    }
+
+    /// Select the previous (`delta` < 0) or next (`delta` > 0)
+    /// option of the item at the given index, wrapping around
+    ///
+    /// # Returns
+    /// `MenuEvent::Changed` if the item is a choice
+    pub fn change(&mut self, index: usize, delta: isize) -> Option<MenuEvent> {
+        let item = &mut self.items[index];
+        let n = item.options.len() as isize;
+        if n == 0 {
+            return None;
+        }
+
+        item.selected = (item.selected as isize + delta).rem_euclid(n) as usize;
+        Some(MenuEvent::Changed(index))
+    }
+
+    /// Activate the item at the given index: choices move to their
+    /// next option
+    pub fn activate(&mut self, index: usize) -> Option<MenuEvent> {
+        self.cursor = index;
+        if self.items[index].is_choice() {
+            self.change(index, 1)
+        } else {
+            Some(MenuEvent::Activated(index))
+        }
+    }
+
+    /// Process the keyboard and touch input of the current frame
+    pub fn handle_input(&mut self, graphics: &Graphics) -> Option<MenuEvent> {
+
+        for touch in touches() {
+            if touch.phase != TouchPhase::Started {
+                continue;
+            }
+
+            if let Some(index) = graphics.menu_item_at(self, touch.position) {
+                self.cursor = index;
+                if touch.position.x < screen_width() / 2.0 {
+                    return self.change(index, -1)
+                        .or(Some(MenuEvent::Activated(index)));
+                }
+                return self.activate(index);
+            }
+        }
+
+        if is_key_pressed(KeyCode::Up) {
+            self.move_cursor(-1);
+        }
+
+        if is_key_pressed(KeyCode::Down) {
+            self.move_cursor(1);
+        }
+
+        if is_key_pressed(KeyCode::Left) {
+            return self.change(self.cursor, -1);
+        }
+
+        if is_key_pressed(KeyCode::Right) {
+            return self.change(self.cursor, 1);
+        }
+
+        if is_key_pressed(KeyCode::Enter) {
+            return self.activate(self.cursor);
+        }
+
+        if is_key_pressed(KeyCode::Escape) {
+            return Some(MenuEvent::Back);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Menu, MenuEvent, MenuItem};
+
+    fn menu() -> Menu {
+        Menu::new("Test", vec![
+            MenuItem::choice("Level", vec!["1".into(), "2".into(), "3".into()], 0),
+            MenuItem::action("Start"),
+        ])
+    }
+
+    #[test]
+    fn test_menu_cursor() {
+        let mut menu = menu();
+        assert_eq!(0, menu.cursor());
+        menu.move_cursor(1);
+        assert_eq!(1, menu.cursor());
+        menu.move_cursor(1);
+        assert_eq!(0, menu.cursor());
+        menu.move_cursor(-1);
+        assert_eq!(1, menu.cursor());
+        menu.set_cursor(5);
+        assert_eq!(1, menu.cursor());
+    }
+
+    #[test]
+    fn test_menu_items() {
+        let mut menu = menu();
+
+        assert_eq!(Some("1"), menu.item(0).value());
+        assert_eq!(Some(MenuEvent::Changed(0)), menu.change(0, -1));
+        assert_eq!(Some("3"), menu.item(0).value());
+        assert_eq!(Some(MenuEvent::Changed(0)), menu.activate(0));
+        assert_eq!(0, menu.item(0).selected());
+        menu.select(0, 1);
+        assert_eq!(Some("2"), menu.item(0).value());
+        menu.select(0, 7);
+        assert_eq!(Some("3"), menu.item(0).value());
+
+        menu.set_options(0, vec!["0".into(), "3".into()]);
+        assert_eq!(Some("3"), menu.item(0).value());
+        menu.set_options(0, vec!["4".into()]);
+        assert_eq!(Some("4"), menu.item(0).value());
+
+        assert_eq!(None, menu.item(1).value());
+        assert_eq!(None, menu.change(1, 1));
+        assert_eq!(Some(MenuEvent::Activated(1)), menu.activate(1));
+        assert_eq!(1, menu.cursor());
+    }
+}
@@ -0,0 +1,294 @@
+//! The piece that is currently falling down the playfield
+
+use crate::playfield::Playfield;
+use crate::shape::Shape;
+
+
+/// A shape placed on the playfield: its position (row and
+/// column of the top left corner of the shape box) and its
+/// rotation state
+#[derive(Clone)]
+pub struct Piece {
+    shape: Shape,
+    row: usize,
+    col: isize,
+    rot: isize,
+}
+
+impl Piece {
+
+    /// Place a shape at its spawn position: horizontally centered
+    /// and moved down by its row offset, with rotation 0
+    pub fn spawn(shape: Shape, pf: &Playfield) -> Self {
+        let col = (pf.n_cols() / 2) as isize - (shape.width() / 2) as isize;
+        let row = shape.row_offset();
+
+        Self {
+            shape,
+            row,
+            col,
+            rot: 0,
+        }
+    }
+
+    /// Place a shape at the given position and rotation (e.g. to
+    /// restore a saved game)
+    pub fn place(shape: Shape, row: usize, col: isize, rot: isize) -> Self {
+        Self {
+            shape,
+            row,
+            col,
+            rot,
+        }
+    }
+
+    pub fn shape(&self) -> &Shape {
+        &self.shape
+    }
+
+    pub fn row(&self) -> usize {
+        self.row
+    }
+
+    pub fn col(&self) -> isize {
+        self.col
+    }
+
+    pub fn rot(&self) -> isize {
+        self.rot
+    }
+
+    /// Playfield positions (row, column) of the blocks of the piece
+    pub fn cells(&self) -> Vec<(usize, usize)> {
+        let mut cells = Vec::new();
+
+        for i in 0..self.shape.len() {
+            let row = self.shape.row(i);
+            let col = self.shape.col(i);
+
+            if *self.shape.rotate(row, col, self.rot) != 0 {
+                cells.push((self.row + row, (self.col + col as isize) as usize));
+            }
+        }
+
+        cells
+    }
+
+    pub fn collides(&self, pf: &Playfield) -> bool {
+        pf.collides(&self.shape, self.row, self.col, self.rot)
+    }
+
+    /// True if the piece can't move down anymore
+    pub fn grounded(&self, pf: &Playfield) -> bool {
+        pf.collides(&self.shape, self.row + 1, self.col, self.rot)
+    }
+
+    /// Move the piece the given number of columns (negative
+    /// values move it to the left) if it doesn't collide.
+    ///
+    /// # Returns
+    /// True if the piece was moved
+    pub fn shift(&mut self, pf: &Playfield, cols: isize) -> bool {
+        if pf.collides(&self.shape, self.row, self.col + cols, self.rot) {
+            false
+        } else {
+            self.col += cols;
+            true
+        }
+    }
+
+    /// Move the piece one row down if it doesn't collide.
+    ///
+    /// # Returns
+    /// True if the piece was moved
+    pub fn move_down(&mut self, pf: &Playfield) -> bool {
+        if self.grounded(pf) {
+            false
+        } else {
+            self.row += 1;
+            true
+        }
+    }
+
+    /// Rotate the piece clockwise.
+    ///
+    /// # Returns
+    /// The index of the kick offset that was used, or `None` if
+    /// the piece couldn't be rotated
+    pub fn rotate_cw(&mut self, pf: &Playfield) -> Option<usize> {
+        self.rotate_to(pf, (self.rot + 1) % 4)
+    }
+
+    /// Rotate the piece counter-clockwise.
+    ///
+    /// # Returns
+    /// The index of the kick offset that was used, or `None` if
+    /// the piece couldn't be rotated
+    pub fn rotate_ccw(&mut self, pf: &Playfield) -> Option<usize> {
+        self.rotate_to(pf, (self.rot + 3) % 4)
+    }
+
+    /// Rotate the piece 180º, directly from state r to r + 2 using
+    /// the 180º kicks of the shape. The piece is not rotated when
+    /// none of them fits.
+    ///
+    /// # Returns
+    /// The index of the kick offset that was used, or `None` if
+    /// the piece couldn't be rotated
+    pub fn rotate_180(&mut self, pf: &Playfield) -> Option<usize> {
+        self.rotate_to(pf, (self.rot + 2) % 4)
+    }
+
+    /// Rotate the piece to the given state without testing any
+    /// kick, as done by the Initial Rotation System when the piece
+    /// enters the playfield.
+    ///
+    /// # Returns
+    /// True if the piece was rotated
+    pub fn rotate_in_place(&mut self, pf: &Playfield, rot: isize) -> bool {
+        if pf.collides(&self.shape, self.row, self.col, rot) {
+            false
+        } else {
+            self.rot = rot;
+            true
+        }
+    }
+
+    /// Try the kick offsets of the shape, in order, until the
+    /// piece fits in the new rotation state.
+    fn rotate_to(&mut self, pf: &Playfield, rot: isize) -> Option<usize> {
+
+        for (i, (cols, rows_up)) in
+            self.shape.kicks(self.rot, rot).iter().enumerate() {
+
+            // Kicks can't move the shape above the top of the playfield
+            let Some(row) = self.row.checked_add_signed(-rows_up) else {
+                continue;
+            };
+            let col = self.col + cols;
+
+            if !pf.collides(&self.shape, row, col, rot) {
+                self.row = row;
+                self.col = col;
+                self.rot = rot;
+                return Some(i);
+            }
+        }
+
+        None
+    }
+
+    /// Add the piece to the playfield.
+    ///
+    /// # Returns
+    /// The rows of the playfield that were modified, in
+    /// ascending order
+    pub fn lock(&self, pf: &mut Playfield) -> Vec<usize> {
+        pf.add(&self.shape, self.row, self.col, self.rot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::color::BLACK;
+
+    use super::Piece;
+    use crate::playfield::Playfield;
+    use crate::shape::{RotationType, Shape, SRS_180_KICKS};
+
+    fn i_shape() -> Shape {
+        Shape::new(
+            vec![0, 0, 0, 0,
+                 1, 1, 1, 1,
+                 0, 0, 0, 0,
+                 0, 0, 0, 0],
+            4,
+            BLACK,
+            RotationType::SRS,
+        )
+    }
+
+    #[test]
+    fn test_piece_moves() {
+        let pf = Playfield::new();
+        let mut piece = Piece::spawn(i_shape(), &pf);
+
+        assert_eq!(0, piece.row());
+        assert_eq!(4, piece.col());
+        assert_eq!(0, piece.rot());
+
+        // Move to the left wall
+        while piece.shift(&pf, -1) {}
+        assert_eq!(1, piece.col());
+
+        // Drop to the floor: the I is placed on the second row of its box
+        while piece.move_down(&pf) {}
+        assert_eq!(20, piece.row());
+        assert!(piece.grounded(&pf));
+        assert_eq!(vec![(21, 1), (21, 2), (21, 3), (21, 4)], piece.cells());
+    }
+
+    #[test]
+    fn test_piece_rotation_kicks() {
+        let pf = Playfield::new();
+
+        // Vertical I against the left wall can't rotate without kicks
+        let mut piece = Piece::spawn(i_shape(), &pf);
+        assert_eq!(Some(0), piece.rotate_cw(&pf));
+        while piece.shift(&pf, -1) {}
+        assert_eq!(-1, piece.col());
+        assert_eq!(None, piece.rotate_cw(&pf));
+        assert_eq!(1, piece.rot());
+
+        // With kicks, the first offset that fits is used
+        let shape = i_shape().set_kicks(1, 2, vec![(0, 0), (1, 0), (2, 0)]);
+        let mut piece = Piece::spawn(shape, &pf);
+        piece.rotate_cw(&pf);
+        while piece.shift(&pf, -1) {}
+        assert_eq!(Some(2), piece.rotate_cw(&pf));
+        assert_eq!(2, piece.rot());
+        assert_eq!(1, piece.col());
+    }
+
+    #[test]
+    fn test_piece_rotation_180() {
+        let mut pf = Playfield::new();
+
+        // T pointing up, resting on the floor
+        let t = Shape::new(
+            vec![0, 1, 0,
+                 1, 1, 1,
+                 0, 0, 0],
+            3,
+            BLACK,
+            RotationType::SRS,
+        );
+
+        let mut piece = Piece::spawn(t.clone(), &pf);
+        while piece.move_down(&pf) {}
+        assert_eq!(20, piece.row());
+
+        // Without kicks, pointing down would go through the floor
+        assert_eq!(None, piece.rotate_180(&pf));
+        assert_eq!(0, piece.rot());
+
+        // SRS+ kicks move it up one row
+        let t_kicks = t.set_kick_table(&SRS_180_KICKS);
+        let mut piece = Piece::spawn(t_kicks.clone(), &pf);
+        while piece.move_down(&pf) {}
+        assert_eq!(Some(1), piece.rotate_180(&pf));
+        assert_eq!(2, piece.rot());
+        assert_eq!(19, piece.row());
+
+        // All the kicks collide: no rotation. Blocks at both sides
+        // of the T tip only leave room for the current state.
+        let mut piece = Piece::spawn(t_kicks, &pf);
+        while piece.move_down(&pf) {}
+        let block = Shape::new(vec![1], 1, BLACK, RotationType::STILL);
+        pf.add(&block, 20, piece.col(), 0);
+        pf.add(&block, 20, piece.col() + 2, 0);
+        assert_eq!(None, piece.rotate_180(&pf));
+        assert_eq!(0, piece.rot());
+        assert_eq!(20, piece.row());
+    }
+}
@@ -1,8 +1,19 @@
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 use crate::shape::Shape;
 
 
 
+/// With the `serde` feature, playfields are serialized as their rows
+/// without the walls and floor (top row first, hidden rows included):
+/// `{"rows": [[0, 0, 1, ...], ...]}`, with 22 rows of 10 cells where 0
+/// is an empty cell.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde",
+    serde(try_from = "PlayfieldRows", into = "PlayfieldRows"))]
 pub struct Playfield {
     cells: [usize; 276]
 }
@@ -11,6 +22,18 @@ impl Playfield {
 
     const N_COLS: usize = 12;
 
+    /// Columns between the walls
+    pub const WIDTH: usize = Self::N_COLS - 2;
+
+    /// Rows above the floor, hidden rows included
+    pub const HEIGHT: usize = 22;
+
+    /// Rows above the visible area, where the shapes spawn
+    pub const HIDDEN_ROWS: usize = 2;
+
+    /// Value of the garbage cells
+    pub const GARBAGE: usize = 8;
+
     pub fn new() -> Self{
 
         // Set borders at cols 0 and 11
@@ -30,6 +53,38 @@ impl Playfield {
         }
     }
 
+    /// Playfield with the given cells (as returned by `cells`), if
+    /// there are as many as needed and the walls are in place
+    pub fn from_cells(cells: &[usize]) -> Option<Self> {
+        let mut pf = Self::new();
+        if cells.len() != pf.cells.len() {
+            return None;
+        }
+
+        for (cell, value) in pf.cells.iter_mut().zip(cells) {
+            // Walls can't be removed, nor added
+            if (*cell == 99) != (*value == 99) {
+                return None;
+            }
+            *cell = *value;
+        }
+
+        Some(pf)
+    }
+
+    /// All the cells, row by row (walls included)
+    pub fn cells(&self) -> &[usize] {
+        &self.cells
+    }
+
+    /// The cells inside the walls, row by row
+    pub fn rows(&self) -> Vec<Vec<usize>> {
+        self.cells.chunks(Self::N_COLS)
+            .take(self.n_rows() - 1)
+            .map(|row| row[1..Self::N_COLS - 1].to_vec())
+            .collect()
+    }
+
     pub fn n_rows(&self) -> usize  {
         self.cells.len() / Self::N_COLS
     }
@@ -148,7 +203,7 @@ impl Playfield {
     /// # Arguments
     /// * `rows` - The rows to clear in ascending order
     ///
-    pub fn clear_rows(&mut self, rows: &Vec<usize>) {
+    pub fn clear_rows(&mut self, rows: &[usize]) {
 
         // Move the rows down
         // The rows are sorted in ascending order, so we start
@@ -209,7 +264,7 @@ impl Playfield {
 
             // if there are blocks outside the playfield (can happen
             // when trying to rotate next to the border) => collision
-            }  else if pf_col < 0 || pf_col > 11 || pf_row > 22 {
+            }  else if !(0..=11).contains(&pf_col) || pf_row > 22 {
                 collision = true;
                 break;
             }
@@ -225,13 +280,92 @@ impl Playfield {
 
     }
 
+    /// Push all the rows up and add a garbage row at the bottom,
+    /// full except for the given column.
+    ///
+    /// # Arguments
+    /// * `hole` - The empty column of the garbage row (1 to 10)
+    ///
+    /// # Returns
+    /// True if some blocks were pushed out of the top of the
+    /// playfield (garbage top out)
+    pub fn add_garbage(&mut self, hole: usize) -> bool {
+
+        let overflow = !self.is_empty(0);
+        let bottom = self.n_rows() - 2;
+
+        for row in 0..bottom {
+            for col in 1..Self::N_COLS - 1 {
+                self.cells[self.cell_idx(row, col)] =
+                    self.cells[self.cell_idx(row + 1, col)];
+            }
+        }
+
+        for col in 1..Self::N_COLS - 1 {
+            let cell_idx = self.cell_idx(bottom, col);
+            self.cells[cell_idx] = if col == hole { 0 } else { Self::GARBAGE };
+        }
+
+        overflow
+    }
+
     fn cell_idx(&self, row: usize, col: usize) -> usize {
         (row * Self::N_COLS) + col
     }
 
 }
 
+impl Default for Playfield {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Serialized form of the playfield
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlayfieldRows {
+    rows: Vec<Vec<usize>>,
+}
+
+#[cfg(feature = "serde")]
+impl From<Playfield> for PlayfieldRows {
+    fn from(pf: Playfield) -> Self {
+        Self { rows: pf.rows() }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<PlayfieldRows> for Playfield {
+    type Error = String;
+
+    fn try_from(rows: PlayfieldRows) -> Result<Self, Self::Error> {
+        let mut pf = Playfield::new();
+        let n_cols = Playfield::N_COLS - 2;
+        if rows.rows.len() != pf.n_rows() - 1 {
+            return Err(format!("expected {} rows, got {}",
+                pf.n_rows() - 1, rows.rows.len()));
+        }
+
+        for (row, cells) in rows.rows.iter().enumerate() {
+            if cells.len() != n_cols {
+                return Err(format!("row {} has {} cells, expected {}",
+                    row, cells.len(), n_cols));
+            }
+            if cells.contains(&99) {
+                return Err(format!("row {} has wall cells", row));
+            }
+            let start = pf.cell_idx(row, 1);
+            pf.cells[start..start + n_cols].copy_from_slice(cells);
+        }
+
+        Ok(pf)
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use macroquad::color::BLACK;
 
@@ -569,7 +703,7 @@ mod tests {
             assert_eq!(1, pf.get_cell(21, i));
         }
 
-        pf.clear_rows(&vec![18, 19, 20, 21]);
+        pf.clear_rows(&[18, 19, 20, 21]);
 
         for i in 1..pf.n_cols() - 1 {
             assert_eq!(0, pf.get_cell(18, i));
@@ -605,7 +739,7 @@ mod tests {
             assert_eq!(1, pf.get_cell(21, i));
         }
 
-        pf.clear_rows(&vec![20, 21]);
+        pf.clear_rows(&[20, 21]);
         
         for i in 1..pf.n_cols() - 1 {
             assert_eq!(0, pf.get_cell(19, i));
@@ -644,7 +778,7 @@ mod tests {
             assert_eq!(1, pf.get_cell(21, i));
         }
 
-        pf.clear_rows(&vec![18, 19, 21]);
+        pf.clear_rows(&[18, 19, 21]);
 
         for i in 1..pf.n_cols() - 1 {
             assert_eq!(0, pf.get_cell(18, i));
@@ -684,7 +818,7 @@ mod tests {
             assert_eq!(1, pf.get_cell(21, i));
         }
 
-        pf.clear_rows(&vec![19, 21]);
+        pf.clear_rows(&[19, 21]);
 
         for i in 1..pf.n_cols() - 1 {
             assert_eq!(0, pf.get_cell(18, i));
@@ -725,7 +859,7 @@ mod tests {
             assert_eq!(1, pf.get_cell(21, i));
         }
 
-        pf.clear_rows(&vec![18, 21]);
+        pf.clear_rows(&[18, 21]);
 
         for i in 1..pf.n_cols() - 1 {
             assert_eq!(0, pf.get_cell(18, i));
@@ -766,7 +900,7 @@ mod tests {
             assert_eq!(1, pf.get_cell(21, i));
         }
 
-        pf.clear_rows(&vec![21]);
+        pf.clear_rows(&[21]);
 
         for i in 1..pf.n_cols() - 1 {
             assert_eq!(0, pf.get_cell(18, i));
@@ -807,4 +941,60 @@ mod tests {
 
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_playfield_add_garbage() {
+        let mut pf = Playfield::new();
+        let block = Shape::new(vec![1], 1, BLACK, RotationType::STILL);
+
+        pf.add(&block, 21, 5, 0);
+        assert_eq!(false, pf.add_garbage(3));
+        assert_eq!(1, pf.get_cell(20, 5));
+        assert_eq!(0, pf.get_cell(21, 3));
+        assert_eq!(Playfield::GARBAGE, pf.get_cell(21, 5));
+        assert_eq!(Playfield::GARBAGE, pf.get_cell(21, 1));
+        assert_eq!(99, pf.get_cell(21, 0));
+        assert_eq!(99, pf.get_cell(22, 3));
+
+        // Blocks pushed out of the top
+        pf.add(&block, 0, 1, 0);
+        assert_eq!(true, pf.add_garbage(3));
+    }
+
+    #[test]
+    fn test_playfield_from_cells() {
+        let mut pf = Playfield::new();
+        let block = Shape::new(vec![1], 1, BLACK, RotationType::STILL);
+        pf.add(&block, 21, 5, 0);
+
+        let restored = Playfield::from_cells(pf.cells()).unwrap();
+        assert_eq!(pf.cells(), restored.cells());
+
+        assert!(Playfield::from_cells(&pf.cells()[1..]).is_none());
+        let mut cells = pf.cells().to_vec();
+        cells[0] = 0;
+        assert!(Playfield::from_cells(&cells).is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_playfield_serde() {
+        let mut pf = Playfield::new();
+        let block = Shape::new(vec![3], 1, BLACK, RotationType::STILL);
+        pf.add(&block, 21, 1, 0);
+
+        let json = serde_json::to_string(&pf).unwrap();
+        assert!(json.starts_with(r#"{"rows":[[0,0,0,0,0,0,0,0,0,0],"#));
+        assert!(json.ends_with(r#"[3,0,0,0,0,0,0,0,0,0]]}"#));
+        let restored: Playfield = serde_json::from_str(&json).unwrap();
+        assert_eq!(pf.cells(), restored.cells());
+
+        // Rows must fit inside the walls
+        let mut rows = pf.rows();
+        rows[4].push(0);
+        let json = serde_json::json!({ "rows": rows }).to_string();
+        assert!(serde_json::from_str::<Playfield>(&json).is_err());
+        rows.truncate(4);
+        let json = serde_json::json!({ "rows": rows }).to_string();
+        assert!(serde_json::from_str::<Playfield>(&json).is_err());
+    }
+}
@@ -0,0 +1,321 @@
+//! Polyomino piece sets
+//!
+//! Generates all the one-sided polyominoes (distinct up to rotation,
+//! mirror images are different pieces) made of a given number of
+//! blocks and turns them into shapes with SRS-like rotation, so games
+//! can use triominoes, pentominoes or any mix of piece sizes.
+
+use std::collections::BTreeSet;
+
+use macroquad::{color::*, prelude::Color};
+
+use crate::definition::RotationDefinition;
+use crate::shape::{RotationType, Shape, ShapeBuilder, SRS_180_KICKS, SRS_I_KICKS,
+    SRS_KICKS};
+
+
+/// Colors assigned to the generated shapes, in order
+const PALETTE: [Color; 18] = [
+    PINK, BLUE, GREEN, ORANGE, RED, PURPLE, YELLOW, SKYBLUE, LIME,
+    MAROON, GOLD, VIOLET, BEIGE, MAGENTA, BROWN, DARKGREEN, DARKBLUE,
+    LIGHTGRAY,
+];
+
+/// A polyomino as a list of (row, col) blocks. Blocks are sorted
+/// and the top and left-most blocks are at row 0 and col 0.
+pub type Cells = Vec<(usize, usize)>;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PieceSet {
+    /// 2 pieces made of 3 blocks
+    TRIOMINOES,
+    /// 7 pieces made of 4 blocks
+    TETROMINOES,
+    /// 18 pieces made of 5 blocks
+    PENTOMINOES,
+    /// Triominoes, tetrominoes and pentominoes together
+    MIXED,
+}
+
+impl PieceSet {
+    pub const ALL: [PieceSet; 4] = [
+        PieceSet::TRIOMINOES,
+        PieceSet::TETROMINOES,
+        PieceSet::PENTOMINOES,
+        PieceSet::MIXED,
+    ];
+
+    /// Number of blocks of the pieces included in the set
+    pub fn sizes(&self) -> Vec<usize> {
+        match self {
+            PieceSet::TRIOMINOES => vec![3],
+            PieceSet::TETROMINOES => vec![4],
+            PieceSet::PENTOMINOES => vec![5],
+            PieceSet::MIXED => vec![3, 4, 5],
+        }
+    }
+
+    /// Return the piece set with the given name, if any
+    pub fn from_name(name: &str) -> Option<PieceSet> {
+        Self::ALL.into_iter().find(|set| set.name() == name)
+    }
+
+    /// Wrap the piece set in a rotation definition, so it can be
+    /// used with `RotationSystem::CUSTOM`
+    pub fn definition(&self) -> RotationDefinition {
+        RotationDefinition::new(self.name(), None, self.build_shapes())
+    }
+}
+
+impl ShapeBuilder for PieceSet {
+
+    fn name(&self) -> &str {
+        match self {
+            PieceSet::TRIOMINOES => "Triominoes",
+            PieceSet::TETROMINOES => "Tetrominoes",
+            PieceSet::PENTOMINOES => "Pentominoes",
+            PieceSet::MIXED => "Mixed",
+        }
+    }
+
+    fn build_shapes(&self) -> Vec<Shape> {
+        self.sizes().into_iter()
+            .flat_map(one_sided)
+            .enumerate()
+            .map(|(i, cells)| to_shape(&cells, PALETTE[i % PALETTE.len()]))
+            .collect()
+    }
+}
+
+
+/// Enumerate all the one-sided polyominoes made of `n` blocks, in
+/// their spawn orientation (see `spawn_orientation`).
+///
+/// Polyominoes are grown one block at a time from the ones of the
+/// previous size, keeping a single (canonical) rotation of each
+/// of them to discard duplicates.
+pub fn one_sided(n: usize) -> Vec<Cells> {
+
+    if n == 0 {
+        return Vec::new();
+    }
+
+    let mut polyominoes: BTreeSet<Cells> = BTreeSet::from([vec![(0, 0)]]);
+
+    for _ in 1..n {
+        let mut grown: BTreeSet<Cells> = BTreeSet::new();
+
+        for cells in &polyominoes {
+            for &(row, col) in cells {
+                for (d_row, d_col) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+
+                    let block = (row as isize + d_row, col as isize + d_col);
+                    let mut blocks: Vec<(isize, isize)> = cells.iter()
+                        .map(|&(r, c)| (r as isize, c as isize))
+                        .collect();
+
+                    if blocks.contains(&block) {
+                        continue;
+                    }
+                    blocks.push(block);
+
+                    grown.insert(canonical(&normalize(&blocks)));
+                }
+            }
+        }
+
+        polyominoes = grown;
+    }
+
+    polyominoes.iter().map(spawn_orientation).collect()
+}
+
+/// Choose the orientation a polyomino spawns in: flat (at least
+/// as wide as tall) and with as many blocks as possible on its
+/// bottom row, so it rests on its flat side like SRS shapes.
+pub fn spawn_orientation(cells: &Cells) -> Cells {
+    rotations(cells).into_iter()
+        .filter(|r| size(r).1 >= size(r).0)
+        .max_by(|a, b| {
+            bottom_blocks(a).cmp(&bottom_blocks(b))
+                // prefer the smallest one on ties, to be deterministic
+                .then_with(|| b.cmp(a))
+        })
+        .unwrap_or_else(|| cells.clone())
+}
+
+/// Build a shape from a polyomino. The shape box is the smallest
+/// square that holds the polyomino, which is centered in it (top
+/// and left aligned when it can't be centered exactly) so it
+/// rotates around the center of the box as in SRS. The row offset
+/// spawns the shape on the hidden rows, right above the visible
+/// part of the playfield.
+///
+/// Shapes in a 3x3 box get the SRS kicks of J, L, S, T and Z, and
+/// shapes in a 4x4 box those of I. Other box sizes have no SRS
+/// table, so their 90º rotations only test the current position.
+/// All of them get the SRS+ 180º kicks.
+pub fn to_shape(cells: &Cells, color: Color) -> Shape {
+    let (height, width) = size(cells);
+    let box_width = height.max(width);
+    let top = (box_width - height) / 2;
+    let left = (box_width - width) / 2;
+
+    let mut m: Vec<usize> = vec![0; box_width * box_width];
+    for (row, col) in cells {
+        m[((top + row) * box_width) + left + col] = 1;
+    }
+
+    let shape = Shape::new(m, box_width, color, RotationType::SRS)
+        .set_row_offset(2_usize.saturating_sub(top + height));
+    let shape = match box_width {
+        3 => shape.set_kick_table(&SRS_KICKS),
+        4 => shape.set_kick_table(&SRS_I_KICKS),
+        _ => shape,
+    };
+    shape.set_kick_table(&SRS_180_KICKS)
+}
+
+/// (height, width) of a normalized polyomino
+fn size(cells: &Cells) -> (usize, usize) {
+    let height = cells.iter().map(|(r, _)| r + 1).max().unwrap_or(0);
+    let width = cells.iter().map(|(_, c)| c + 1).max().unwrap_or(0);
+    (height, width)
+}
+
+fn bottom_blocks(cells: &Cells) -> usize {
+    let (height, _) = size(cells);
+    cells.iter().filter(|(r, _)| r + 1 == height).count()
+}
+
+/// Move the blocks so the top and left-most ones are at row 0
+/// and col 0, and sort them
+fn normalize(blocks: &[(isize, isize)]) -> Cells {
+    let min_row = blocks.iter().map(|(r, _)| *r).min().unwrap_or(0);
+    let min_col = blocks.iter().map(|(_, c)| *c).min().unwrap_or(0);
+
+    let mut cells: Cells = blocks.iter()
+        .map(|(r, c)| ((r - min_row) as usize, (c - min_col) as usize))
+        .collect();
+    cells.sort();
+    cells
+}
+
+/// The 4 rotations of a polyomino (0º, 90º, 180º and 270º clockwise)
+fn rotations(cells: &Cells) -> Vec<Cells> {
+    let mut rotations = vec![cells.clone()];
+
+    for i in 1..4 {
+        let (height, _) = size(&rotations[i - 1]);
+        let blocks: Vec<(isize, isize)> = rotations[i - 1].iter()
+            .map(|&(r, c)| (c as isize, (height - 1 - r) as isize))
+            .collect();
+        rotations.push(normalize(&blocks));
+    }
+
+    rotations
+}
+
+/// The smallest of the rotations of a polyomino, used to
+/// identify it regardless of its orientation
+fn canonical(cells: &Cells) -> Cells {
+    rotations(cells).into_iter().min().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::color::BLACK;
+
+    use super::{one_sided, spawn_orientation, to_shape, PieceSet};
+    use crate::shape::{ShapeBuilder, SRS_180_KICKS, SRS_I_KICKS, SRS_KICKS};
+
+    #[test]
+    fn test_one_sided_count() {
+        assert_eq!(0, one_sided(0).len());
+        assert_eq!(1, one_sided(1).len());
+        assert_eq!(1, one_sided(2).len());
+        assert_eq!(2, one_sided(3).len());
+        assert_eq!(7, one_sided(4).len());
+        assert_eq!(18, one_sided(5).len());
+        assert_eq!(60, one_sided(6).len());
+    }
+
+    #[test]
+    fn test_spawn_orientation() {
+        // T pointing down spawns pointing up (flat side down)
+        let t = vec![(0, 0), (0, 1), (0, 2), (1, 1)];
+        assert_eq!(vec![(0, 1), (1, 0), (1, 1), (1, 2)], spawn_orientation(&t));
+
+        // Vertical I spawns horizontal
+        let i = vec![(0, 0), (1, 0), (2, 0), (3, 0)];
+        assert_eq!(vec![(0, 0), (0, 1), (0, 2), (0, 3)], spawn_orientation(&i));
+    }
+
+    #[test]
+    fn test_to_shape() {
+        let cells = |shape: &crate::shape::Shape| -> Vec<usize> {
+            (0..shape.len()).map(|i| *shape.rotate(shape.row(i), shape.col(i), 0))
+                .collect()
+        };
+
+        // I is centered in a 4x4 box, on the second row
+        let i = to_shape(&vec![(0, 0), (0, 1), (0, 2), (0, 3)], BLACK);
+        assert_eq!(4, i.width());
+        assert_eq!(vec![0, 0, 0, 0,
+                        1, 1, 1, 1,
+                        0, 0, 0, 0,
+                        0, 0, 0, 0], cells(&i));
+        assert_eq!(0, i.row_offset());
+
+        // T is top aligned in a 3x3 box
+        let t = to_shape(&vec![(0, 1), (1, 0), (1, 1), (1, 2)], BLACK);
+        assert_eq!(vec![0, 1, 0,
+                        1, 1, 1,
+                        0, 0, 0], cells(&t));
+        assert_eq!(0, t.row_offset());
+
+        // Domino spawns on the last hidden row
+        let d = to_shape(&vec![(0, 0), (0, 1)], BLACK);
+        assert_eq!(vec![1, 1,
+                        0, 0], cells(&d));
+        assert_eq!(1, d.row_offset());
+
+        assert_eq!(&[(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)], t.kicks(0, 1));
+        assert_eq!(&[(0, 0), (1, 0), (1, 2), (1, 1), (0, 2), (0, 1)], t.kicks(1, 3));
+    }
+
+    #[test]
+    fn test_kick_tables() {
+        // 3x3 boxes use the J, L, S, T and Z table, 4x4 boxes the I
+        // one, and the rest only test the current position
+        let t = to_shape(&vec![(0, 1), (1, 0), (1, 1), (1, 2)], BLACK);
+        assert_eq!(&SRS_KICKS[0].2, t.kicks(0, 1));
+        let i = to_shape(&vec![(0, 0), (0, 1), (0, 2), (0, 3)], BLACK);
+        assert_eq!(&SRS_I_KICKS[0].2, i.kicks(0, 1));
+
+        let monomino = to_shape(&vec![(0, 0)], BLACK);
+        let domino = to_shape(&vec![(0, 0), (0, 1)], BLACK);
+        let pentomino = to_shape(&vec![(0, 0), (0, 1), (0, 2), (0, 3), (0, 4)], BLACK);
+        for shape in [&monomino, &domino, &pentomino] {
+            assert_eq!(&[(0, 0)], shape.kicks(0, 1));
+            assert_eq!(&[(0, 0)], shape.kicks(3, 2));
+            assert_eq!(&SRS_180_KICKS[0].2, shape.kicks(0, 2));
+        }
+    }
+
+    #[test]
+    fn test_piece_sets() {
+        assert_eq!(2, PieceSet::TRIOMINOES.build_shapes().len());
+        assert_eq!(7, PieceSet::TETROMINOES.build_shapes().len());
+        assert_eq!(18, PieceSet::PENTOMINOES.build_shapes().len());
+        assert_eq!(27, PieceSet::MIXED.build_shapes().len());
+
+        for set in PieceSet::ALL {
+            assert_eq!(Some(set), PieceSet::from_name(set.name()));
+        }
+
+        let definition = PieceSet::PENTOMINOES.definition();
+        assert_eq!("Pentominoes", definition.name());
+        assert_eq!(18, definition.shapes().len());
+    }
+}
@@ -0,0 +1,393 @@
+//! Game recording and deterministic replays
+//!
+//! Games advance in fixed ticks (`game::TICK`) and deal their shapes
+//! with a seeded generator, so a game can be reproduced from its
+//! seed, its settings and the input of every tick. Replays only keep
+//! the ticks where the input changed.
+//!
+//! Replays are saved as TOML files:
+//!
+//! ```toml
+//! version = 1
+//! seed = 1718031234567
+//! rotation_system = "SRS"
+//! randomizer = "Bag"
+//! start_level = 1
+//! ticks = 3600
+//!
+//! [ruleset]
+//! hold = true
+//! # ...
+//!
+//! [handling]
+//! das = 0.1
+//! # ...
+//!
+//! [[events]]
+//! tick = 12
+//! down = ["MoveLeft"]     # actions held down from this tick on
+//!
+//! [[events]]
+//! tick = 30
+//! pressed = ["HardDrop"]  # actions pressed this tick
+//! up = ["MoveLeft"]       # actions released this tick
+//! ```
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::Game;
+use crate::handling::Handling;
+use crate::input::{Action, Input};
+use crate::rsg::Randomizer;
+use crate::ruleset::Ruleset;
+use crate::shape::{RotationSystem, ShapeBuilder};
+
+
+/// Version of the replay files written by this version of the game
+pub const VERSION: u32 = 1;
+
+/// Input that changed in a tick
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Event {
+    pub tick: u64,
+    /// Actions pressed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pressed: Vec<Action>,
+    /// Actions held down from this tick on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub down: Vec<Action>,
+    /// Actions released
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub up: Vec<Action>,
+    /// Columns moved directly (touch gestures)
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub shift: isize,
+    /// Rows dropped directly (touch gestures)
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub drops: usize,
+}
+
+fn is_zero<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// A recorded game
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Replay {
+    pub version: u32,
+    pub seed: u64,
+    /// Name of the rotation system
+    pub rotation_system: String,
+    pub randomizer: Randomizer,
+    pub start_level: usize,
+    /// Duration of the game, in ticks
+    pub ticks: u64,
+    pub ruleset: Ruleset,
+    pub handling: Handling,
+    #[serde(default)]
+    pub events: Vec<Event>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReplayError {
+    /// The file couldn't be read or written
+    Io(String),
+    /// The file isn't a valid replay
+    Parse(String),
+    /// The file was written by a newer version of the game
+    Version(u32),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(message) =>
+                write!(f, "can't access replay: {}", message),
+            ReplayError::Parse(message) =>
+                write!(f, "invalid replay: {}", message),
+            ReplayError::Version(version) =>
+                write!(f, "unsupported replay version {} (newest is {})",
+                    version, VERSION),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl Replay {
+
+    /// Parse a replay file
+    pub fn from_toml(src: &str) -> Result<Self, ReplayError> {
+        let replay: Replay = toml::from_str(src)
+            .map_err(|e| ReplayError::Parse(e.to_string()))?;
+
+        if replay.version > VERSION {
+            return Err(ReplayError::Version(replay.version));
+        }
+
+        Ok(replay)
+    }
+
+    pub fn to_toml(&self) -> Result<String, ReplayError> {
+        toml::to_string(self).map_err(|e| ReplayError::Parse(e.to_string()))
+    }
+
+    /// Load a replay file (natively or on the web)
+    pub async fn load(path: &str) -> Result<Self, ReplayError> {
+        let src = macroquad::file::load_string(path).await
+            .map_err(|e| ReplayError::Io(e.to_string()))?;
+
+        Self::from_toml(&src)
+    }
+
+    /// Save the replay to a file, creating its directory if needed
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: &str) -> Result<(), ReplayError> {
+        let path = std::path::Path::new(path);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| ReplayError::Io(e.to_string()))?;
+        }
+
+        std::fs::write(path, self.to_toml()?)
+            .map_err(|e| ReplayError::Io(e.to_string()))
+    }
+
+    /// Set up the game as it was when the replay was recorded
+    ///
+    /// # Arguments
+    /// * `game` - The game to restart
+    /// * `rotation_system` - The rotation system named in the replay
+    pub fn start(&self, game: &mut Game, rotation_system: RotationSystem) {
+        game.shapes_mut().update_rotation_system(rotation_system);
+        game.shapes_mut().update_randomizer(self.randomizer);
+        game.set_ruleset(self.ruleset.clone());
+        game.set_start_level(self.start_level);
+        game.set_handling(self.handling.clone());
+        game.restart_with_seed(self.seed);
+    }
+}
+
+/// Records the input of every tick of a game
+pub struct Recorder {
+    replay: Replay,
+    // Actions held down in the last tick
+    down: Vec<Action>,
+}
+
+impl Recorder {
+
+    /// Start recording a game that was just started
+    pub fn new(game: &Game) -> Self {
+        Self {
+            replay: Replay {
+                version: VERSION,
+                seed: game.seed(),
+                rotation_system: game.shapes().current_rotation_system()
+                    .name().to_string(),
+                randomizer: game.shapes().current_randomizer(),
+                start_level: game.stats().level,
+                ticks: 0,
+                ruleset: game.ruleset().clone(),
+                handling: game.handling().clone(),
+                events: Vec::new(),
+            },
+            down: Vec::new(),
+        }
+    }
+
+    /// Record the input of the next tick
+    pub fn record(&mut self, input: &Input) {
+        let event = Event {
+            tick: self.replay.ticks,
+            pressed: input.pressed().to_vec(),
+            down: input.down().iter()
+                .filter(|action| !self.down.contains(action))
+                .copied().collect(),
+            up: self.down.iter()
+                .filter(|action| !input.down().contains(action))
+                .copied().collect(),
+            shift: input.shift(),
+            drops: input.drops(),
+        };
+
+        if event != (Event { tick: event.tick, ..Default::default() }) {
+            self.replay.events.push(event);
+        }
+
+        self.down = input.down().to_vec();
+        self.replay.ticks += 1;
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+}
+
+/// Plays back the input recorded in a replay, tick by tick
+#[derive(Clone)]
+pub struct Player {
+    replay: Replay,
+    tick: u64,
+    // Next event to play
+    event: usize,
+    input: Input,
+}
+
+impl Player {
+
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            replay,
+            tick: 0,
+            event: 0,
+            input: Input::default(),
+        }
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    /// Ticks already played
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Input of the last tick played
+    pub fn input(&self) -> &Input {
+        &self.input
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.tick >= self.replay.ticks
+    }
+
+    /// Input of the next tick, or `None` when the replay is over
+    pub fn next_input(&mut self) -> Option<&Input> {
+        if self.is_over() {
+            return None;
+        }
+
+        self.input.consume();
+
+        if let Some(event) = self.replay.events.get(self.event)
+            && event.tick == self.tick {
+            for action in &event.pressed {
+                self.input.press(*action);
+            }
+            for action in &event.down {
+                self.input.hold(*action);
+            }
+            for action in &event.up {
+                self.input.release(*action);
+            }
+            self.input.add_shift(event.shift);
+            self.input.add_drops(event.drops);
+            self.event += 1;
+        }
+
+        self.tick += 1;
+        Some(&self.input)
+    }
+
+    /// Play the next tick of the replay on the game
+    ///
+    /// # Returns
+    /// False if the replay is over
+    pub fn play(&mut self, game: &mut Game) -> bool {
+        match self.next_input() {
+            Some(input) => {
+                game.tick(input);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Player, Recorder, Replay, ReplayError, VERSION};
+    use crate::game::Game;
+    use crate::input::{Action, Input};
+    use crate::rsg::Randomizer;
+    use crate::ruleset::Ruleset;
+    use crate::shape::RotationSystem;
+
+    /// Play a game pressing and holding some actions
+    fn play(game: &mut Game, recorder: &mut Recorder) {
+        for tick in 0..2000 {
+            let mut input = Input::default();
+            match tick % 90 {
+                0 => input.press(Action::RotateCw),
+                10..=20 => input.hold(Action::MoveLeft),
+                30 if tick % 180 == 30 => input.add_shift(3),
+                40 => input.press(Action::Hold),
+                50..=55 => input.hold(Action::SoftDrop),
+                60 => input.press(Action::HardDrop),
+                _ => {}
+            }
+
+            if game.is_over() {
+                break;
+            }
+            recorder.record(&input);
+            game.tick(&input);
+        }
+    }
+
+    fn cells(game: &Game) -> Vec<usize> {
+        let pf = game.playfield();
+        (0..pf.n_rows())
+            .flat_map(|row| (0..pf.n_cols()).map(move |col| pf.get_cell(row, col)))
+            .collect()
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        game.shapes_mut().update_randomizer(Randomizer::Bag);
+        game.restart_with_seed(1234);
+
+        let mut recorder = Recorder::new(&game);
+        play(&mut game, &mut recorder);
+
+        let replay = Replay::from_toml(&recorder.replay().to_toml().unwrap())
+            .unwrap();
+        assert_eq!(recorder.replay(), &replay);
+        assert_eq!(1234, replay.seed);
+        assert_eq!("SRS", replay.rotation_system);
+        assert!(replay.events.len() < replay.ticks as usize);
+
+        // A different game is restarted with the replay settings
+        let mut replayed = Game::new(RotationSystem::NES, Ruleset::classic());
+        replay.start(&mut replayed, RotationSystem::SRS);
+
+        let mut player = Player::new(replay);
+        while player.play(&mut replayed) {}
+
+        assert!(player.is_over());
+        assert!(game.stats().pieces > 10);
+        assert_eq!(game.stats(), replayed.stats());
+        assert_eq!(cells(&game), cells(&replayed));
+        assert_eq!(game.piece().cells(), replayed.piece().cells());
+    }
+
+    #[test]
+    fn test_replay_version() {
+        let game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        let mut replay = Recorder::new(&game).replay().clone();
+        assert_eq!(VERSION, replay.version);
+
+        replay.version = VERSION + 1;
+        assert_eq!(Err(ReplayError::Version(VERSION + 1)),
+            Replay::from_toml(&replay.to_toml().unwrap()));
+        assert!(matches!(Replay::from_toml("version = 1"),
+            Err(ReplayError::Parse(_))));
+    }
+}
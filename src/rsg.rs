@@ -1,7 +1,73 @@
-/// Random Shape Generators
+//! Random Shape Generators
+//!
+//! With the `serde` feature, the generators (`TSR`, `Classic` and
+//! `Bag`) serialize their full state, e.g.
+//! `{"next": 3, "n_shapes": 7, "rng": "5851f42d4c957f2d"}` (plus the
+//! shapes left in the bag for `Bag`), so a deserialized generator
+//! deals the same shapes as the original one.
+
+use macroquad::miniquad;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+
+/// A new seed for a random number generator, taken from the clock
+pub fn random_seed() -> u64 {
+    (miniquad::date::now() * 1000.0) as u64
+}
+
+/// Seeded pseudo random number generator (PCG, as used by macroquad)
 ///
+/// Each shape generator owns one, so the sequence of shapes only
+/// depends on the seed (the global macroquad generator is also used
+/// by animations).
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Rng {
+    #[cfg_attr(feature = "serde", serde(with = "hex"))]
+    state: u64,
+}
+
+impl Rng {
+
+    const MULTIPLIER: u64 = 6364136223846793005;
+    const INCREMENT: u64 = 1442695040888963407;
+
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Self { state: 0 };
+        rng.rand();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.rand();
+
+        rng
+    }
+
+    /// Internal state, to restore the generator later
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    /// Generator that goes on from a state returned by `state`
+    pub fn from_state(state: u64) -> Self {
+        Self { state }
+    }
 
-use macroquad::{miniquad, prelude::rand};
+    /// A random number between 0 and `u32::MAX`
+    pub fn rand(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(Self::MULTIPLIER)
+            .wrapping_add(Self::INCREMENT);
+
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    /// A random number between `low` (included) and `high` (excluded)
+    pub fn gen_range(&mut self, low: usize, high: usize) -> usize {
+        low + (self.rand() as usize % (high - low).max(1))
+    }
+}
 
 
 pub trait RSG {
@@ -19,30 +85,92 @@ pub trait RSG {
     /// in the previous call to get(): it will
     /// also be returned by the next call to get()
     fn next(&self) -> usize;
+
+    /// Copy the generator with its current state, so the copy
+    /// generates the same shapes
+    fn clone_box(&self) -> Box<dyn RSG>;
+
+    /// The current state of the generator, to restore it later
+    fn state(&self) -> RsgState;
+
+    /// Go on generating shapes from a state returned by `state`
+    /// (of a generator of the same kind and number of shapes)
+    fn set_state(&mut self, state: &RsgState);
+}
+
+/// State of a random shape generator: the next shape, the state of
+/// its random number generator and the shapes left in its bag (only
+/// used by `Bag`)
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RsgState {
+    pub next: usize,
+    #[cfg_attr(feature = "serde", serde(with = "hex"))]
+    pub rng: u64,
+    #[cfg_attr(feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub bag: Vec<usize>,
 }
 
+impl Clone for Box<dyn RSG> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+
+/// Random number generator states are saved as hexadecimal strings,
+/// since they don't fit in TOML integers (signed, 64-bit), nor in
+/// JavaScript numbers
+#[cfg(feature = "serde")]
+mod hex {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &u64, serializer: S)
+        -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:016x}", value))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D)
+        -> Result<u64, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        u64::from_str_radix(&hex, 16).map_err(D::Error::custom)
+    }
+}
 
 /// Truly Pseudo Random Shape Generator
 /// Generates a random shape number
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TSR {
     next: usize,
     n_shapes: usize,
+    rng: Rng,
 }
 
 impl TSR {
 
-    /// Returns a Truly Pseudo Random Shape Generator
-    /// 
+    /// Returns a Truly Pseudo Random Shape Generator, seeded from the clock
+    ///
     /// # Arguments
     /// * `n_shapes` - The total number of shapes
     pub fn new(n_shapes: usize) -> Self {
+        Self::with_seed(n_shapes, random_seed())
+    }
 
-        // Initialize random number generator
-        rand::srand(miniquad::date::now() as u64);
+    /// Returns a Truly Pseudo Random Shape Generator that always
+    /// generates the same shapes for the same seed
+    ///
+    /// # Arguments
+    /// * `n_shapes` - The total number of shapes
+    /// * `seed` - Seed of the random number generator
+    pub fn with_seed(n_shapes: usize, seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
 
-        Self { 
-            next: rand::gen_range(0, n_shapes),
+        Self {
+            next: rng.gen_range(0, n_shapes),
             n_shapes,
+            rng,
         }
     }
 }
@@ -50,7 +178,7 @@ impl TSR {
 impl RSG for TSR {
     fn get(&mut self) -> usize {
         let current = self.next;
-        self.next = rand::gen_range(0, self.n_shapes);
+        self.next = self.rng.gen_range(0, self.n_shapes);
 
         current
     }
@@ -58,4 +186,299 @@ impl RSG for TSR {
     fn next(&self) -> usize {
         self.next
     }
-}
\ No newline at end of file
+
+    fn clone_box(&self) -> Box<dyn RSG> {
+        Box::new(self.clone())
+    }
+
+    fn state(&self) -> RsgState {
+        RsgState { next: self.next, rng: self.rng.state(), bag: Vec::new() }
+    }
+
+    fn set_state(&mut self, state: &RsgState) {
+        self.next = state.next;
+        self.rng = Rng::from_state(state.rng);
+    }
+}
+
+/// Classic Shape Generator
+/// Rolls again (only once) when the same shape comes up twice in
+/// a row, as done by the NES version
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Classic {
+    next: usize,
+    n_shapes: usize,
+    rng: Rng,
+}
+
+impl Classic {
+
+    /// Returns a Classic Shape Generator, seeded from the clock
+    ///
+    /// # Arguments
+    /// * `n_shapes` - The total number of shapes
+    pub fn new(n_shapes: usize) -> Self {
+        Self::with_seed(n_shapes, random_seed())
+    }
+
+    /// Returns a Classic Shape Generator that always
+    /// generates the same shapes for the same seed
+    ///
+    /// # Arguments
+    /// * `n_shapes` - The total number of shapes
+    /// * `seed` - Seed of the random number generator
+    pub fn with_seed(n_shapes: usize, seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+
+        Self {
+            next: rng.gen_range(0, n_shapes),
+            n_shapes,
+            rng,
+        }
+    }
+}
+
+impl RSG for Classic {
+    fn get(&mut self) -> usize {
+        let current = self.next;
+        self.next = self.rng.gen_range(0, self.n_shapes);
+        if self.next == current {
+            self.next = self.rng.gen_range(0, self.n_shapes);
+        }
+
+        current
+    }
+
+    fn next(&self) -> usize {
+        self.next
+    }
+
+    fn clone_box(&self) -> Box<dyn RSG> {
+        Box::new(self.clone())
+    }
+
+    fn state(&self) -> RsgState {
+        RsgState { next: self.next, rng: self.rng.state(), bag: Vec::new() }
+    }
+
+    fn set_state(&mut self, state: &RsgState) {
+        self.next = state.next;
+        self.rng = Rng::from_state(state.rng);
+    }
+}
+
+
+/// Bag Shape Generator
+/// Deals all the shapes in random order before shuffling them
+/// again, so the same shape never comes up more than twice in a
+/// row (7-bag with the 7 tetrominoes)
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Bag {
+    // Shapes left in the current bag, dealt from the end
+    bag: Vec<usize>,
+    next: usize,
+    n_shapes: usize,
+    rng: Rng,
+}
+
+impl Bag {
+
+    /// Returns a Bag Shape Generator, seeded from the clock
+    ///
+    /// # Arguments
+    /// * `n_shapes` - The total number of shapes
+    pub fn new(n_shapes: usize) -> Self {
+        Self::with_seed(n_shapes, random_seed())
+    }
+
+    /// Returns a Bag Shape Generator that always deals the same
+    /// shapes for the same seed
+    ///
+    /// # Arguments
+    /// * `n_shapes` - The total number of shapes
+    /// * `seed` - Seed of the random number generator
+    pub fn with_seed(n_shapes: usize, seed: u64) -> Self {
+        let mut bag = Self {
+            bag: Vec::new(),
+            next: 0,
+            n_shapes,
+            rng: Rng::new(seed),
+        };
+        bag.next = bag.deal();
+
+        bag
+    }
+
+    fn deal(&mut self) -> usize {
+        if self.bag.is_empty() {
+            self.bag = (0..self.n_shapes).collect();
+            // Fisher-Yates shuffle
+            for i in (1..self.bag.len()).rev() {
+                let j = self.rng.gen_range(0, i + 1);
+                self.bag.swap(i, j);
+            }
+        }
+
+        self.bag.pop().unwrap_or(0)
+    }
+}
+
+impl RSG for Bag {
+    fn get(&mut self) -> usize {
+        let current = self.next;
+        self.next = self.deal();
+
+        current
+    }
+
+    fn next(&self) -> usize {
+        self.next
+    }
+
+    fn clone_box(&self) -> Box<dyn RSG> {
+        Box::new(self.clone())
+    }
+
+    fn state(&self) -> RsgState {
+        RsgState { next: self.next, rng: self.rng.state(), bag: self.bag.clone() }
+    }
+
+    fn set_state(&mut self, state: &RsgState) {
+        self.next = state.next;
+        self.rng = Rng::from_state(state.rng);
+        self.bag = state.bag.clone();
+    }
+}
+
+
+/// The available random shape generators
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Randomizer {
+    Random,
+    Classic,
+    Bag,
+}
+
+impl Randomizer {
+
+    pub const ALL: [Randomizer; 3] = [
+        Randomizer::Random,
+        Randomizer::Classic,
+        Randomizer::Bag,
+    ];
+
+    pub fn name(&self) -> &str {
+        match self {
+            Randomizer::Random => "Random",
+            Randomizer::Classic => "Classic",
+            Randomizer::Bag => "Bag",
+        }
+    }
+
+    /// Build a random shape generator of this kind
+    ///
+    /// # Arguments
+    /// * `n_shapes` - The total number of shapes
+    /// * `seed` - Seed of the random number generator
+    pub fn build(&self, n_shapes: usize, seed: u64) -> Box<dyn RSG> {
+        match self {
+            Randomizer::Random => Box::new(TSR::with_seed(n_shapes, seed)),
+            Randomizer::Classic => Box::new(Classic::with_seed(n_shapes, seed)),
+            Randomizer::Bag => Box::new(Bag::with_seed(n_shapes, seed)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bag, Classic, Randomizer, Rng, RSG, TSR};
+
+    #[test]
+    fn test_random_range() {
+        let mut tsr = TSR::new(7);
+        let mut classic = Classic::new(7);
+        for _ in 0..100 {
+            assert!(tsr.get() < 7);
+            assert!(classic.get() < 7);
+        }
+    }
+
+    #[test]
+    fn test_bag() {
+        let mut bag = Bag::new(7);
+
+        for _ in 0..10 {
+            let mut dealt: Vec<usize> = (0..7).map(|_| bag.get()).collect();
+            dealt.sort();
+            assert_eq!((0..7).collect::<Vec<usize>>(), dealt);
+        }
+    }
+
+    #[test]
+    fn test_seed() {
+        let mut rng = Rng::new(42);
+        assert_eq!(rng, Rng::new(42));
+        assert_ne!(rng.rand(), Rng::new(43).rand());
+
+        for randomizer in Randomizer::ALL {
+            let mut a = randomizer.build(7, 1234);
+            let mut b = randomizer.build(7, 1234);
+            for _ in 0..50 {
+                assert_eq!(a.get(), b.get());
+            }
+
+            // Copies go on generating the same shapes
+            let mut c = a.clone();
+            for _ in 0..50 {
+                assert_eq!(a.get(), c.get());
+            }
+        }
+    }
+
+    #[test]
+    fn test_state() {
+        for randomizer in Randomizer::ALL {
+            let mut a = randomizer.build(7, 1234);
+            for _ in 0..10 {
+                a.get();
+            }
+
+            // A generator with another seed goes on from the state
+            let mut b = randomizer.build(7, 99);
+            b.set_state(&a.state());
+            assert_eq!(a.state(), b.state());
+            for _ in 0..50 {
+                assert_eq!(a.get(), b.get());
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_rsg_serde() {
+        fn check<G: RSG + serde::Serialize + serde::de::DeserializeOwned>(
+            mut generator: G) {
+            for _ in 0..10 {
+                generator.get();
+            }
+            let json = serde_json::to_string(&generator).unwrap();
+            let mut restored: G = serde_json::from_str(&json).unwrap();
+            for _ in 0..50 {
+                assert_eq!(generator.get(), restored.get());
+            }
+        }
+
+        check(TSR::with_seed(7, 1));
+        check(Classic::with_seed(7, 2));
+        check(Bag::with_seed(7, 3));
+
+        // Random number generator states don't fit in JavaScript
+        // numbers, so they're written as strings
+        let json = serde_json::to_value(TSR::with_seed(7, 1)).unwrap();
+        assert!(json["rng"].is_string());
+        assert_eq!(7, json["n_shapes"]);
+    }
+}
@@ -0,0 +1,100 @@
+//! Game rules that can change from one game mode to another
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::game::TICK;
+use crate::level::LevelSystem;
+use crate::spin::SpinRule;
+
+
+/// Optional game mechanics. They don't depend on the rotation
+/// system, so any of them can be combined with any rotation system.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Ruleset {
+    /// The current shape can be swapped with the held one (once
+    /// per shape)
+    pub hold: bool,
+    /// Initial Rotation System: holding a rotate button while the
+    /// next shape enters the playfield spawns it already rotated,
+    /// if it fits
+    pub irs: bool,
+    /// Initial Hold System: holding the hold button while the next
+    /// shape enters the playfield swaps it with the held one right
+    /// away (requires `hold`)
+    pub ihs: bool,
+    /// Entry delay (ARE): time between a piece being fixed (or the
+    /// line clear delay ending) and the next one spawning, in seconds
+    pub are: f64,
+    /// Time the cleared lines stay on the playfield before the rows
+    /// above fall down, in seconds
+    pub line_clear_delay: f64,
+    /// Which pieces can spin
+    pub spins: SpinRule,
+    /// How levels advance and how fast pieces fall
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub levels: LevelSystem,
+    /// Lines to clear to finish the game (endless if `None`)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub line_goal: Option<usize>,
+    /// Time the game lasts, in seconds (unlimited if `None`)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub time_limit: Option<f64>,
+}
+
+impl Ruleset {
+
+    /// Classic games: no hold, no initial rotation, NES-like
+    /// delays and NES levels
+    pub fn classic() -> Self {
+        Self {
+            hold: false,
+            irs: false,
+            ihs: false,
+            are: 10.0 * TICK,
+            line_clear_delay: 18.0 * TICK,
+            spins: SpinRule::TOnly,
+            levels: LevelSystem::Nes,
+            line_goal: None,
+            time_limit: None,
+        }
+    }
+
+    /// Arcade games: initial rotation and TGM-like delays
+    pub fn arcade() -> Self {
+        Self {
+            hold: false,
+            irs: true,
+            ihs: false,
+            are: 30.0 * TICK,
+            line_clear_delay: 41.0 * TICK,
+            spins: SpinRule::TOnly,
+            levels: LevelSystem::Guideline,
+            line_goal: None,
+            time_limit: None,
+        }
+    }
+
+    /// Modern games: hold, initial rotation, initial hold and
+    /// no delays
+    pub fn modern() -> Self {
+        Self {
+            hold: true,
+            irs: true,
+            ihs: true,
+            are: 0.0,
+            line_clear_delay: 0.0,
+            spins: SpinRule::TOnly,
+            levels: LevelSystem::Guideline,
+            line_goal: None,
+            time_limit: None,
+        }
+    }
+}
+
+impl Default for Ruleset {
+    fn default() -> Self {
+        Self::modern()
+    }
+}
@@ -0,0 +1,240 @@
+//! Suspended games
+//!
+//! A game in progress can be suspended and resumed later, even after
+//! closing the game (or the browser tab, on the web). Its full state
+//! is saved: the playfield cells, the current piece, the hold, the
+//! state of the random shape generator (which also gives the next
+//! shapes), the stats and the game clock and timers.
+//!
+//! There's a single suspended game at a time, kept in persistent
+//! storage (see `storage`) as TOML. Like replays, saved games are
+//! versioned, and games saved by newer versions of the game are
+//! rejected.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::{Phase, Stats};
+use crate::handling::Handling;
+use crate::rsg::{Randomizer, RsgState};
+use crate::ruleset::Ruleset;
+use crate::storage;
+
+
+/// Version of the saved games written by this version of the game
+pub const VERSION: u32 = 1;
+
+/// Storage key of the suspended game
+const STORAGE_KEY: &str = "suspended";
+
+/// The piece falling when the game was saved
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SavedPiece {
+    /// Index of the shape in the rotation system
+    pub shape: usize,
+    pub row: usize,
+    pub col: isize,
+    pub rot: isize,
+}
+
+/// The full state of a game in progress
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SavedGame {
+    pub version: u32,
+    /// Name of the game mode, as chosen in the menus
+    #[serde(default)]
+    pub mode: String,
+    /// Name of the rotation system
+    pub rotation_system: String,
+    pub randomizer: Randomizer,
+    pub seed: u64,
+    pub start_level: usize,
+    /// Playfield cells, row by row (walls included)
+    pub cells: Vec<usize>,
+    /// Index of the held shape in the rotation system, if any
+    pub hold: Option<usize>,
+    pub hold_used: bool,
+    /// Game clock, in seconds
+    pub time: f64,
+    /// Ticks run since the game started
+    #[serde(default)]
+    pub ticks: u64,
+    /// Ticks when every 10 lines were cleared
+    #[serde(default)]
+    pub splits: Vec<u64>,
+    /// Time of the last gravity drop
+    pub drop_start: f64,
+    pub lock_start: Option<f64>,
+    pub lock_pending: bool,
+    pub phase: Phase,
+    pub phase_start: f64,
+    /// Full rows waiting for the line clear delay to end
+    pub clearing: Vec<usize>,
+    /// Garbage rows (hole columns) waiting for the next piece
+    pub garbage: Vec<usize>,
+    /// Consecutive line clearing pieces
+    pub combo: usize,
+    pub back_to_back: bool,
+    pub piece: SavedPiece,
+    pub generator: RsgState,
+    pub stats: Stats,
+    pub ruleset: Ruleset,
+    pub handling: Handling,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SaveError {
+    /// The game couldn't be read or written
+    Io(String),
+    /// The saved game can't be read
+    Parse(String),
+    /// The game was saved by a newer version of the game
+    Version(u32),
+    /// The saved state isn't a possible game state
+    Invalid(String),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::Io(message) =>
+                write!(f, "can't access saved game: {}", message),
+            SaveError::Parse(message) =>
+                write!(f, "invalid saved game: {}", message),
+            SaveError::Version(version) =>
+                write!(f, "unsupported saved game version {} (newest is {})",
+                    version, VERSION),
+            SaveError::Invalid(message) =>
+                write!(f, "invalid saved game state: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl SavedGame {
+
+    /// Parse a saved game
+    pub fn from_toml(src: &str) -> Result<Self, SaveError> {
+        // Check the version first: newer games may not parse
+        let version = toml::from_str::<toml::Table>(src)
+            .map_err(|e| SaveError::Parse(e.to_string()))?
+            .get("version")
+            .and_then(|version| version.as_integer())
+            .unwrap_or(0);
+        if version > VERSION as i64 {
+            return Err(SaveError::Version(version as u32));
+        }
+
+        toml::from_str(src).map_err(|e| SaveError::Parse(e.to_string()))
+    }
+
+    pub fn to_toml(&self) -> Result<String, SaveError> {
+        toml::to_string(self).map_err(|e| SaveError::Parse(e.to_string()))
+    }
+
+    /// The suspended game, if there's one
+    pub fn load() -> Option<Result<Self, SaveError>> {
+        storage::load(STORAGE_KEY).map(|src| Self::from_toml(&src))
+    }
+
+    /// Keep the game as the suspended one, replacing any other
+    pub fn store(&self) -> Result<(), SaveError> {
+        storage::save(STORAGE_KEY, &self.to_toml()?)
+            .map_err(|e| SaveError::Io(e.to_string()))
+    }
+
+    /// Forget the suspended game (once it's resumed, so it can't be
+    /// resumed again)
+    pub fn discard() -> Result<(), SaveError> {
+        storage::remove(STORAGE_KEY).map_err(|e| SaveError::Io(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SaveError, SavedGame, VERSION};
+    use crate::game::Game;
+    use crate::input::{Action, Input};
+    use crate::rsg::Randomizer;
+    use crate::ruleset::Ruleset;
+    use crate::shape::RotationSystem;
+
+    /// Play some ticks, pressing actions (never holding them)
+    fn play(game: &mut Game, ticks: std::ops::Range<usize>) {
+        for tick in ticks {
+            let mut input = Input::default();
+            match tick % 50 {
+                0 => input.press(Action::RotateCw),
+                10 => input.add_shift((tick / 50 % 9) as isize - 4),
+                20 if tick % 150 == 20 => input.press(Action::Hold),
+                49 => input.press(Action::HardDrop),
+                _ => {}
+            }
+            game.tick(&input);
+        }
+    }
+
+    fn cells(game: &Game) -> Vec<usize> {
+        game.playfield().cells().to_vec()
+    }
+
+    #[test]
+    fn test_suspend_and_resume() {
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        game.shapes_mut().update_randomizer(Randomizer::Bag);
+        game.restart_with_seed(77);
+        play(&mut game, 0..525);
+        assert!(!game.is_over());
+
+        let saved = SavedGame::from_toml(&game.suspend().to_toml().unwrap())
+            .unwrap();
+        assert_eq!(game.suspend(), saved);
+        assert_eq!("SRS", saved.rotation_system);
+
+        let mut resumed = Game::resume(&saved, RotationSystem::SRS).unwrap();
+        assert_eq!(game.stats(), resumed.stats());
+        assert_eq!(cells(&game), cells(&resumed));
+
+        // Both games go on exactly the same
+        play(&mut game, 525..1000);
+        play(&mut resumed, 525..1000);
+        assert!(game.stats().pieces > 15);
+        assert_eq!(game.stats(), resumed.stats());
+        assert_eq!(cells(&game), cells(&resumed));
+        assert_eq!(game.piece().cells(), resumed.piece().cells());
+        assert_eq!(game.shapes().next_shape().color(),
+            resumed.shapes().next_shape().color());
+    }
+
+    #[test]
+    fn test_invalid_saves() {
+        let game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        let mut saved = game.suspend();
+
+        saved.version = VERSION + 1;
+        assert_eq!(Err(SaveError::Version(VERSION + 1)),
+            SavedGame::from_toml(&saved.to_toml().unwrap()));
+        assert!(matches!(SavedGame::from_toml("version = 1"),
+            Err(SaveError::Parse(_))));
+
+        let mut saved = game.suspend();
+        saved.cells.pop();
+        assert!(matches!(Game::resume(&saved, RotationSystem::SRS),
+            Err(SaveError::Invalid(_))));
+
+        let mut saved = game.suspend();
+        saved.hold = Some(7);
+        assert!(matches!(Game::resume(&saved, RotationSystem::SRS),
+            Err(SaveError::Invalid(_))));
+
+        // The piece can't overlap the walls
+        let mut saved = game.suspend();
+        saved.piece.col = -1;
+        assert!(matches!(Game::resume(&saved, RotationSystem::SRS),
+            Err(SaveError::Invalid(_))));
+    }
+}
@@ -0,0 +1,333 @@
+//! High score tables, one per game mode
+//!
+//! Tables rank the highest scores first, except Sprint tables (named
+//! `Sprint ...`), which rank the fastest times first.
+//!
+//! Tables are kept in persistent storage (see `storage`) as TOML,
+//! with an array of entries per mode:
+//!
+//! ```toml
+//! [[Modern]]
+//! name = "ALP"
+//! score = 12400
+//! lines = 42
+//! time = 184.5
+//! date = 1718031234
+//! ```
+//!
+//! Loading never fails: invalid entries (or a whole unreadable file)
+//! are dropped, so a corrupted file only costs the scores it lost.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use macroquad::miniquad;
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{self, StorageError};
+
+
+/// Entries kept in each table
+pub const MAX_ENTRIES: usize = 10;
+
+/// Longest player name
+pub const MAX_NAME_LENGTH: usize = 12;
+
+/// Storage key of the tables
+const STORAGE_KEY: &str = "scores";
+
+/// Start of the names of the tables ranked by time
+const TIME_RANKED: &str = "Sprint";
+
+/// How the entries of a table are ranked
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ranking {
+    /// Highest score first
+    Score,
+    /// Fastest time first
+    Time,
+}
+
+impl Ranking {
+
+    /// Ranking of the table of a game mode
+    pub fn of(mode: &str) -> Self {
+        if mode.starts_with(TIME_RANKED) {
+            Ranking::Time
+        } else {
+            Ranking::Score
+        }
+    }
+
+    /// Order of two entries (`Less` if the first one ranks above)
+    fn compare(self, entry: &Score, other: &Score) -> Ordering {
+        match self {
+            Ranking::Score => other.score.cmp(&entry.score),
+            Ranking::Time => entry.time.total_cmp(&other.time),
+        }
+    }
+
+    /// Whether an entry can be ranked at all: games without a score
+    /// or without a time aren't
+    fn ranks(self, entry: &Score) -> bool {
+        match self {
+            Ranking::Score => entry.score > 0,
+            Ranking::Time => entry.time > 0.0,
+        }
+    }
+}
+
+/// An entry of a high score table
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Score {
+    /// Player name
+    pub name: String,
+    pub score: usize,
+    pub lines: usize,
+    /// Game time, in seconds
+    pub time: f64,
+    /// When the game was played, in seconds since the Unix epoch
+    pub date: u64,
+}
+
+impl Score {
+
+    /// Entry for a game finished now
+    pub fn new(name: &str, score: usize, lines: usize, time: f64) -> Self {
+        Self {
+            name: name.to_string(),
+            score,
+            lines,
+            time,
+            date: miniquad::date::now() as u64,
+        }
+    }
+
+    /// Entry fixed to be valid: a trimmed, non-empty name of up to
+    /// `MAX_NAME_LENGTH` characters and a non-negative time
+    fn sanitized(mut self) -> Self {
+        self.name = self.name.trim().chars().take(MAX_NAME_LENGTH).collect();
+        if self.name.is_empty() {
+            self.name = "?".to_string();
+        }
+        if !self.time.is_finite() || self.time < 0.0 {
+            self.time = 0.0;
+        }
+        self
+    }
+}
+
+/// High score tables by game mode, best entries first
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct HighScores {
+    tables: BTreeMap<String, Vec<Score>>,
+}
+
+impl HighScores {
+
+    /// Parse stored tables, keeping every valid entry
+    pub fn from_toml(src: &str) -> Self {
+        let mut scores = Self::default();
+        let Ok(table) = toml::from_str::<toml::Table>(src) else {
+            return scores;
+        };
+
+        for (mode, entries) in table {
+            let Some(entries) = entries.as_array() else {
+                continue;
+            };
+
+            let mut entries: Vec<Score> = entries.iter()
+                .filter_map(|entry| entry.clone().try_into::<Score>().ok())
+                .map(Score::sanitized)
+                .collect();
+            // Stable, so ties keep their order
+            let ranking = Ranking::of(&mode);
+            entries.sort_by(|entry, other| ranking.compare(entry, other));
+            entries.truncate(MAX_ENTRIES);
+
+            if !entries.is_empty() {
+                scores.tables.insert(mode, entries);
+            }
+        }
+
+        scores
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap_or_default()
+    }
+
+    /// Load the stored tables (empty ones if there are none)
+    pub fn load() -> Self {
+        storage::load(STORAGE_KEY)
+            .map(|src| Self::from_toml(&src))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), StorageError> {
+        storage::save(STORAGE_KEY, &self.to_toml())
+    }
+
+    /// Entries of the given mode, best first
+    pub fn table(&self, mode: &str) -> &[Score] {
+        self.tables.get(mode).map_or(&[], |entries| entries.as_slice())
+    }
+
+    /// An entry makes it into the table of the mode if it can be
+    /// ranked and the table isn't full or the entry beats its last one
+    pub fn qualifies(&self, mode: &str, entry: &Score) -> bool {
+        let ranking = Ranking::of(mode);
+        let table = self.table(mode);
+        ranking.ranks(entry) && (table.len() < MAX_ENTRIES
+            || table.last().is_some_and(|last|
+                ranking.compare(entry, last) == Ordering::Less))
+    }
+
+    /// Add an entry to the table of the mode, if it qualifies
+    ///
+    /// # Returns
+    /// The position of the entry in the table (0 is the best), if it
+    /// was added
+    pub fn insert(&mut self, mode: &str, entry: Score) -> Option<usize> {
+        let entry = entry.sanitized();
+        if !self.qualifies(mode, &entry) {
+            return None;
+        }
+
+        let ranking = Ranking::of(mode);
+        let table = self.tables.entry(mode.to_string()).or_default();
+        // Below the entries ranked the same, which came first
+        let rank = table.iter()
+            .position(|other| ranking.compare(&entry, other) == Ordering::Less)
+            .unwrap_or(table.len());
+        table.insert(rank, entry);
+        table.truncate(MAX_ENTRIES);
+
+        Some(rank)
+    }
+}
+
+/// Date (YYYY-MM-DD, UTC) of a time in seconds since the Unix epoch
+pub fn format_date(seconds: u64) -> String {
+    // Civil from days (Howard Hinnant), with eras of 400 years
+    let days = (seconds / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524
+        - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4
+        - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_date, HighScores, Ranking, Score, MAX_ENTRIES};
+
+    fn score(name: &str, score: usize) -> Score {
+        Score { name: name.to_string(), score, lines: 10, time: 60.0, date: 0 }
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut scores = HighScores::default();
+        assert!(!scores.qualifies("Modern", &score("A", 0)));
+
+        for i in 1..=MAX_ENTRIES {
+            assert_eq!(Some(0), scores.insert("Modern", score("A", i * 100)));
+        }
+        assert_eq!(MAX_ENTRIES, scores.table("Modern").len());
+        assert!(scores.table("Classic").is_empty());
+
+        // Full table: only better scores get in, below equal ones
+        assert!(!scores.qualifies("Modern", &score("B", 100)));
+        assert_eq!(None, scores.insert("Modern", score("B", 50)));
+        assert_eq!(Some(6), scores.insert("Modern", score("C", 500)));
+        assert_eq!(MAX_ENTRIES, scores.table("Modern").len());
+        assert_eq!("C", scores.table("Modern")[6].name);
+        assert_eq!(200, scores.table("Modern")[MAX_ENTRIES - 1].score);
+        assert!(scores.qualifies("Classic", &score("B", 100)));
+    }
+
+    #[test]
+    fn test_insert_times() {
+        let time = |name: &str, time: f64| Score { time, ..score(name, 0) };
+        let mut scores = HighScores::default();
+        let board = "Sprint Modern 40 lines";
+        assert_eq!(Ranking::Time, Ranking::of(board));
+        assert_eq!(Ranking::Score, Ranking::of("Marathon Modern 150 lines"));
+        assert!(!scores.qualifies(board, &time("A", 0.0)));
+
+        // Fastest first, scores don't matter
+        for i in 1..=MAX_ENTRIES {
+            let rank = scores.insert(board, time("A", 100.0 - i as f64));
+            assert_eq!(Some(0), rank);
+        }
+
+        // Full table: only faster times get in, below equal ones
+        assert!(!scores.qualifies(board, &time("B", 99.0)));
+        assert_eq!(None, scores.insert(board, time("B", 120.0)));
+        assert_eq!(Some(5), scores.insert(board, time("C", 94.0)));
+        assert_eq!("C", scores.table(board)[5].name);
+        assert_eq!(MAX_ENTRIES, scores.table(board).len());
+        assert_eq!(98.0, scores.table(board)[MAX_ENTRIES - 1].time);
+
+        // Loading keeps them fastest first
+        assert_eq!(scores, HighScores::from_toml(&scores.to_toml()));
+    }
+
+    #[test]
+    fn test_load() {
+        let mut scores = HighScores::default();
+        scores.insert("Modern", score("ALP", 1200));
+        scores.insert("Modern", score("BOB", 800));
+        scores.insert("Classic", score("ALP", 300));
+        assert_eq!(scores, HighScores::from_toml(&scores.to_toml()));
+
+        // Invalid entries and tables are dropped, the rest is fixed
+        let src = r#"
+            Arcade = 3
+
+            [[Modern]]
+            name = "  A very long player name  "
+            score = 100
+            lines = 1
+            time = -5.0
+            date = 0
+
+            [[Modern]]
+            name = "BAD"
+            score = "lots"
+
+            [[Modern]]
+            name = "ALP"
+            score = 900
+            lines = 9
+            time = 90.0
+            date = 1718031234
+        "#;
+        let scores = HighScores::from_toml(src);
+        let table = scores.table("Modern");
+        assert_eq!(2, table.len());
+        assert_eq!("ALP", table[0].name);
+        assert_eq!("A very long ", table[1].name);
+        assert_eq!(0.0, table[1].time);
+        assert!(scores.table("Arcade").is_empty());
+
+        assert_eq!(HighScores::default(), HighScores::from_toml("[[Modern]"));
+    }
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!("1970-01-01", format_date(0));
+        assert_eq!("2000-02-29", format_date(951782400));
+        assert_eq!("2024-06-10", format_date(1718031234));
+    }
+}
@@ -0,0 +1,362 @@
+//! Player profiles and their settings
+//!
+//! Every profile has a name, its own settings (controls, handling,
+//! preferred rotation system and randomizer, theme, volume and block
+//! size) and lifetime statistics of the games played with it. They
+//! are kept in persistent storage (see `storage`) as TOML:
+//!
+//! ```toml
+//! current = "ALP"
+//!
+//! [[profiles]]
+//! name = "ALP"
+//!
+//! [profiles.settings]
+//! rotation_system = "SRS"
+//! randomizer = "Bag"
+//! theme = "Dark"
+//! # ...
+//!
+//! [profiles.settings.bindings]
+//! Hold = ["C", "LeftShift"]
+//! # ...
+//!
+//! [profiles.stats]
+//! games = 12
+//! # ...
+//! ```
+//!
+//! Missing settings take their default values, and profiles that
+//! can't be read are dropped, so a corrupted file never stops the
+//! game from starting.
+
+use serde::{Deserialize, Serialize};
+
+use crate::animation::ClearStyle;
+use crate::game::Stats;
+use crate::graphics::{Theme, BLOCK_SIZE};
+use crate::handling::Handling;
+use crate::input::Bindings;
+use crate::rsg::Randomizer;
+use crate::storage::{self, StorageError};
+use crate::touch::{GestureSettings, TouchLayout};
+
+
+/// Name of the profile created when there's none
+pub const DEFAULT_PROFILE: &str = "Player";
+
+/// Storage key of the profiles
+const STORAGE_KEY: &str = "profiles";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub bindings: Bindings,
+    pub handling: Handling,
+    /// Name of the preferred rotation system
+    pub rotation_system: String,
+    pub randomizer: Randomizer,
+    pub theme: Theme,
+    /// Sound volume, from 0 to 1
+    pub volume: f32,
+    /// Largest block size, in pixels
+    pub block_size: f32,
+    pub clear_style: ClearStyle,
+    pub touch_layout: TouchLayout,
+    /// Touch drag distance, in blocks
+    pub drag_distance: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            bindings: Bindings::default(),
+            handling: Handling::default(),
+            rotation_system: "NES".to_string(),
+            randomizer: Randomizer::ALL[0],
+            theme: Theme::default(),
+            volume: 0.8,
+            block_size: BLOCK_SIZE,
+            clear_style: ClearStyle::ALL[0],
+            touch_layout: TouchLayout::ALL[0],
+            drag_distance: GestureSettings::default().drag_distance,
+        }
+    }
+}
+
+/// Statistics of all the games played with a profile
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LifetimeStats {
+    pub games: usize,
+    pub pieces: usize,
+    pub lines: usize,
+    /// Sum of the scores of all the games
+    pub score: usize,
+    pub best_score: usize,
+    /// Time played, in seconds
+    pub time: f64,
+}
+
+impl LifetimeStats {
+
+    /// Add a finished game
+    pub fn record(&mut self, stats: &Stats) {
+        self.games += 1;
+        self.pieces += stats.pieces;
+        self.lines += stats.lines;
+        self.score += stats.score;
+        self.best_score = self.best_score.max(stats.score);
+        self.time += stats.time;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    #[serde(default)]
+    pub settings: Settings,
+    #[serde(default)]
+    pub stats: LifetimeStats,
+}
+
+impl Profile {
+
+    /// A profile with the default settings
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            settings: Settings::default(),
+            stats: LifetimeStats::default(),
+        }
+    }
+}
+
+/// The player profiles, one of them in use. There's always at least
+/// one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profiles {
+    profiles: Vec<Profile>,
+    current: usize,
+}
+
+/// Stored form of the profiles
+#[derive(Serialize)]
+struct StoredProfiles<'a> {
+    current: &'a str,
+    profiles: &'a [Profile],
+}
+
+impl Default for Profiles {
+    fn default() -> Self {
+        Self {
+            profiles: vec![Profile::new(DEFAULT_PROFILE)],
+            current: 0,
+        }
+    }
+}
+
+impl Profiles {
+
+    /// Parse stored profiles, keeping every valid one
+    pub fn from_toml(src: &str) -> Self {
+        let Ok(table) = toml::from_str::<toml::Table>(src) else {
+            return Self::default();
+        };
+
+        let mut profiles: Vec<Profile> = Vec::new();
+        let stored = table.get("profiles").and_then(|p| p.as_array());
+        for profile in stored.into_iter().flatten() {
+            if let Ok(profile) = profile.clone().try_into::<Profile>()
+                && !profile.name.trim().is_empty()
+                && !profiles.iter().any(|p| p.name == profile.name) {
+                profiles.push(profile);
+            }
+        }
+
+        if profiles.is_empty() {
+            return Self::default();
+        }
+
+        let current = table.get("current")
+            .and_then(|name| name.as_str())
+            .and_then(|name| profiles.iter().position(|p| p.name == name))
+            .unwrap_or(0);
+
+        Self { profiles, current }
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(&StoredProfiles {
+            current: &self.current().name,
+            profiles: &self.profiles,
+        }).unwrap_or_default()
+    }
+
+    /// Load the stored profiles (a default one if there are none)
+    pub fn load() -> Self {
+        storage::load(STORAGE_KEY)
+            .map(|src| Self::from_toml(&src))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), StorageError> {
+        storage::save(STORAGE_KEY, &self.to_toml())
+    }
+
+    pub fn profiles(&self) -> &[Profile] {
+        &self.profiles
+    }
+
+    /// Index of the profile in use
+    pub fn index(&self) -> usize {
+        self.current
+    }
+
+    /// The profile in use
+    pub fn current(&self) -> &Profile {
+        &self.profiles[self.current]
+    }
+
+    pub fn current_mut(&mut self) -> &mut Profile {
+        &mut self.profiles[self.current]
+    }
+
+    /// Use the profile with the given index, if there's one
+    pub fn select(&mut self, index: usize) {
+        if index < self.profiles.len() {
+            self.current = index;
+        }
+    }
+
+    /// Use the profile with the given name, creating it (with the
+    /// default settings) if there's none
+    ///
+    /// # Returns
+    /// The index of the profile
+    pub fn add(&mut self, name: &str) -> usize {
+        let name = name.trim();
+        self.current = match self.profiles.iter().position(|p| p.name == name) {
+            Some(index) => index,
+            None => {
+                self.profiles.push(Profile::new(name));
+                self.profiles.len() - 1
+            }
+        };
+        self.current
+    }
+
+    /// Delete the profile in use and use the first one, unless it's
+    /// the last profile left
+    ///
+    /// # Returns
+    /// False if it wasn't deleted
+    pub fn remove_current(&mut self) -> bool {
+        if self.profiles.len() == 1 {
+            return false;
+        }
+
+        self.profiles.remove(self.current);
+        self.current = 0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::input::KeyCode;
+
+    use super::{LifetimeStats, Profiles, DEFAULT_PROFILE};
+    use crate::game::Stats;
+    use crate::graphics::Theme;
+    use crate::handling::Handling;
+    use crate::input::Action;
+
+    #[test]
+    fn test_profiles() {
+        let mut profiles = Profiles::default();
+        assert_eq!(DEFAULT_PROFILE, profiles.current().name);
+
+        assert_eq!(1, profiles.add(" ALP "));
+        assert_eq!("ALP", profiles.current().name);
+        assert_eq!(1, profiles.add("ALP"));
+        assert_eq!(2, profiles.profiles().len());
+
+        profiles.select(5);
+        assert_eq!(1, profiles.index());
+        profiles.select(0);
+        assert_eq!(DEFAULT_PROFILE, profiles.current().name);
+
+        assert!(profiles.remove_current());
+        assert_eq!("ALP", profiles.current().name);
+        assert!(!profiles.remove_current());
+    }
+
+    #[test]
+    fn test_lifetime_stats() {
+        let game = Stats {
+            pieces: 30, lines: 10, level: 2, score: 1500, time: 60.0, keys: 80,
+            ..Default::default()
+        };
+
+        let mut lifetime = LifetimeStats::default();
+        lifetime.record(&game);
+        lifetime.record(&Stats { score: 500, ..game.clone() });
+        assert_eq!(2, lifetime.games);
+        assert_eq!(20, lifetime.lines);
+        assert_eq!(2000, lifetime.score);
+        assert_eq!(1500, lifetime.best_score);
+        assert_eq!(120.0, lifetime.time);
+    }
+
+    #[test]
+    fn test_load() {
+        let mut profiles = Profiles::default();
+        profiles.add("ALP");
+        let settings = &mut profiles.current_mut().settings;
+        settings.theme = Theme::Dark;
+        settings.rotation_system = "SRS".to_string();
+        settings.bindings.bind(Action::Hold, KeyCode::Q);
+        profiles.current_mut().stats.games = 3;
+
+        assert_eq!(profiles, Profiles::from_toml(&profiles.to_toml()));
+
+        // Invalid profiles are dropped and missing settings take
+        // their default values
+        let src = r#"
+            current = "BOB"
+
+            [[profiles]]
+            settings = 3
+
+            [[profiles]]
+            name = "BOB"
+
+            [profiles.settings]
+            theme = "Light"
+        "#;
+        let profiles = Profiles::from_toml(src);
+        assert_eq!(1, profiles.profiles().len());
+        assert_eq!("BOB", profiles.current().name);
+        assert_eq!(Theme::Light, profiles.current().settings.theme);
+        assert_eq!("NES", profiles.current().settings.rotation_system);
+
+        // So do missing handling settings
+        let src = r#"
+            current = "ALP"
+
+            [[profiles]]
+            name = "ALP"
+
+            [profiles.settings.handling]
+            das = 0.2
+        "#;
+        let profiles = Profiles::from_toml(src);
+        let handling = &profiles.current().settings.handling;
+        assert_eq!(0.2, handling.das);
+        assert_eq!(Handling::default().arr, handling.arr);
+        assert!(handling.preserve_das);
+
+        assert_eq!(Profiles::default(), Profiles::from_toml("current = "));
+    }
+}
@@ -1,6 +1,52 @@
+#[cfg(feature = "serde")]
+use std::collections::BTreeMap;
+
 use macroquad::{color::*, prelude::Color};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::definition::RotationDefinition;
+use crate::rsg::{self, Randomizer, RsgState, RSG};
+
+/// Kick offset tested when no kick table is defined
+const NO_KICKS: [(isize, isize); 1] = [(0, 0)];
+
+/// Kick offsets tested when rotating from one state to
+/// another: (from, to, offsets), with offsets given as
+/// (columns to the right, rows up)
+pub type Kicks<const N: usize> = (isize, isize, [(isize, isize); N]);
+
+/// SRS wall kicks for J, L, S, T and Z shapes
+pub const SRS_KICKS: [Kicks<5>; 8] = [
+    (0, 1, [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)]),
+    (1, 0, [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)]),
+    (1, 2, [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)]),
+    (2, 1, [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)]),
+    (2, 3, [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)]),
+    (3, 2, [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)]),
+    (3, 0, [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)]),
+    (0, 3, [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)]),
+];
 
-use crate::rsg::{RSG, TSR};
+/// SRS wall kicks for the I shape
+pub const SRS_I_KICKS: [Kicks<5>; 8] = [
+    (0, 1, [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)]),
+    (1, 0, [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)]),
+    (1, 2, [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)]),
+    (2, 1, [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)]),
+    (2, 3, [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)]),
+    (3, 2, [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)]),
+    (3, 0, [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)]),
+    (0, 3, [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)]),
+];
+
+/// SRS+ kicks for 180º rotations (all shapes but O)
+pub const SRS_180_KICKS: [Kicks<6>; 4] = [
+    (0, 2, [(0, 0), (0, 1), (1, 1), (-1, 1), (1, 0), (-1, 0)]),
+    (1, 3, [(0, 0), (1, 0), (1, 2), (1, 1), (0, 2), (0, 1)]),
+    (2, 0, [(0, 0), (0, -1), (-1, -1), (1, -1), (-1, 0), (1, 0)]),
+    (3, 1, [(0, 0), (-1, 0), (-1, 2), (-1, 1), (0, 2), (0, 1)]),
+];
 
 pub trait Rotation {
     fn rotate_cell(&self, row: usize, col: usize, rot: isize, width: usize) -> (usize, usize);
@@ -11,30 +57,54 @@ pub trait ShapeBuilder {
     fn build_shapes(&self) -> Vec<Shape>;
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum RotationType {
     SRS,
     NES,
+    GB,
+    SEGA,
     STILL,
+    CUSTOM,
 }
 
+/// With the `serde` feature, built-in rotation systems are serialized
+/// as their names (`"SRS"`) and custom ones as their definition
+/// (`{"CUSTOM": {"name": ..., "lock_delay": ..., "shapes": [...]}}`)
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum RotationSystem {
     SRS,
     NES,
+    GB,
+    SEGA,
+    CUSTOM(RotationDefinition),
 }
 
-#[derive(Clone)]
+/// With the `serde` feature, shapes are serialized as their cells
+/// (row by row, the four rotation states one after another for
+/// `CUSTOM` shapes), width, color (`[r, g, b, a]`, from 0 to 1),
+/// rotation type, spawn row offset and kicks by transition
+/// (`{"0>1": [[0, 0], [-1, 0]]}`, as in rotation system definitions)
+#[derive(Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "ShapeData", into = "ShapeData"))]
 pub struct Shape {
     m: Vec<usize>,
+    n_cells: usize,
     width: usize,
     color: Color,
     rot_type: RotationType,
     row_offset: usize,
+    kicks: Vec<Vec<(isize, isize)>>,
 }
 
+#[derive(Clone)]
 pub struct ShapeFactory {
     shapes: Vec<Shape>,
     rotation_system: RotationSystem,
+    randomizer: Randomizer,
+    seed: u64,
     random_shape_generator: Box<dyn RSG>,
 }
 
@@ -58,7 +128,55 @@ impl Rotation for RotationType {
                     _ => panic!("Rotation values must go from 0 to 3"),
                 }
             },
+            // Left-handed version of the NES rotation: vertical states
+            // lean to the left of the box instead of to the right
+            RotationType::GB => {
+                match rot {
+                    0 | 2 => (row, col),
+                    1 | 3 => ((width - 1) - col, row),
+                    _ => panic!("Rotation values must go from 0 to 3"),
+                }
+            },
+            // Sega shapes are 3x3 and keep their bottom row empty in
+            // the spawn state. States 0 and 2 stay on the top two rows
+            // and vertical states (1 and 3) stay on the centre column,
+            // so the mapping is a plain rotation followed by a shift.
+            RotationType::SEGA => {
+                match rot {
+                    0 => (row, col),
+                    1 => {
+                        if col == width - 1 {
+                            (width - 1, row)
+                        } else {
+                            ((width - 2) - col, row)
+                        }
+                    },
+                    2 => {
+                        if row == width - 1 {
+                            (row, col)
+                        } else {
+                            ((width - 2) - row, (width - 1) - col)
+                        }
+                    },
+                    3 => {
+                        if col == 0 {
+                            (width - 1, (width - 1) - row)
+                        } else {
+                            (col - 1, (width - 1) - row)
+                        }
+                    },
+                    _ => panic!("Rotation values must go from 0 to 3: Got {}", rot),
+                }
+            },
             RotationType::STILL => (row, col),
+            // The four rotation states are stacked in the shape matrix
+            // (see Shape::with_states)
+            RotationType::CUSTOM => {
+                match rot {
+                    0..=3 => ((rot as usize * width) + row, col),
+                    _ => panic!("Rotation values must go from 0 to 3: Got {}", rot),
+                }
+            },
         }
     }
 }
@@ -69,6 +187,9 @@ impl ShapeBuilder for RotationSystem {
         match self {
             RotationSystem::SRS => "SRS",
             RotationSystem::NES => "NES",
+            RotationSystem::GB => "GB",
+            RotationSystem::SEGA => "SEGA",
+            RotationSystem::CUSTOM(definition) => definition.name(),
         }
     }
 
@@ -76,11 +197,30 @@ impl ShapeBuilder for RotationSystem {
         match self {
             RotationSystem::SRS => Self::srs_shapes(),
             RotationSystem::NES => Self::nes_shapes(),
+            RotationSystem::GB => Self::gb_shapes(),
+            RotationSystem::SEGA => Self::sega_shapes(),
+            RotationSystem::CUSTOM(definition) => definition.shapes().clone(),
         }
     }
 }
 
 impl RotationSystem {
+
+    /// Time (in seconds) a shape can rest on the stack before it
+    /// is fixed to the playfield. `None` means that the shape is
+    /// fixed as soon as gravity can't move it down anymore.
+    pub fn lock_delay(&self) -> Option<f64> {
+        match self {
+            RotationSystem::SRS => None,
+            RotationSystem::NES => None,
+            RotationSystem::GB => None,
+            // 30 frames at 60 Hz, only reset when the shape
+            // moves down one row (step reset)
+            RotationSystem::SEGA => Some(0.5),
+            RotationSystem::CUSTOM(definition) => definition.lock_delay(),
+        }
+    }
+
     fn srs_shapes() -> Vec<Shape> {
         let j: Vec<usize> = Vec::from([
             1, 0, 0,
@@ -120,55 +260,83 @@ impl RotationSystem {
         ]);
 
         vec![
-            Shape::new(j, 3, PINK, RotationType::SRS),
-            Shape::new(l, 3, BLUE, RotationType::SRS),
-            Shape::new(s, 3, GREEN, RotationType::SRS),
-            Shape::new(z, 3, ORANGE, RotationType::SRS),
-            Shape::new(i, 4, RED, RotationType::SRS),
-            Shape::new(t, 3, PURPLE, RotationType::SRS),
+            Shape::new(j, 3, PINK, RotationType::SRS)
+                .set_kick_table(&SRS_KICKS)
+                .set_kick_table(&SRS_180_KICKS),
+            Shape::new(l, 3, BLUE, RotationType::SRS)
+                .set_kick_table(&SRS_KICKS)
+                .set_kick_table(&SRS_180_KICKS),
+            Shape::new(s, 3, GREEN, RotationType::SRS)
+                .set_kick_table(&SRS_KICKS)
+                .set_kick_table(&SRS_180_KICKS),
+            Shape::new(z, 3, ORANGE, RotationType::SRS)
+                .set_kick_table(&SRS_KICKS)
+                .set_kick_table(&SRS_180_KICKS),
+            Shape::new(i, 4, RED, RotationType::SRS)
+                .set_kick_table(&SRS_I_KICKS)
+                .set_kick_table(&SRS_180_KICKS),
+            Shape::new(t, 3, PURPLE, RotationType::SRS)
+                .set_kick_table(&SRS_KICKS)
+                .set_kick_table(&SRS_180_KICKS),
             Shape::new(o, 4, YELLOW, RotationType::STILL),
         ]
     }
 
     fn nes_shapes() -> Vec<Shape> {
-        let j_nes: Vec<usize> = Vec::from([
+        Self::nintendo_shapes(RotationType::NES)
+    }
+
+    /// Game Boy shapes (left-handed Nintendo Rotation System).
+    /// The Game Boy spawns every shape in the same orientation
+    /// (flat side up) and rows as the NES, so both share their spawn
+    /// states. J, L and T shapes rotate like in the NES, while I, S
+    /// and Z toggle between their spawn state and a vertical state
+    /// that leans to the left of their box.
+    fn gb_shapes() -> Vec<Shape> {
+        Self::nintendo_shapes(RotationType::GB)
+    }
+
+    /// Shapes of the Nintendo Rotation Systems, which only differ in
+    /// how the I, S and Z shapes toggle between their two states
+    fn nintendo_shapes(toggle: RotationType) -> Vec<Shape> {
+        let j: Vec<usize> = Vec::from([
             0, 0, 0,
             1, 1, 1,
             0, 0, 1,
         ]);
 
-        let l_nes: Vec<usize> = Vec::from([
+        let l: Vec<usize> = Vec::from([
             0, 0, 0,
             1, 1, 1,
             1, 0, 0,
         ]);
 
-        let s_nes: Vec<usize> = Vec::from([
+        let s: Vec<usize> = Vec::from([
             0, 0, 0,
             0, 1, 1,
             1, 1, 0,
         ]);
 
-        let z_nes: Vec<usize> = Vec::from([
+        let z: Vec<usize> = Vec::from([
             0, 0, 0,
             1, 1, 0,
             0, 1, 1,
         ]);
 
-        let i_nes: Vec<usize> = Vec::from([
+        let i: Vec<usize> = Vec::from([
             0, 0, 0, 0,
             0, 0, 0, 0,
             1, 1, 1, 1,
             0, 0, 0, 0,
         ]);
 
-        let t_nes: Vec<usize> = Vec::from([
+        let t: Vec<usize> = Vec::from([
             0, 0, 0,
             1, 1, 1,
             0, 1, 0,
         ]);
 
-        let o_nes: Vec<usize> = Vec::from([
+        let o: Vec<usize> = Vec::from([
             0, 0, 0, 0,
             0, 1, 1, 0,
             0, 1, 1, 0,
@@ -176,13 +344,71 @@ impl RotationSystem {
         ]);
 
         vec![
-            Shape::new(j_nes, 3, PINK, RotationType::SRS).set_row_offset(1),
-            Shape::new(l_nes, 3, BLUE, RotationType::SRS).set_row_offset(1),
-            Shape::new(s_nes, 3, GREEN, RotationType::NES).set_row_offset(1),
-            Shape::new(z_nes, 3, ORANGE, RotationType::NES).set_row_offset(1),
-            Shape::new(i_nes, 4, RED, RotationType::NES),
-            Shape::new(t_nes, 3, PURPLE, RotationType::SRS).set_row_offset(1),
-            Shape::new(o_nes, 4, YELLOW, RotationType::STILL).set_row_offset(1),
+            Shape::new(j, 3, PINK, RotationType::SRS).set_row_offset(1),
+            Shape::new(l, 3, BLUE, RotationType::SRS).set_row_offset(1),
+            Shape::new(s, 3, GREEN, toggle.clone()).set_row_offset(1),
+            Shape::new(z, 3, ORANGE, toggle.clone()).set_row_offset(1),
+            Shape::new(i, 4, RED, toggle),
+            Shape::new(t, 3, PURPLE, RotationType::SRS).set_row_offset(1),
+            Shape::new(o, 4, YELLOW, RotationType::STILL).set_row_offset(1),
+        ]
+    }
+
+    /// Sega Tetris (1988) shapes. All of them spawn top-aligned
+    /// in their box (flat side up) and there are no wall kicks.
+    fn sega_shapes() -> Vec<Shape> {
+        let j_sega: Vec<usize> = Vec::from([
+            1, 1, 1,
+            0, 0, 1,
+            0, 0, 0,
+        ]);
+
+        let l_sega: Vec<usize> = Vec::from([
+            1, 1, 1,
+            1, 0, 0,
+            0, 0, 0,
+        ]);
+
+        let s_sega: Vec<usize> = Vec::from([
+            0, 1, 1,
+            1, 1, 0,
+            0, 0, 0,
+        ]);
+
+        let z_sega: Vec<usize> = Vec::from([
+            1, 1, 0,
+            0, 1, 1,
+            0, 0, 0,
+        ]);
+
+        let i_sega: Vec<usize> = Vec::from([
+            0, 0, 0, 0,
+            1, 1, 1, 1,
+            0, 0, 0, 0,
+            0, 0, 0, 0,
+        ]);
+
+        let t_sega: Vec<usize> = Vec::from([
+            1, 1, 1,
+            0, 1, 0,
+            0, 0, 0,
+        ]);
+
+        let o_sega: Vec<usize> = Vec::from([
+            0, 1, 1, 0,
+            0, 1, 1, 0,
+            0, 0, 0, 0,
+            0, 0, 0, 0,
+        ]);
+
+        vec![
+            Shape::new(j_sega, 3, PINK, RotationType::SEGA).set_row_offset(2),
+            Shape::new(l_sega, 3, BLUE, RotationType::SEGA).set_row_offset(2),
+            Shape::new(s_sega, 3, GREEN, RotationType::NES).set_row_offset(2),
+            Shape::new(z_sega, 3, ORANGE, RotationType::NES).set_row_offset(2),
+            Shape::new(i_sega, 4, RED, RotationType::NES).set_row_offset(1),
+            Shape::new(t_sega, 3, PURPLE, RotationType::SEGA).set_row_offset(2),
+            Shape::new(o_sega, 4, YELLOW, RotationType::STILL).set_row_offset(2),
         ]
     }
 }
@@ -191,11 +417,40 @@ impl RotationSystem {
 impl Shape {
     pub fn new(m: Vec<usize>, width: usize, color: Color, shape_type: RotationType) -> Self {
         Self {
+            n_cells: m.len(),
             m,
             width,
             color,
             rot_type: shape_type,
             row_offset: 0,
+            kicks: Vec::new(),
+        }
+    }
+
+    /// Build a shape from explicit rotation states instead of
+    /// computing them from the spawn state.
+    ///
+    /// # Arguments
+    /// * `states` - Square matrices (`width` x `width`) for each
+    ///   rotation state, starting with the spawn state. When less
+    ///   than 4 states are given they are repeated in order (e.g.
+    ///   2 states toggle like NES I, S and Z shapes).
+    /// * `width` - The width of every state matrix
+    /// * `color` - The color of the shape
+    pub fn with_states(states: &[Vec<usize>], width: usize, color: Color) -> Self {
+        let mut m: Vec<usize> = Vec::with_capacity(4 * width * width);
+        for rot in 0..4 {
+            m.extend(&states[rot % states.len()]);
+        }
+
+        Self {
+            m,
+            n_cells: width * width,
+            width,
+            color,
+            rot_type: RotationType::CUSTOM,
+            row_offset: 0,
+            kicks: Vec::new(),
         }
     }
 
@@ -207,8 +462,13 @@ impl Shape {
         self.width
     }
 
+    /// Number of cells of the shape in a single rotation state
     pub fn len(&self) -> usize {
-        self.m.len()
+        self.n_cells
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n_cells == 0
     }
 
     pub fn rot_type(&self) -> &RotationType {
@@ -232,22 +492,73 @@ impl Shape {
         self
     }
 
+    /// Set the kick offsets tested when rotating from one state
+    /// to another. Offsets are (columns to the right, rows up)
+    /// and are tested in order until one of them doesn't collide.
+    ///
+    /// # Arguments
+    /// * `from` - Rotation state before rotating (0 to 3)
+    /// * `to` - Rotation state after rotating (0 to 3)
+    /// * `offsets` - The offsets to test, in order
+    pub fn set_kicks(mut self, from: isize, to: isize,
+                     offsets: Vec<(isize, isize)>) -> Self {
+        if self.kicks.is_empty() {
+            self.kicks = vec![Vec::new(); 16];
+        }
+        self.kicks[(from * 4 + to) as usize] = offsets;
+        self
+    }
+
+    /// Set the kick offsets of several transitions at once (see
+    /// `set_kicks`)
+    pub fn set_kick_table<const N: usize>(mut self,
+                                          table: &[Kicks<N>]) -> Self {
+        for (from, to, offsets) in table {
+            self = self.set_kicks(*from, *to, offsets.to_vec());
+        }
+        self
+    }
+
+    /// Return the kick offsets (columns to the right, rows up)
+    /// to test when rotating from one state to another. Shapes
+    /// without kicks only test their current position.
+    pub fn kicks(&self, from: isize, to: isize) -> &[(isize, isize)] {
+        match self.kicks.get((from * 4 + to) as usize) {
+            Some(offsets) if !offsets.is_empty() => offsets,
+            _ => &NO_KICKS,
+        }
+    }
+
     pub fn rotate(&self, row: usize, col: usize, rot: isize) -> &usize {
         let (row_r, col_r) = self.rot_type.rotate_cell(row, col, rot, self.width);
-        &self.m[((row_r * self.width) + col_r) as usize]
+        &self.m[(row_r * self.width) + col_r]
     }
 }
 
 impl ShapeFactory {
     pub fn new(rotation_system: RotationSystem) -> Self {
-        
+        Self::with_randomizer(rotation_system, Randomizer::Random)
+    }
+
+    pub fn with_randomizer(rotation_system: RotationSystem,
+                           randomizer: Randomizer) -> Self {
+        Self::with_seed(rotation_system, randomizer, rsg::random_seed())
+    }
+
+    /// Shape factory that always deals the same shapes for the same
+    /// seed
+    pub fn with_seed(rotation_system: RotationSystem,
+                     randomizer: Randomizer, seed: u64) -> Self {
+
         let shapes = rotation_system.build_shapes();
         let n_shapes = shapes.len();
 
         Self {
             shapes,
             rotation_system,
-            random_shape_generator: Box::new(TSR::new(n_shapes)),
+            randomizer,
+            seed,
+            random_shape_generator: randomizer.build(n_shapes, seed),
         }
     }
 
@@ -262,7 +573,21 @@ impl ShapeFactory {
         self.shapes = self.rotation_system.build_shapes();
         let n_shapes = self.shapes.len();
         // Update the random shape generator with the new number of shapes
-        self.random_shape_generator = Box::new(TSR::new(n_shapes));
+        self.random_shape_generator = self.randomizer.build(n_shapes, self.seed);
+    }
+
+    pub fn current_randomizer(&self) -> Randomizer {
+        self.randomizer
+    }
+
+    pub fn update_randomizer(&mut self, randomizer: Randomizer) {
+        self.randomizer = randomizer;
+        self.random_shape_generator = randomizer.build(self.shapes.len(), self.seed);
+    }
+
+    /// Seed of the random shape generator
+    pub fn seed(&self) -> u64 {
+        self.seed
     }
 
     pub fn current_shape(&mut self) -> Shape {
@@ -270,7 +595,7 @@ impl ShapeFactory {
         self.shapes[shape_number].clone()
     }
 
-    pub fn next_shape(&mut self) -> Shape {
+    pub fn next_shape(&self) -> Shape {
         let shape_number = self.random_shape_generator.next();
         self.shapes[shape_number].clone()
     }
@@ -280,15 +605,109 @@ impl ShapeFactory {
         &self.shapes
     }
 
+    /// Index of a shape in the list of active shapes, if it's there
+    pub fn shape_index(&self, shape: &Shape) -> Option<usize> {
+        self.shapes.iter().position(|s| s == shape)
+    }
+
+    /// State of the random shape generator
+    pub fn generator_state(&self) -> RsgState {
+        self.random_shape_generator.state()
+    }
+
+    /// Go on dealing shapes from a state returned by
+    /// `generator_state` (e.g. to resume a saved game)
+    pub fn set_generator_state(&mut self, state: &RsgState) {
+        self.random_shape_generator.set_state(state);
+    }
+
     
 }
 
+/// Serialized form of a shape
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ShapeData {
+    cells: Vec<usize>,
+    width: usize,
+    color: [f32; 4],
+    rotation: RotationType,
+    #[serde(default)]
+    row_offset: usize,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    kicks: BTreeMap<String, Vec<[isize; 2]>>,
+}
+
+#[cfg(feature = "serde")]
+impl From<Shape> for ShapeData {
+    fn from(shape: Shape) -> Self {
+        let kicks = shape.kicks.iter().enumerate()
+            .filter(|(_, offsets)| !offsets.is_empty())
+            .map(|(i, offsets)| (
+                format!("{}>{}", i / 4, i % 4),
+                offsets.iter().map(|&(col, row)| [col, row]).collect()))
+            .collect();
+
+        Self {
+            cells: shape.m,
+            width: shape.width,
+            color: [shape.color.r, shape.color.g, shape.color.b, shape.color.a],
+            rotation: shape.rot_type,
+            row_offset: shape.row_offset,
+            kicks,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<ShapeData> for Shape {
+    type Error = String;
+
+    fn try_from(data: ShapeData) -> Result<Self, Self::Error> {
+        let width = data.width;
+        let n_cells = data.cells.len();
+        // Custom shapes have their four rotation states stacked, and
+        // still shapes only need whole rows
+        let (n_states, fits) = match data.rotation {
+            RotationType::CUSTOM => (4, n_cells == 4 * width * width),
+            RotationType::STILL => (1, width > 0 && n_cells > 0
+                && n_cells.is_multiple_of(width)),
+            _ => (1, n_cells == width * width),
+        };
+        if width == 0 || !fits {
+            return Err(format!("{} cells don't fit a shape of width {}",
+                n_cells, width));
+        }
+
+        let [r, g, b, a] = data.color;
+        let mut shape = Shape::new(data.cells, width, Color::new(r, g, b, a),
+            data.rotation).set_row_offset(data.row_offset);
+        shape.n_cells /= n_states;
+
+        for (transition, offsets) in data.kicks {
+            let (from, to) = transition.split_once('>')
+                .and_then(|(from, to)| Some((from.parse().ok()?, to.parse().ok()?)))
+                .filter(|&(from, to): &(isize, isize)|
+                    (0..4).contains(&from) && (0..4).contains(&to))
+                .ok_or_else(|| format!("invalid kick transition '{}'", transition))?;
+            shape = shape.set_kicks(from, to,
+                offsets.iter().map(|o| (o[0], o[1])).collect());
+        }
+
+        Ok(shape)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use macroquad::color::*;
 
     use super::Shape;
     use super::RotationType;
+    use super::{RotationSystem, ShapeBuilder};
+    #[cfg(feature = "serde")]
+    use crate::definition::RotationDefinition;
 
     #[test]
     fn test_rotate_shape() {
@@ -644,4 +1063,293 @@ mod tests {
             }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_rotate_sega_shape() {
+        // 3x3 shape
+        let s = Shape::new(
+            Vec::from([
+                0, 1, 2,
+                3, 4, 5,
+                6, 7, 8,
+            ]),
+            3,
+            BLACK,
+            RotationType::SEGA,
+        );
+
+        // 0º
+        assert_eq!(0, *s.rotate(0, 0, 0));
+        assert_eq!(4, *s.rotate(1, 1, 0));
+        assert_eq!(8, *s.rotate(2, 2, 0));
+        // 90º: top two rows become the left and centre columns
+        assert_eq!(3, *s.rotate(0, 0, 1));
+        assert_eq!(4, *s.rotate(1, 0, 1));
+        assert_eq!(5, *s.rotate(2, 0, 1));
+        assert_eq!(0, *s.rotate(0, 1, 1));
+        assert_eq!(1, *s.rotate(1, 1, 1));
+        assert_eq!(2, *s.rotate(2, 1, 1));
+        assert_eq!(6, *s.rotate(0, 2, 1));
+        assert_eq!(7, *s.rotate(1, 2, 1));
+        assert_eq!(8, *s.rotate(2, 2, 1));
+        // 180º: top two rows swapped and mirrored, bottom row kept
+        assert_eq!(5, *s.rotate(0, 0, 2));
+        assert_eq!(4, *s.rotate(0, 1, 2));
+        assert_eq!(3, *s.rotate(0, 2, 2));
+        assert_eq!(2, *s.rotate(1, 0, 2));
+        assert_eq!(1, *s.rotate(1, 1, 2));
+        assert_eq!(0, *s.rotate(1, 2, 2));
+        assert_eq!(6, *s.rotate(2, 0, 2));
+        assert_eq!(7, *s.rotate(2, 1, 2));
+        assert_eq!(8, *s.rotate(2, 2, 2));
+        // 270º: top two rows become the centre and right columns
+        assert_eq!(8, *s.rotate(0, 0, 3));
+        assert_eq!(7, *s.rotate(1, 0, 3));
+        assert_eq!(6, *s.rotate(2, 0, 3));
+        assert_eq!(2, *s.rotate(0, 1, 3));
+        assert_eq!(1, *s.rotate(1, 1, 3));
+        assert_eq!(0, *s.rotate(2, 1, 3));
+        assert_eq!(5, *s.rotate(0, 2, 3));
+        assert_eq!(4, *s.rotate(1, 2, 3));
+        assert_eq!(3, *s.rotate(2, 2, 3));
+    }
+
+    #[test]
+    fn test_sega_shapes_stay_top_aligned() {
+        let t = Shape::new(
+            Vec::from([
+                1, 1, 1,
+                0, 1, 0,
+                0, 0, 0,
+            ]),
+            3,
+            BLACK,
+            RotationType::SEGA,
+        );
+
+        let cells = |rot: isize| -> Vec<usize> {
+            (0..t.len()).map(|i| *t.rotate(t.row(i), t.col(i), rot)).collect()
+        };
+
+        assert_eq!(vec![1, 1, 1, 0, 1, 0, 0, 0, 0], cells(0));
+        assert_eq!(vec![0, 1, 0, 1, 1, 0, 0, 1, 0], cells(1));
+        assert_eq!(vec![0, 1, 0, 1, 1, 1, 0, 0, 0], cells(2));
+        assert_eq!(vec![0, 1, 0, 0, 1, 1, 0, 1, 0], cells(3));
+    }
+
+    #[test]
+    fn test_rotate_gb_shape() {
+        // 3x3 shape
+        let s = Shape::new(
+            Vec::from([
+                0, 1, 2,
+                3, 4, 5,
+                6, 7, 8,
+            ]),
+            3,
+            BLACK,
+            RotationType::GB,
+        );
+
+        // FIRST ROW ----------------------
+        for i in 0..4 {
+            if i % 2 == 0 {
+                assert_eq!(0, *s.rotate(0, 0, i));
+                assert_eq!(1, *s.rotate(0, 1, i));
+                assert_eq!(2, *s.rotate(0, 2, i));
+            } else {
+                assert_eq!(6, *s.rotate(0, 0, i));
+                assert_eq!(3, *s.rotate(0, 1, i));
+                assert_eq!(0, *s.rotate(0, 2, i));
+            }
+        }
+        // SECOND ROW ----------------------
+        for i in 0..4 {
+            if i % 2 == 0 {
+                assert_eq!(3, *s.rotate(1, 0, i));
+                assert_eq!(4, *s.rotate(1, 1, i));
+                assert_eq!(5, *s.rotate(1, 2, i));
+            } else {
+                assert_eq!(7, *s.rotate(1, 0, i));
+                assert_eq!(4, *s.rotate(1, 1, i));
+                assert_eq!(1, *s.rotate(1, 2, i));
+            }
+        }
+        // THIRD ROW ----------------------
+        for i in 0..4 {
+            if i % 2 == 0 {
+                assert_eq!(6, *s.rotate(2, 0, i));
+                assert_eq!(7, *s.rotate(2, 1, i));
+                assert_eq!(8, *s.rotate(2, 2, i));
+            } else {
+                assert_eq!(8, *s.rotate(2, 0, i));
+                assert_eq!(5, *s.rotate(2, 1, i));
+                assert_eq!(2, *s.rotate(2, 2, i));
+            }
+        }
+
+        // 4x4 shape
+        let s = Shape::new(
+            Vec::from([
+                0,  1,  2,  3,
+                4,  5,  6,  7,
+                8,  9, 10, 11,
+               12, 13, 14, 15,
+            ]),
+            4,
+            BLACK,
+            RotationType::GB,
+        );
+
+        // FIRST ROW ----------------------
+        for i in 0..4 {
+            if i % 2 == 0 {
+                assert_eq!(0, *s.rotate(0, 0, i));
+                assert_eq!(1, *s.rotate(0, 1, i));
+                assert_eq!(2, *s.rotate(0, 2, i));
+                assert_eq!(3, *s.rotate(0, 3, i));
+            } else {
+                assert_eq!(12, *s.rotate(0, 0, i));
+                assert_eq!(8, *s.rotate(0, 1, i));
+                assert_eq!(4, *s.rotate(0, 2, i));
+                assert_eq!(0, *s.rotate(0, 3, i));
+            }
+        }
+        // SECOND ROW ----------------------
+        for i in 0..4 {
+            if i % 2 == 0 {
+                assert_eq!(4, *s.rotate(1, 0, i));
+                assert_eq!(5, *s.rotate(1, 1, i));
+                assert_eq!(6, *s.rotate(1, 2, i));
+                assert_eq!(7, *s.rotate(1, 3, i));
+            } else {
+                assert_eq!(13, *s.rotate(1, 0, i));
+                assert_eq!(9, *s.rotate(1, 1, i));
+                assert_eq!(5, *s.rotate(1, 2, i));
+                assert_eq!(1, *s.rotate(1, 3, i));
+            }
+        }
+        // THIRD ROW ----------------------
+        for i in 0..4 {
+            if i % 2 == 0 {
+                assert_eq!(8, *s.rotate(2, 0, i));
+                assert_eq!(9, *s.rotate(2, 1, i));
+                assert_eq!(10, *s.rotate(2, 2, i));
+                assert_eq!(11, *s.rotate(2, 3, i));
+            } else {
+                assert_eq!(14, *s.rotate(2, 0, i));
+                assert_eq!(10, *s.rotate(2, 1, i));
+                assert_eq!(6, *s.rotate(2, 2, i));
+                assert_eq!(2, *s.rotate(2, 3, i));
+            }
+        }
+        // FOURTH ROW ----------------------
+        for i in 0..4 {
+            if i % 2 == 0 {
+                assert_eq!(12, *s.rotate(3, 0, i));
+                assert_eq!(13, *s.rotate(3, 1, i));
+                assert_eq!(14, *s.rotate(3, 2, i));
+                assert_eq!(15, *s.rotate(3, 3, i));
+            } else {
+                assert_eq!(15, *s.rotate(3, 0, i));
+                assert_eq!(11, *s.rotate(3, 1, i));
+                assert_eq!(7, *s.rotate(3, 2, i));
+                assert_eq!(3, *s.rotate(3, 3, i));
+            }
+        }
+    }
+
+    #[test]
+    fn test_gb_shapes_spawn_states() {
+        let gb = RotationSystem::GB.build_shapes();
+        let nes = RotationSystem::NES.build_shapes();
+        let cells = |shape: &Shape, rot: isize| -> Vec<usize> {
+            (0..shape.len())
+                .map(|i| *shape.rotate(shape.row(i), shape.col(i), rot))
+                .collect()
+        };
+
+        // Same spawn states and rows as the NES
+        for (gb, nes) in gb.iter().zip(&nes) {
+            assert_eq!(cells(nes, 0), cells(gb, 0));
+            assert_eq!(nes.row_offset(), gb.row_offset());
+        }
+
+        // J, L, T and O rotate like in the NES
+        for i in [0, 1, 5, 6] {
+            for rot in 0..4 {
+                assert_eq!(cells(&nes[i], rot), cells(&gb[i], rot));
+            }
+        }
+
+        // S, Z and I toggle to a vertical state that leans left
+        let (s, z, i) = (&gb[2], &gb[3], &gb[4]);
+        for rot in [1, 3] {
+            assert_eq!(vec![
+                1, 0, 0,
+                1, 1, 0,
+                0, 1, 0,
+            ], cells(s, rot));
+            assert_eq!(vec![
+                0, 1, 0,
+                1, 1, 0,
+                1, 0, 0,
+            ], cells(z, rot));
+            assert_eq!(vec![
+                0, 1, 0, 0,
+                0, 1, 0, 0,
+                0, 1, 0, 0,
+                0, 1, 0, 0,
+            ], cells(i, rot));
+        }
+        for rot in [0, 2] {
+            assert_eq!(cells(s, 0), cells(s, rot));
+            assert_eq!(cells(z, 0), cells(z, rot));
+            assert_eq!(cells(i, 0), cells(i, rot));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_shape_serde() {
+        for system in [RotationSystem::SRS, RotationSystem::NES,
+                       RotationSystem::GB, RotationSystem::SEGA] {
+            for shape in system.build_shapes() {
+                let json = serde_json::to_string(&shape).unwrap();
+                assert!(shape == serde_json::from_str::<Shape>(&json).unwrap());
+            }
+        }
+
+        let t = Shape::new(vec![0, 1, 0, 1, 1, 1, 0, 0, 0], 3, PURPLE,
+            RotationType::SRS).set_kicks(0, 1, vec![(0, 0), (-1, 0)]);
+        let json = serde_json::to_value(&t).unwrap();
+        assert_eq!(serde_json::json!([[0, 0], [-1, 0]]), json["kicks"]["0>1"]);
+        assert_eq!("SRS", json["rotation"]);
+        assert_eq!(4, json["color"].as_array().unwrap().len());
+
+        // The cells must fit the width, and kicks need valid states
+        let mut json = serde_json::to_value(&t).unwrap();
+        json["width"] = 4.into();
+        assert!(serde_json::from_value::<Shape>(json).is_err());
+        let mut json = serde_json::to_value(&t).unwrap();
+        json["kicks"]["0>4"] = serde_json::json!([[0, 0]]);
+        assert!(serde_json::from_value::<Shape>(json).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_rotation_system_serde() {
+        assert_eq!("\"SEGA\"",
+            serde_json::to_string(&RotationSystem::SEGA).unwrap());
+        let system: RotationSystem = serde_json::from_str("\"GB\"").unwrap();
+        assert_eq!("GB", system.name());
+
+        let custom = RotationSystem::CUSTOM(RotationDefinition::new(
+            "Custom", Some(0.5), RotationSystem::NES.build_shapes()));
+        let json = serde_json::to_string(&custom).unwrap();
+        let restored: RotationSystem = serde_json::from_str(&json).unwrap();
+        assert_eq!("Custom", restored.name());
+        assert_eq!(Some(0.5), restored.lock_delay());
+        assert!(custom.build_shapes() == restored.build_shapes());
+    }
+}
@@ -0,0 +1,401 @@
+//! Spin detection: pieces fixed right after being rotated into
+//! a tight spot
+//!
+//! T-spins use the 3-corner rule: at least 3 of the 4 cells
+//! diagonally adjacent to the centre of the T must be occupied
+//! (walls and floor included). It's a full T-spin when both
+//! corners at the pointing side of the T are occupied, or when
+//! the rotation used the last kick test and it moved the piece 2
+//! rows (the SRS "TST" or "Fin" kick); otherwise it's a mini T-spin.
+//!
+//! Other pieces (S, Z, L, J and I) can spin too, depending on the
+//! `SpinRule`: they spin when they're fixed after a rotation and
+//! can't move left, right or up (immobility rule).
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::piece::Piece;
+use crate::playfield::Playfield;
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Spin {
+    #[default]
+    None,
+    Mini,
+    Full,
+}
+
+/// Kick used by the last rotation of a piece
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Kick {
+    /// Offset (columns right, rows up)
+    pub offset: (isize, isize),
+    /// It was the last kick test of the rotation
+    pub last: bool,
+}
+
+/// Which pieces can spin
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SpinRule {
+    /// Only T-spins (3-corner rule)
+    TOnly,
+    /// T-spins, plus mini spins for the other pieces (immobility)
+    AllMini,
+    /// T-spins, plus full spins for the other pieces (immobility)
+    AllSpin,
+}
+
+impl SpinRule {
+
+    pub const ALL: [SpinRule; 3] = [
+        SpinRule::TOnly,
+        SpinRule::AllMini,
+        SpinRule::AllSpin,
+    ];
+
+    pub fn name(&self) -> &str {
+        match self {
+            SpinRule::TOnly => "T only",
+            SpinRule::AllMini => "All mini",
+            SpinRule::AllSpin => "All spin",
+        }
+    }
+}
+
+/// True if the given cell is out of the playfield or not empty
+fn occupied(pf: &Playfield, row: isize, col: isize) -> bool {
+    if row < 0 || col < 0
+        || row >= pf.n_rows() as isize || col >= pf.n_cols() as isize {
+        return true;
+    }
+
+    pf.get_cell(row as usize, col as usize) != 0
+}
+
+/// Name of the tetromino (I, O, T, S, Z, L or J) made by the
+/// blocks of the piece, whatever its rotation state.
+///
+/// # Returns
+/// `None` if the piece is not a tetromino
+pub fn tetromino(piece: &Piece) -> Option<char> {
+    let cells = piece.cells();
+    if cells.len() != 4 {
+        return None;
+    }
+
+    let top = cells.iter().map(|(row, _)| *row).min()?;
+    let left = cells.iter().map(|(_, col)| *col).min()?;
+    let mut cells: Vec<(usize, usize)> = cells.iter()
+        .map(|(row, col)| (row - top, col - left))
+        .collect();
+    let height = cells.iter().map(|(row, _)| *row).max()? + 1;
+    let width = cells.iter().map(|(_, col)| *col).max()? + 1;
+
+    match (height, width) {
+        (1, 4) | (4, 1) => return Some('I'),
+        (2, 2) => return Some('O'),
+        (3, 2) => {
+            // Rotate clockwise to lay it down in 2 rows
+            cells = cells.iter().map(|(row, col)| (*col, 2 - row)).collect();
+        }
+        (2, 3) => {}
+        _ => return None,
+    }
+
+    let row_cols = |r: usize| {
+        let mut cols: Vec<usize> = cells.iter()
+            .filter(|(row, _)| *row == r)
+            .map(|(_, col)| *col)
+            .collect();
+        cols.sort();
+        cols
+    };
+
+    match (row_cols(0).as_slice(), row_cols(1).as_slice()) {
+        ([1, 2], [0, 1]) => Some('S'),
+        ([0, 1], [1, 2]) => Some('Z'),
+        ([1], [0, 1, 2]) | ([0, 1, 2], [1]) => Some('T'),
+        ([2], [0, 1, 2]) | ([0, 1, 2], [0]) => Some('L'),
+        ([0], [0, 1, 2]) | ([0, 1, 2], [2]) => Some('J'),
+        _ => None,
+    }
+}
+
+/// True if the piece can't move left, right or up
+fn immobile(piece: &Piece, pf: &Playfield) -> bool {
+    let (shape, row, col, rot) = (piece.shape(), piece.row(), piece.col(), piece.rot());
+
+    pf.collides(shape, row, col - 1, rot)
+        && pf.collides(shape, row, col + 1, rot)
+        && (row == 0 || pf.collides(shape, row - 1, col, rot))
+}
+
+/// Check if fixing the piece at its current position is a spin,
+/// according to the given rule.
+///
+/// # Arguments
+/// * `piece` - The piece, not yet fixed to the playfield
+/// * `pf` - The playfield
+/// * `kick` - Kick used by the last action, if it was a rotation.
+///   `None` if the last action moved the piece.
+/// * `rule` - Which pieces can spin
+pub fn detect(piece: &Piece, pf: &Playfield, kick: Option<Kick>,
+              rule: SpinRule) -> Spin {
+
+    if kick.is_none() {
+        return Spin::None;
+    }
+
+    match (tetromino(piece), rule) {
+        (Some('T'), _) => t_spin(piece, pf, kick),
+        (Some('O') | None, _) | (_, SpinRule::TOnly) => Spin::None,
+        (Some(_), SpinRule::AllMini) if immobile(piece, pf) => Spin::Mini,
+        (Some(_), SpinRule::AllSpin) if immobile(piece, pf) => Spin::Full,
+        _ => Spin::None,
+    }
+}
+
+/// Find the centre of a T shaped piece (its only block with 3
+/// neighbours) and the direction it points to (rows, columns).
+///
+/// # Returns
+/// `None` if the piece is not a T tetromino
+fn t_centre(piece: &Piece) -> Option<((isize, isize), (isize, isize))> {
+    let cells: Vec<(isize, isize)> = piece.cells().iter()
+        .map(|(row, col)| (*row as isize, *col as isize))
+        .collect();
+
+    if cells.len() != 4 {
+        return None;
+    }
+
+    let directions = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+    for (row, col) in &cells {
+        let neighbours: Vec<(isize, isize)> = directions.iter()
+            .filter(|(dr, dc)| cells.contains(&(row + dr, col + dc)))
+            .copied()
+            .collect();
+
+        if neighbours.len() == 3 {
+            // The T points to the neighbour opposite to the
+            // missing one
+            let front = directions.iter()
+                .find(|(dr, dc)| neighbours.contains(&(*dr, *dc))
+                    && !neighbours.contains(&(-dr, -dc)))
+                .copied()?;
+
+            return Some(((*row, *col), front));
+        }
+    }
+
+    None
+}
+
+/// Check if fixing the piece at its current position is a T-spin.
+///
+/// # Arguments
+/// * `piece` - The piece, not yet fixed to the playfield
+/// * `pf` - The playfield
+/// * `kick` - Kick used by the last action, if it was a rotation.
+///   `None` if the last action moved the piece.
+pub fn t_spin(piece: &Piece, pf: &Playfield, kick: Option<Kick>) -> Spin {
+
+    let Some(kick) = kick else {
+        return Spin::None;
+    };
+
+    let Some(((row, col), (front_row, front_col))) = t_centre(piece) else {
+        return Spin::None;
+    };
+
+    let corners = [(-1, -1), (-1, 1), (1, -1), (1, 1)];
+    let occupied_corners = corners.iter()
+        .filter(|(dr, dc)| occupied(pf, row + dr, col + dc))
+        .count();
+
+    if occupied_corners < 3 {
+        return Spin::None;
+    }
+
+    // Corners at the pointing side: same row (or column) offset as
+    // the front direction
+    let front_corners = corners.iter()
+        .filter(|(dr, dc)| (front_row != 0 && *dr == front_row)
+            || (front_col != 0 && *dc == front_col))
+        .filter(|(dr, dc)| occupied(pf, row + dr, col + dc))
+        .count();
+
+    if front_corners == 2 || (kick.last && kick.offset.1.abs() == 2) {
+        Spin::Full
+    } else {
+        Spin::Mini
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::color::BLACK;
+
+    use super::{detect, t_spin, tetromino, Kick, Spin, SpinRule};
+    use crate::piece::Piece;
+    use crate::playfield::Playfield;
+    use crate::shape::{RotationType, Shape};
+
+    /// Kick of a rotation that isn't the last kick test
+    fn kick(cols: isize, rows_up: isize) -> Option<Kick> {
+        Some(Kick { offset: (cols, rows_up), last: false })
+    }
+
+    fn t_shape() -> Shape {
+        Shape::new(
+            vec![0, 1, 0,
+                 1, 1, 1,
+                 0, 0, 0],
+            3,
+            BLACK,
+            RotationType::SRS,
+        )
+    }
+
+    /// T pointing down, on the floor: centre at (20, col + 1)
+    fn t_down(pf: &Playfield) -> Piece {
+        let mut piece = Piece::spawn(t_shape(), pf);
+        piece.rotate_in_place(pf, 2);
+        while piece.move_down(pf) {}
+        piece
+    }
+
+    #[test]
+    fn test_t_spin() {
+        let mut pf = Playfield::new();
+        let block = Shape::new(vec![1], 1, BLACK, RotationType::STILL);
+        let piece = t_down(&pf);
+        let col = piece.col();
+        assert_eq!(19, piece.row());
+
+        // Front corners (below the T) only
+        pf.add(&block, 21, col, 0);
+        pf.add(&block, 21, col + 2, 0);
+        assert_eq!(Spin::None, t_spin(&piece, &pf, kick(0, 0)));
+
+        // 3 corners, both front corners
+        pf.add(&block, 19, col, 0);
+        assert_eq!(Spin::Full, t_spin(&piece, &pf, kick(0, 0)));
+        // The last action wasn't a rotation
+        assert_eq!(Spin::None, t_spin(&piece, &pf, None));
+    }
+
+    #[test]
+    fn test_t_spin_mini() {
+        let mut pf = Playfield::new();
+        let block = Shape::new(vec![1], 1, BLACK, RotationType::STILL);
+        let piece = t_down(&pf);
+        let col = piece.col();
+
+        // 3 corners, only one at the front
+        pf.add(&block, 19, col, 0);
+        pf.add(&block, 19, col + 2, 0);
+        pf.add(&block, 21, col, 0);
+        assert_eq!(Spin::Mini, t_spin(&piece, &pf, kick(1, 0)));
+
+        // The last kick test moving the piece 2 rows (TST or Fin
+        // kick) makes a full T-spin, other kicks don't
+        let fin = Kick { offset: (-1, -2), last: true };
+        assert_eq!(Spin::Full, t_spin(&piece, &pf, Some(fin)));
+        assert_eq!(Spin::Mini, t_spin(&piece, &pf, kick(0, -2)));
+
+        // T pointing right against the left wall: the wall gives
+        // the back corners
+        let mut pf = Playfield::new();
+        let mut piece = Piece::spawn(t_shape(), &pf);
+        piece.rotate_in_place(&pf, 1);
+        while piece.shift(&pf, -1) {}
+        while piece.move_down(&pf) {}
+        // Centre at (20, 1): back corners in the wall, front corners
+        // at (19, 2) and (21, 2)
+        assert_eq!((19, 0), (piece.row(), piece.col()));
+        pf.add(&block, 19, 2, 0);
+        assert_eq!(Spin::Mini, t_spin(&piece, &pf, kick(0, 0)));
+        pf.add(&block, 21, 2, 0);
+        assert_eq!(Spin::Full, t_spin(&piece, &pf, kick(0, 0)));
+    }
+
+    #[test]
+    fn test_not_a_t() {
+        let pf = Playfield::new();
+        let o = Shape::new(vec![1, 1, 1, 1], 2, BLACK, RotationType::STILL);
+        let mut piece = Piece::spawn(o, &pf);
+        while piece.move_down(&pf) {}
+        assert_eq!(Spin::None, t_spin(&piece, &pf, kick(0, 0)));
+    }
+
+    #[test]
+    fn test_tetromino() {
+        let pf = Playfield::new();
+        let shapes = [
+            ('T', vec![0, 1, 0, 1, 1, 1, 0, 0, 0]),
+            ('S', vec![0, 1, 1, 1, 1, 0, 0, 0, 0]),
+            ('Z', vec![1, 1, 0, 0, 1, 1, 0, 0, 0]),
+            ('L', vec![0, 0, 1, 1, 1, 1, 0, 0, 0]),
+            ('J', vec![1, 0, 0, 1, 1, 1, 0, 0, 0]),
+        ];
+
+        // Same name in all the rotation states
+        for (name, m) in shapes {
+            let shape = Shape::new(m, 3, BLACK, RotationType::SRS);
+            let mut piece = Piece::spawn(shape, &pf);
+            for rot in 0..4 {
+                piece.rotate_in_place(&pf, rot);
+                assert_eq!(Some(name), tetromino(&piece));
+            }
+        }
+
+        let i = Shape::new(vec![0, 0, 0, 0, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0],
+            4, BLACK, RotationType::SRS);
+        let mut piece = Piece::spawn(i, &pf);
+        assert_eq!(Some('I'), tetromino(&piece));
+        piece.rotate_in_place(&pf, 1);
+        assert_eq!(Some('I'), tetromino(&piece));
+
+        let o = Shape::new(vec![1, 1, 1, 1], 2, BLACK, RotationType::STILL);
+        assert_eq!(Some('O'), tetromino(&Piece::spawn(o, &pf)));
+
+        let block = Shape::new(vec![1], 1, BLACK, RotationType::STILL);
+        assert_eq!(None, tetromino(&Piece::spawn(block, &pf)));
+    }
+
+    #[test]
+    fn test_all_spin() {
+        let mut pf = Playfield::new();
+        let block = Shape::new(vec![1], 1, BLACK, RotationType::STILL);
+
+        // Vertical I against the left wall
+        let i = Shape::new(vec![0, 0, 0, 0, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0],
+            4, BLACK, RotationType::SRS);
+        let mut piece = Piece::spawn(i, &pf);
+        piece.rotate_in_place(&pf, 1);
+        while piece.shift(&pf, -1) {}
+        while piece.move_down(&pf) {}
+        let col = (piece.col() + 2) as usize;
+        assert_eq!(1, col);
+
+        // It can move right and up
+        let kick = kick(0, 0);
+        assert_eq!(Spin::None, detect(&piece, &pf, kick, SpinRule::AllSpin));
+
+        // Stuck in a well, covered by a block
+        for row in 18..22 {
+            pf.add(&block, row, 2, 0);
+        }
+        pf.add(&block, 17, 1, 0);
+        assert_eq!(Spin::Full, detect(&piece, &pf, kick, SpinRule::AllSpin));
+        assert_eq!(Spin::Mini, detect(&piece, &pf, kick, SpinRule::AllMini));
+        assert_eq!(Spin::None, detect(&piece, &pf, kick, SpinRule::TOnly));
+        // The last action wasn't a rotation
+        assert_eq!(Spin::None, detect(&piece, &pf, None, SpinRule::AllSpin));
+    }
+}
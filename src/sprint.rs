@@ -0,0 +1,193 @@
+//! Sprint mode: clear a number of lines as fast as possible
+//!
+//! Games are timed by ticks (see `Game::ticks`), so times are exact
+//! and replays reproduce them. The split times (every 10 lines) of
+//! the best game for each rules and goal are kept in persistent
+//! storage (see `storage`) as TOML, by rules and goal:
+//!
+//! ```toml
+//! "Modern 40" = [712, 1405, 2130, 2866]
+//! ```
+//!
+//! They're the "ghost" the splits of new games are compared to.
+
+#[cfg(feature = "serde")]
+use std::collections::BTreeMap;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::game::TICK;
+#[cfg(feature = "serde")]
+use crate::storage::{self, StorageError};
+
+
+/// Storage key of the personal bests
+#[cfg(feature = "serde")]
+const STORAGE_KEY: &str = "sprint";
+
+/// Split times of the best game for each rules and goal, in ticks
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct PersonalBests {
+    bests: BTreeMap<String, Vec<u64>>,
+}
+
+#[cfg(feature = "serde")]
+impl PersonalBests {
+
+    /// Parse stored personal bests, keeping every valid one
+    pub fn from_toml(src: &str) -> Self {
+        let mut bests = Self::default();
+        let Ok(table) = toml::from_str::<toml::Table>(src) else {
+            return bests;
+        };
+
+        for (key, splits) in table {
+            let valid_key = key.rsplit_once(' ').is_some_and(|(rules, goal)|
+                !rules.is_empty() && goal.parse::<usize>().is_ok());
+            if !valid_key {
+                continue;
+            }
+            if let Ok(splits) = splits.try_into::<Vec<u64>>()
+                && !splits.is_empty()
+                && splits.windows(2).all(|pair| pair[0] <= pair[1]) {
+                bests.bests.insert(key, splits);
+            }
+        }
+
+        bests
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap_or_default()
+    }
+
+    /// Load the stored personal bests (none if there are none)
+    pub fn load() -> Self {
+        storage::load(STORAGE_KEY)
+            .map(|src| Self::from_toml(&src))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), StorageError> {
+        storage::save(STORAGE_KEY, &self.to_toml())
+    }
+
+    /// Split times of the best game with the given rules (e.g.
+    /// "Modern") clearing the given lines
+    pub fn best(&self, rules: &str, goal: usize) -> Option<&[u64]> {
+        self.bests.get(&key(rules, goal)).map(|splits| splits.as_slice())
+    }
+
+    /// Keep the splits of a finished game if it beats the best one
+    /// (the last split is the final time)
+    ///
+    /// # Returns
+    /// True if it's a new personal best
+    pub fn record(&mut self, rules: &str, goal: usize, splits: &[u64]) -> bool {
+        let Some(&time) = splits.last() else {
+            return false;
+        };
+        if self.best(rules, goal).and_then(|best| best.last())
+            .is_some_and(|&best| best <= time) {
+            return false;
+        }
+
+        self.bests.insert(key(rules, goal), splits.to_vec());
+        true
+    }
+}
+
+/// Stored name of the personal best of some rules and goal
+#[cfg(feature = "serde")]
+fn key(rules: &str, goal: usize) -> String {
+    format!("{} {}", rules, goal)
+}
+
+/// Format a time given in ticks as minutes, seconds and milliseconds
+/// (e.g. 1:05.417)
+pub fn format_ticks(ticks: u64) -> String {
+    let millis = (ticks as f64 * TICK * 1000.0).round() as u64;
+    format!("{}:{:02}.{:03}",
+        millis / 60000, (millis / 1000) % 60, millis % 1000)
+}
+
+/// Format the difference between a time and the best one, both in
+/// ticks, as signed seconds and milliseconds (e.g. -0.517)
+pub fn format_delta(ticks: u64, best: u64) -> String {
+    let millis = ((ticks as f64 - best as f64) * TICK * 1000.0).round() as i64;
+    let sign = if millis < 0 { '-' } else { '+' };
+    format!("{}{}.{:03}", sign, millis.abs() / 1000, millis.abs() % 1000)
+}
+
+/// Pieces per second
+pub fn pps(pieces: usize, ticks: u64) -> f64 {
+    if ticks == 0 {
+        return 0.0;
+    }
+    pieces as f64 / (ticks as f64 * TICK)
+}
+
+/// Keys pressed per piece
+pub fn kpp(keys: usize, pieces: usize) -> f64 {
+    if pieces == 0 {
+        return 0.0;
+    }
+    keys as f64 / pieces as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_delta, format_ticks, kpp, pps};
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_personal_bests() {
+        use super::PersonalBests;
+
+        let mut bests = PersonalBests::default();
+        assert_eq!(None, bests.best("Modern", 40));
+        assert!(!bests.record("Modern", 40, &[]));
+
+        assert!(bests.record("Modern", 40, &[700, 1400, 2100, 2800]));
+        assert!(!bests.record("Modern", 40, &[600, 1300, 2000, 2800]));
+        assert!(bests.record("Modern", 40, &[750, 1450, 2100, 2790]));
+        assert_eq!(Some(750), bests.best("Modern", 40).map(|best| best[0]));
+        assert!(bests.record("Modern", 20, &[500, 1000]));
+
+        // Each rules have their own best
+        assert_eq!(None, bests.best("Classic", 40));
+        assert!(bests.record("Classic", 40, &[900, 1800, 2700, 3600]));
+        assert_eq!(Some(2790), bests.best("Modern", 40).map(|best| best[3]));
+
+        assert_eq!(bests, PersonalBests::from_toml(&bests.to_toml()));
+
+        // Invalid entries are dropped
+        let bests = PersonalBests::from_toml(r#"
+            "Modern 40" = [700, 600]
+            "Modern 20" = "fast"
+            "Modern lots" = [100]
+            40 = [100]
+            "Modern 100" = [100, 200]
+        "#);
+        assert_eq!(None, bests.best("Modern", 40));
+        assert_eq!(None, bests.best("Modern", 20));
+        assert_eq!(Some(&[100, 200][..]), bests.best("Modern", 100));
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!("0:00.000", format_ticks(0));
+        assert_eq!("0:01.017", format_ticks(61));
+        assert_eq!("1:05.000", format_ticks(3900));
+        assert_eq!("+0.500", format_delta(130, 100));
+        assert_eq!("-1.017", format_delta(100, 161));
+        assert_eq!("+0.000", format_delta(100, 100));
+
+        assert_eq!(2.0, pps(120, 3600));
+        assert_eq!(0.0, pps(0, 0));
+        assert_eq!(2.5, kpp(250, 100));
+    }
+}
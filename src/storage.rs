@@ -0,0 +1,111 @@
+//! Persistent key-value storage
+//!
+//! Values are saved as files in the `data` directory on native
+//! builds, and in the browser localStorage on the web (through the
+//! `storage.js` plugin loaded by `index.html`).
+
+use std::fmt;
+
+
+#[derive(Debug, PartialEq)]
+pub enum StorageError {
+    /// The value couldn't be written
+    Io(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageError::Io(message) => write!(f, "can't save data: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+/// Directory of the stored values on native builds
+#[cfg(not(target_arch = "wasm32"))]
+const DATA_DIR: &str = "data";
+
+#[cfg(not(target_arch = "wasm32"))]
+fn path(key: &str) -> std::path::PathBuf {
+    std::path::Path::new(DATA_DIR).join(format!("{}.toml", key))
+}
+
+/// The value stored with the given key, if any
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load(key: &str) -> Option<String> {
+    std::fs::read_to_string(path(key)).ok()
+}
+
+/// Store a value with the given key, replacing the previous one
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save(key: &str, value: &str) -> Result<(), StorageError> {
+    std::fs::create_dir_all(DATA_DIR)
+        .and_then(|_| std::fs::write(path(key), value))
+        .map_err(|e| StorageError::Io(e.to_string()))
+}
+
+/// Remove the value stored with the given key, if any
+#[cfg(not(target_arch = "wasm32"))]
+pub fn remove(key: &str) -> Result<(), StorageError> {
+    match std::fs::remove_file(path(key)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound =>
+            Err(StorageError::Io(e.to_string())),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+unsafe extern "C" {
+    fn storage_length(key: *const u8, key_len: usize) -> i32;
+    fn storage_read(key: *const u8, key_len: usize, dest: *mut u8);
+    fn storage_write(key: *const u8, key_len: usize,
+                     value: *const u8, value_len: usize) -> bool;
+    fn storage_remove(key: *const u8, key_len: usize) -> bool;
+}
+
+/// The value stored with the given key, if any
+#[cfg(target_arch = "wasm32")]
+pub fn load(key: &str) -> Option<String> {
+    // SAFETY: the plugin reads `key_len` bytes from `key`, and writes
+    // exactly `len` bytes (the length it just returned) to `dest`
+    unsafe {
+        let len = storage_length(key.as_ptr(), key.len());
+        if len < 0 {
+            return None;
+        }
+
+        let mut bytes = vec![0u8; len as usize];
+        storage_read(key.as_ptr(), key.len(), bytes.as_mut_ptr());
+        String::from_utf8(bytes).ok()
+    }
+}
+
+/// Store a value with the given key, replacing the previous one
+#[cfg(target_arch = "wasm32")]
+pub fn save(key: &str, value: &str) -> Result<(), StorageError> {
+    // SAFETY: the plugin only reads the given bytes
+    let saved = unsafe {
+        storage_write(key.as_ptr(), key.len(), value.as_ptr(), value.len())
+    };
+
+    if saved {
+        Ok(())
+    } else {
+        Err(StorageError::Io("localStorage is not available".to_string()))
+    }
+}
+
+/// Remove the value stored with the given key, if any
+#[cfg(target_arch = "wasm32")]
+pub fn remove(key: &str) -> Result<(), StorageError> {
+    // SAFETY: the plugin only reads the given bytes
+    let removed = unsafe { storage_remove(key.as_ptr(), key.len()) };
+
+    if removed {
+        Ok(())
+    } else {
+        Err(StorageError::Io("localStorage is not available".to_string()))
+    }
+}
@@ -0,0 +1,395 @@
+//! Touch screen controls
+//!
+//! Two layouts are available:
+//! * Gestures: a horizontal drag moves the piece one column every
+//!   `drag_distance`, a downward drag soft drops it one row every
+//!   `drag_distance`, a fast downward flick hard drops it, a tap
+//!   rotates it (clockwise on the right half of the screen,
+//!   counterclockwise on the left half) and a two-finger tap holds it.
+//! * Buttons: on-screen buttons that work like keys.
+//!
+//! Distances and speeds are given in blocks, so they don't depend on
+//! the size of the screen.
+
+use macroquad::prelude::*;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::input::{Action, Input};
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TouchLayout {
+    Gestures,
+    Buttons,
+}
+
+impl TouchLayout {
+
+    pub const ALL: [TouchLayout; 2] = [
+        TouchLayout::Gestures,
+        TouchLayout::Buttons,
+    ];
+
+    pub fn name(&self) -> &str {
+        match self {
+            TouchLayout::Gestures => "Gestures",
+            TouchLayout::Buttons => "Buttons",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GestureSettings {
+    /// Drag distance that moves the piece one column (or one row
+    /// down), in blocks
+    pub drag_distance: f32,
+    /// Minimum speed of a flick, in blocks per second
+    pub flick_speed: f32,
+    /// Minimum length of a flick, in blocks
+    pub flick_distance: f32,
+    /// Maximum duration of a tap, in seconds
+    pub tap_time: f64,
+    /// Maximum distance a finger can move during a tap, in blocks
+    pub tap_distance: f32,
+}
+
+impl Default for GestureSettings {
+    fn default() -> Self {
+        Self {
+            drag_distance: 1.5,
+            flick_speed: 40.0,
+            flick_distance: 3.0,
+            tap_time: 0.25,
+            tap_distance: 0.5,
+        }
+    }
+}
+
+/// What the gestures of a frame do to the piece
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Gestures {
+    /// Columns to move the piece (negative to the left)
+    pub shift: isize,
+    /// Rows to soft drop the piece
+    pub drops: usize,
+    /// Pressed actions (rotations, hard drop and hold)
+    pub actions: Vec<Action>,
+}
+
+/// A finger on the screen
+struct Stroke {
+    id: u64,
+    start: Vec2,
+    start_time: f64,
+    // Position of the last column or row moved
+    anchor: Vec2,
+}
+
+/// Turns touches into gestures. A gesture starts when a finger
+/// touches the screen and ends when all of them are lifted.
+pub struct GestureRecognizer {
+    settings: GestureSettings,
+    strokes: Vec<Stroke>,
+    // Most fingers on the screen at once during the gesture
+    fingers: usize,
+    // The gesture moved the piece, so it can't be a tap
+    moved: bool,
+    start_time: f64,
+}
+
+impl GestureRecognizer {
+
+    pub fn new(settings: GestureSettings) -> Self {
+        Self {
+            settings,
+            strokes: Vec::new(),
+            fingers: 0,
+            moved: false,
+            start_time: 0.0,
+        }
+    }
+
+    pub fn settings(&self) -> &GestureSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: GestureSettings) {
+        self.settings = settings;
+    }
+
+    /// Process the touches of the current frame
+    ///
+    /// # Arguments
+    /// * `touches` - Touches of the frame
+    /// * `time` - Current time, in seconds
+    /// * `block_size` - Size of a block on the screen, in pixels
+    pub fn update(&mut self, touches: &[Touch], time: f64, block_size: f32) -> Gestures {
+        let mut gestures = Gestures::default();
+
+        // Forget touches that ended while nobody was listening
+        // (e.g. in a menu)
+        if touches.is_empty() {
+            self.strokes.clear();
+        }
+
+        for touch in touches {
+            self.touch(touch, time, block_size, screen_width(), &mut gestures);
+        }
+
+        gestures
+    }
+
+    /// Process a single touch event on a screen of the given width
+    fn touch(&mut self, touch: &Touch, time: f64, block_size: f32, width: f32,
+             gestures: &mut Gestures) {
+
+        let (id, phase, position) = (touch.id, touch.phase, touch.position);
+        let step = self.settings.drag_distance * block_size;
+
+        match phase {
+            TouchPhase::Started => {
+                self.strokes.retain(|stroke| stroke.id != id);
+                if self.strokes.is_empty() {
+                    self.fingers = 0;
+                    self.moved = false;
+                    self.start_time = time;
+                }
+
+                self.strokes.push(Stroke {
+                    id,
+                    start: position,
+                    start_time: time,
+                    anchor: position,
+                });
+                self.fingers = self.fingers.max(self.strokes.len());
+            }
+            TouchPhase::Moved | TouchPhase::Stationary => {
+                let single = self.fingers == 1;
+                let tap_distance = self.settings.tap_distance * block_size;
+                let Some(stroke) = self.strokes.iter_mut()
+                    .find(|stroke| stroke.id == id) else {
+                    return;
+                };
+
+                if position.distance(stroke.start) > tap_distance {
+                    self.moved = true;
+                }
+
+                // Only single finger drags move the piece
+                if !single || step <= 0.0 {
+                    return;
+                }
+
+                while position.x - stroke.anchor.x >= step {
+                    gestures.shift += 1;
+                    stroke.anchor.x += step;
+                }
+                while stroke.anchor.x - position.x >= step {
+                    gestures.shift -= 1;
+                    stroke.anchor.x -= step;
+                }
+
+                // Dragging up moves the anchor, so the next drag
+                // down starts from there
+                if position.y < stroke.anchor.y {
+                    stroke.anchor.y = position.y;
+                }
+                while position.y - stroke.anchor.y >= step {
+                    gestures.drops += 1;
+                    stroke.anchor.y += step;
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                let Some(index) = self.strokes.iter()
+                    .position(|stroke| stroke.id == id) else {
+                    return;
+                };
+                let stroke = self.strokes.remove(index);
+
+                if phase == TouchPhase::Cancelled {
+                    self.moved = true;
+                    return;
+                }
+
+                let delta = position - stroke.start;
+                let duration = (time - stroke.start_time).max(f64::EPSILON);
+                let speed = delta.y / duration as f32;
+
+                if self.fingers == 1
+                    && delta.y >= self.settings.flick_distance * block_size
+                    && delta.y > delta.x.abs()
+                    && speed >= self.settings.flick_speed * block_size {
+                    gestures.actions.push(Action::HardDrop);
+                    self.moved = true;
+                }
+
+                // Taps are recognized when the last finger is lifted
+                let tap = self.strokes.is_empty() && !self.moved
+                    && time - self.start_time <= self.settings.tap_time;
+
+                if tap && self.fingers > 1 {
+                    gestures.actions.push(Action::Hold);
+                } else if tap && stroke.start.x > width / 2.0 {
+                    gestures.actions.push(Action::RotateCw);
+                } else if tap {
+                    gestures.actions.push(Action::RotateCcw);
+                }
+            }
+        }
+    }
+}
+
+/// Position of the on-screen buttons: directions and drops at the
+/// bottom left corner, rotations and hold at the bottom right one
+///
+/// # Arguments
+/// * `width`, `height` - Size of the screen, in pixels
+/// * `block_size` - Size of a block on the screen, in pixels
+pub fn buttons(width: f32, height: f32, block_size: f32) -> Vec<(Action, Rect)> {
+    let size = 2.5 * block_size;
+    let margin = 0.25 * block_size;
+
+    let bottom = height - margin - size;
+    let top = bottom - margin - size;
+    // Columns from each side of the screen
+    let left = |i: f32| margin + (i * (size + margin));
+    let right = |i: f32| width - size - left(i);
+
+    let button = |action, x, y| (action, Rect::new(x, y, size, size));
+
+    vec![
+        button(Action::MoveLeft, left(0.0), bottom),
+        button(Action::SoftDrop, left(1.0), bottom),
+        button(Action::MoveRight, left(2.0), bottom),
+        button(Action::HardDrop, left(1.0), top),
+        button(Action::RotateCw, right(0.0), bottom),
+        button(Action::RotateCcw, right(1.0), bottom),
+        button(Action::Rotate180, right(0.0), top),
+        button(Action::Hold, right(1.0), top),
+    ]
+}
+
+/// Press the actions of the on-screen buttons being touched
+pub fn press_buttons(buttons: &[(Action, Rect)], touches: &[Touch], input: &mut Input) {
+    for touch in touches {
+        for (action, rect) in buttons {
+            if !rect.contains(touch.position) {
+                continue;
+            }
+
+            match touch.phase {
+                TouchPhase::Started => {
+                    input.press(*action);
+                    input.hold(*action);
+                }
+                TouchPhase::Moved | TouchPhase::Stationary => input.hold(*action),
+                TouchPhase::Ended | TouchPhase::Cancelled => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use macroquad::prelude::*;
+
+    use super::{buttons, press_buttons, GestureRecognizer, GestureSettings, Gestures};
+    use crate::input::{Action, Input};
+
+    // Blocks of 10 pixels, moving a column every 10 pixels
+    const BLOCK: f32 = 10.0;
+    const WIDTH: f32 = 300.0;
+
+    fn touch(recognizer: &mut GestureRecognizer, id: u64, phase: TouchPhase,
+             x: f32, y: f32, time: f64, gestures: &mut Gestures) {
+        let touch = Touch { id, phase, position: vec2(x, y) };
+        recognizer.touch(&touch, time, BLOCK, WIDTH, gestures);
+    }
+
+    fn recognizer() -> GestureRecognizer {
+        GestureRecognizer::new(GestureSettings {
+            drag_distance: 1.0,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_drag() {
+        let mut recognizer = recognizer();
+        let mut gestures = Gestures::default();
+
+        touch(&mut recognizer, 0, TouchPhase::Started, 100.0, 100.0, 0.0, &mut gestures);
+        touch(&mut recognizer, 0, TouchPhase::Moved, 132.0, 100.0, 0.5, &mut gestures);
+        assert_eq!(3, gestures.shift);
+
+        touch(&mut recognizer, 0, TouchPhase::Moved, 115.0, 100.0, 1.0, &mut gestures);
+        assert_eq!(2, gestures.shift);
+
+        // Dragging up doesn't drop, but dragging down again does
+        touch(&mut recognizer, 0, TouchPhase::Moved, 115.0, 80.0, 1.5, &mut gestures);
+        touch(&mut recognizer, 0, TouchPhase::Moved, 115.0, 105.0, 2.0, &mut gestures);
+        assert_eq!(2, gestures.drops);
+
+        // Slow drags are neither flicks nor taps
+        touch(&mut recognizer, 0, TouchPhase::Ended, 115.0, 105.0, 2.0, &mut gestures);
+        assert!(gestures.actions.is_empty());
+    }
+
+    #[test]
+    fn test_flick() {
+        let mut recognizer = recognizer();
+        let mut gestures = Gestures::default();
+
+        touch(&mut recognizer, 0, TouchPhase::Started, 100.0, 100.0, 0.0, &mut gestures);
+        touch(&mut recognizer, 0, TouchPhase::Ended, 105.0, 150.0, 0.1, &mut gestures);
+        assert_eq!(vec![Action::HardDrop], gestures.actions);
+    }
+
+    #[test]
+    fn test_taps() {
+        let mut recognizer = recognizer();
+        let mut gestures = Gestures::default();
+
+        // Taps on the left half of the screen rotate counterclockwise
+        touch(&mut recognizer, 0, TouchPhase::Started, 1.0, 100.0, 0.0, &mut gestures);
+        touch(&mut recognizer, 0, TouchPhase::Ended, 2.0, 100.0, 0.1, &mut gestures);
+        assert_eq!(vec![Action::RotateCcw], gestures.actions);
+
+        // Two-finger taps hold
+        let mut gestures = Gestures::default();
+        touch(&mut recognizer, 1, TouchPhase::Started, 1.0, 100.0, 1.0, &mut gestures);
+        touch(&mut recognizer, 2, TouchPhase::Started, 50.0, 100.0, 1.05, &mut gestures);
+        touch(&mut recognizer, 1, TouchPhase::Ended, 1.0, 100.0, 1.1, &mut gestures);
+        assert!(gestures.actions.is_empty());
+        touch(&mut recognizer, 2, TouchPhase::Ended, 50.0, 100.0, 1.15, &mut gestures);
+        assert_eq!(vec![Action::Hold], gestures.actions);
+
+        // Long presses aren't taps
+        let mut gestures = Gestures::default();
+        touch(&mut recognizer, 3, TouchPhase::Started, 1.0, 100.0, 2.0, &mut gestures);
+        touch(&mut recognizer, 3, TouchPhase::Ended, 1.0, 100.0, 3.0, &mut gestures);
+        assert!(gestures.actions.is_empty());
+    }
+
+    #[test]
+    fn test_buttons() {
+        let buttons = buttons(WIDTH, WIDTH, BLOCK);
+        let (action, rect) = buttons[0];
+        assert_eq!(Action::MoveLeft, action);
+        assert!(rect.bottom() <= WIDTH);
+
+        let touch = |phase| Touch { id: 0, phase, position: rect.center() };
+
+        let mut input = Input::default();
+        press_buttons(&buttons, &[touch(TouchPhase::Started)], &mut input);
+        assert!(input.is_pressed(Action::MoveLeft));
+        assert!(input.held().left);
+
+        let mut input = Input::default();
+        press_buttons(&buttons, &[touch(TouchPhase::Stationary)], &mut input);
+        assert!(!input.is_pressed(Action::MoveLeft));
+        assert!(input.held().left);
+    }
+}
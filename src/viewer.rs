@@ -0,0 +1,288 @@
+//! Replay viewer: plays a replay back with pause, frame stepping,
+//! speed control and seeking
+//!
+//! Seeking restores the closest snapshot of the game taken while the
+//! replay was played (one every `SNAPSHOT_TICKS`) and plays on from
+//! there, so it never has to start over from the first tick.
+
+use crate::game::{Game, Phase, TICK};
+use crate::replay::{Player, Replay};
+use crate::shape::RotationSystem;
+
+
+/// Playback speeds
+pub const SPEEDS: [f64; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+
+/// Index of the normal speed in `SPEEDS`
+const NORMAL_SPEED: usize = 2;
+
+/// Ticks between two snapshots (5 seconds of game)
+const SNAPSHOT_TICKS: u64 = 300;
+
+/// Most ticks played in a single update, so slow frames don't make
+/// the playback fall further behind
+const MAX_TICKS_PER_UPDATE: usize = 80;
+
+/// The state of the playback at a given tick
+#[derive(Clone)]
+struct Snapshot {
+    game: Game,
+    player: Player,
+}
+
+pub struct ReplayViewer {
+    game: Game,
+    player: Player,
+    // Sorted by tick
+    snapshots: Vec<Snapshot>,
+    paused: bool,
+    speed: usize,
+    // Time not played yet, in seconds
+    accumulator: f64,
+}
+
+impl ReplayViewer {
+
+    /// Viewer of a replay, ready to play its first tick
+    ///
+    /// # Arguments
+    /// * `replay` - The replay to play
+    /// * `rotation_system` - The rotation system named in the replay
+    pub fn new(replay: Replay, rotation_system: RotationSystem) -> Self {
+        let mut game = Game::new(rotation_system.clone(), replay.ruleset.clone());
+        replay.start(&mut game, rotation_system);
+
+        let player = Player::new(replay);
+        let snapshots = vec![Snapshot { game: game.clone(), player: player.clone() }];
+
+        Self {
+            game,
+            player,
+            snapshots,
+            paused: false,
+            speed: NORMAL_SPEED,
+            accumulator: 0.0,
+        }
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    pub fn player(&self) -> &Player {
+        &self.player
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.accumulator = 0.0;
+    }
+
+    /// Playback speed (1 is the normal speed)
+    pub fn speed(&self) -> f64 {
+        SPEEDS[self.speed]
+    }
+
+    /// Play faster (`delta` > 0) or slower (`delta` < 0), within the
+    /// available speeds
+    pub fn change_speed(&mut self, delta: isize) {
+        self.speed = (self.speed as isize + delta)
+            .clamp(0, SPEEDS.len() as isize - 1) as usize;
+    }
+
+    /// Number of the current piece (the first one is 1)
+    pub fn piece(&self) -> usize {
+        self.game.stats().pieces + 1
+    }
+
+    /// Advance the playback the given real time, unless it's paused
+    pub fn update(&mut self, dt: f64) {
+        if self.paused {
+            return;
+        }
+
+        self.accumulator += dt * self.speed();
+        let mut ticks = 0;
+        while self.accumulator >= TICK && ticks < MAX_TICKS_PER_UPDATE {
+            self.accumulator -= TICK;
+            ticks += 1;
+            if !self.play_tick() {
+                break;
+            }
+        }
+
+        if ticks == MAX_TICKS_PER_UPDATE {
+            self.accumulator = 0.0;
+        }
+    }
+
+    /// Pause the playback and play a single tick
+    pub fn step(&mut self) {
+        self.paused = true;
+        self.play_tick();
+    }
+
+    /// Go to the moment the given piece spawned (or to the end of the
+    /// replay, if it never did)
+    pub fn seek(&mut self, piece: usize) {
+        let locked = piece.max(1) - 1;
+
+        // Latest snapshot before the piece spawned (the first one is
+        // taken as the first piece spawns)
+        let snapshot = self.snapshots.iter()
+            .rev()
+            .find(|snapshot| snapshot.game.stats().pieces < locked
+                || (snapshot.game.stats().pieces == locked
+                    && snapshot.game.phase() != Phase::Falling))
+            .unwrap_or(&self.snapshots[0]);
+
+        // Go back unless the piece is ahead, also when it's the
+        // current one but has been falling for a while
+        if self.piece() >= piece || snapshot.player.tick() > self.player.tick() {
+            self.game = snapshot.game.clone();
+            self.player = snapshot.player.clone();
+        }
+
+        while self.game.stats().pieces < locked
+            || self.game.phase() != Phase::Falling {
+            if !self.play_tick() {
+                break;
+            }
+        }
+
+        self.accumulator = 0.0;
+    }
+
+    /// Play the next tick, taking a snapshot when it's time to
+    ///
+    /// # Returns
+    /// False if the replay is over
+    fn play_tick(&mut self) -> bool {
+        if !self.player.play(&mut self.game) {
+            return false;
+        }
+
+        let tick = self.player.tick();
+        let last = self.snapshots.last().map_or(0, |s| s.player.tick());
+        if tick.is_multiple_of(SNAPSHOT_TICKS) && tick > last {
+            self.snapshots.push(Snapshot {
+                game: self.game.clone(),
+                player: self.player.clone(),
+            });
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReplayViewer;
+    use crate::game::{Game, TICK};
+    use crate::input::{Action, Input};
+    use crate::replay::{Player, Recorder, Replay};
+    use crate::ruleset::Ruleset;
+    use crate::shape::RotationSystem;
+
+    /// Record a game dropping a piece every second
+    fn replay() -> Replay {
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        game.restart_with_seed(42);
+
+        let mut recorder = Recorder::new(&game);
+        for tick in 0..3000 {
+            let mut input = Input::default();
+            if tick % 60 == 59 {
+                input.press(Action::HardDrop);
+            }
+            if game.is_over() {
+                break;
+            }
+            recorder.record(&input);
+            game.tick(&input);
+        }
+
+        recorder.replay().clone()
+    }
+
+    /// The playfield cells of the game
+    fn cells(game: &Game) -> Vec<usize> {
+        let pf = game.playfield();
+        (0..pf.n_rows())
+            .flat_map(|row| (0..pf.n_cols()).map(move |col| pf.get_cell(row, col)))
+            .collect()
+    }
+
+    #[test]
+    fn test_viewer_playback() {
+        let mut viewer = ReplayViewer::new(replay(), RotationSystem::SRS);
+
+        viewer.update(0.5);
+        assert_eq!(30, viewer.player().tick());
+
+        viewer.change_speed(10);
+        assert_eq!(8.0, viewer.speed());
+        viewer.update(TICK * 2.0);
+        assert_eq!(46, viewer.player().tick());
+
+        viewer.toggle_pause();
+        viewer.update(1.0);
+        assert_eq!(46, viewer.player().tick());
+        viewer.step();
+        assert_eq!(47, viewer.player().tick());
+        assert!(viewer.is_paused());
+    }
+
+    #[test]
+    fn test_viewer_seek() {
+        let replay = replay();
+        let mut viewer = ReplayViewer::new(replay.clone(), RotationSystem::SRS);
+
+        viewer.seek(10);
+        assert_eq!(10, viewer.piece());
+        let tick = viewer.player().tick();
+
+        // Seeking back restores a snapshot and plays on from there
+        viewer.seek(3);
+        assert_eq!(3, viewer.piece());
+        viewer.seek(10);
+        assert_eq!(tick, viewer.player().tick());
+
+        // Seeking the current piece while it falls goes back to its spawn
+        for _ in 0..20 {
+            viewer.step();
+        }
+        assert_eq!(10, viewer.piece());
+        viewer.seek(10);
+        assert_eq!(tick, viewer.player().tick());
+
+        // Seeking back to each piece from further on, also past the
+        // snapshots taken while the pieces fall
+        let mut spawns = ReplayViewer::new(replay.clone(), RotationSystem::SRS);
+        for piece in 1..=12 {
+            spawns.seek(piece);
+            viewer.seek(20);
+            viewer.seek(piece);
+            assert_eq!(spawns.player().tick(), viewer.player().tick());
+        }
+        viewer.seek(10);
+
+        // Same state as playing the replay from the start
+        let mut game = Game::new(RotationSystem::SRS, Ruleset::modern());
+        replay.start(&mut game, RotationSystem::SRS);
+        let mut player = Player::new(replay);
+        while player.tick() < tick {
+            player.play(&mut game);
+        }
+        assert_eq!(cells(&game), cells(viewer.game()));
+        assert_eq!(game.piece().cells(), viewer.game().piece().cells());
+
+        // Pieces that never spawned take it to the end
+        viewer.seek(1000);
+        assert!(viewer.player().is_over());
+    }
+}
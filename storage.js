@@ -0,0 +1,51 @@
+// localStorage access for the web build of Rusty Blocks (see src/storage.rs)
+// Must be loaded after the macroquad JS bundle and before load().
+
+miniquad_add_plugin({
+    name: "rusty_blocks_storage",
+    version: 1,
+    register_plugin: function (importObject) {
+        function key_string(ptr, len) {
+            return new TextDecoder().decode(new Uint8Array(wasm_memory.buffer, ptr, len));
+        }
+
+        function stored(ptr, len) {
+            try {
+                return window.localStorage.getItem(key_string(ptr, len));
+            } catch (e) {
+                return null;
+            }
+        }
+
+        // Length in bytes of the stored value, or -1 if there is none
+        importObject.env.storage_length = function (key, key_len) {
+            var value = stored(key, key_len);
+            return value === null ? -1 : new TextEncoder().encode(value).length;
+        };
+
+        importObject.env.storage_read = function (key, key_len, dest) {
+            var bytes = new TextEncoder().encode(stored(key, key_len) || "");
+            new Uint8Array(wasm_memory.buffer, dest, bytes.length).set(bytes);
+        };
+
+        importObject.env.storage_write = function (key, key_len, value, value_len) {
+            try {
+                var text = new TextDecoder().decode(
+                    new Uint8Array(wasm_memory.buffer, value, value_len));
+                window.localStorage.setItem(key_string(key, key_len), text);
+                return true;
+            } catch (e) {
+                return false;
+            }
+        };
+
+        importObject.env.storage_remove = function (key, key_len) {
+            try {
+                window.localStorage.removeItem(key_string(key, key_len));
+                return true;
+            } catch (e) {
+                return false;
+            }
+        };
+    }
+});